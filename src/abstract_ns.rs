@@ -0,0 +1,208 @@
+//! The `abstract_ns` module provides helpers for sockets in the Linux abstract namespace (the
+//! names that `ss` and this crate show with a leading `@`).
+//!
+//! Abstract sockets have no filesystem permissions: any process in the same network namespace can
+//! connect to one, and any process can bind a name that isn't already taken. Peer credentials are
+//! therefore the only access control available, so [`AbstractListener`] requires a
+//! [`PeerPolicy`] up front, and silently drops connections from peers that fail it. Creating a
+//! listener that accepts anyone requires passing [`PeerPolicy::AllowAnyone`] explicitly.
+//!
+//! The same applies in the other direction: since a malicious process may have bound the name
+//! first, [`connect_checked()`] verifies the server's credentials after connecting.
+//!
+//! Names are given as raw bytes, without the leading NUL byte.
+//!
+//! ```
+//! use unix_cred::abstract_ns::{self, AbstractListener, PeerPolicy};
+//!
+//! let name = format!("unix-cred-doc-{}", std::process::id());
+//!
+//! let listener = AbstractListener::bind(name.as_bytes(), PeerPolicy::SameUser).unwrap();
+//! let client = abstract_ns::connect_checked(name.as_bytes(), &PeerPolicy::SameUser).unwrap();
+//! let (server, (uid, _gid)) = listener.accept().unwrap();
+//! assert_eq!(uid, unsafe { libc::geteuid() });
+//! ```
+//!
+//! [`AbstractListener`]: ./struct.AbstractListener.html
+//! [`PeerPolicy`]: ../policy/enum.PeerPolicy.html
+//! [`PeerPolicy::AllowAnyone`]: ../policy/enum.PeerPolicy.html#variant.AllowAnyone
+//! [`connect_checked()`]: ./fn.connect_checked.html
+
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::prelude::*;
+
+use crate::addr::UnixSocketName;
+use crate::sock;
+
+pub use crate::policy::PeerPolicy;
+
+#[inline]
+fn name(name: &[u8]) -> UnixSocketName {
+    UnixSocketName::Abstract(name.to_vec())
+}
+
+/// Connect to the stream socket with the given abstract name, *without* checking the server's
+/// credentials.
+///
+/// Anyone can bind an abstract name, so consider using [`connect_checked()`] instead.
+///
+/// [`connect_checked()`]: ./fn.connect_checked.html
+pub fn connect(abstract_name: &[u8]) -> io::Result<UnixStream> {
+    sock::connect_new(libc::SOCK_STREAM, &name(abstract_name)).map(UnixStream::from)
+}
+
+/// Connect to the stream socket with the given abstract name, and check the server's credentials
+/// against the given policy.
+///
+/// If the server is not allowed by the policy, the connection is closed and this fails with
+/// `EACCES`.
+pub fn connect_checked(abstract_name: &[u8], policy: &PeerPolicy) -> io::Result<UnixStream> {
+    let sock = connect(abstract_name)?;
+    policy.check(&sock)?;
+    Ok(sock)
+}
+
+/// A stream socket listening on an abstract name, which only accepts connections from peers that
+/// are allowed by its [`PeerPolicy`](../policy/enum.PeerPolicy.html).
+#[derive(Debug)]
+pub struct AbstractListener {
+    listener: UnixListener,
+    policy: PeerPolicy,
+}
+
+impl AbstractListener {
+    /// Bind a new stream socket to the given abstract name, and start listening on it.
+    pub fn bind(abstract_name: &[u8], policy: PeerPolicy) -> io::Result<Self> {
+        let fd = sock::bind_new(libc::SOCK_STREAM, &name(abstract_name))?;
+
+        Ok(Self {
+            listener: UnixListener::from(fd),
+            policy,
+        })
+    }
+
+    /// Accept a new connection from a peer that is allowed by the policy, returning the connected
+    /// socket and the peer's effective UID and GID.
+    ///
+    /// Connections from peers that are not allowed (or whose credentials cannot be retrieved) are
+    /// closed immediately, and this keeps waiting for another connection.
+    pub fn accept(&self) -> io::Result<(UnixStream, (libc::uid_t, libc::gid_t))> {
+        loop {
+            let (sock, _) = self.listener.accept()?;
+
+            if let Ok(ids) = self.policy.check(&sock) {
+                return Ok((sock, ids));
+            }
+        }
+    }
+
+    /// Return an iterator over allowed incoming connections.
+    ///
+    /// The iterator never returns `None`.
+    #[inline]
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<UnixStream>> + '_ {
+        std::iter::repeat_with(move || self.accept().map(|(sock, _)| sock))
+    }
+
+    /// Get the policy that this listener checks peers against.
+    #[inline]
+    pub fn policy(&self) -> &PeerPolicy {
+        &self.policy
+    }
+
+    /// Get the abstract name that this listener is bound to (without the leading NUL byte).
+    pub fn abstract_name(&self) -> io::Result<Vec<u8>> {
+        match sock::local_addr(self.listener.as_fd())? {
+            Some(UnixSocketName::Abstract(name)) => Ok(name),
+            _ => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
+
+    /// Move the socket into or out of non-blocking mode.
+    ///
+    /// In non-blocking mode, [`accept()`](#method.accept) fails with `EWOULDBLOCK` once there are
+    /// no more pending connections (including after dropping rejected connections).
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+}
+
+impl AsRawFd for AbstractListener {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl AsFd for AbstractListener {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_name(suffix: &str) -> Vec<u8> {
+        format!("unix-cred-abstract-test-{}-{}", std::process::id(), suffix).into_bytes()
+    }
+
+    #[test]
+    fn test_listener() {
+        let name = test_name("listener");
+        let listener = AbstractListener::bind(&name, PeerPolicy::SameUser).unwrap();
+        assert_eq!(listener.abstract_name().unwrap(), name);
+
+        // The name is taken
+        assert_eq!(
+            AbstractListener::bind(&name, PeerPolicy::AllowAnyone)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EADDRINUSE)
+        );
+
+        let client = connect_checked(&name, &PeerPolicy::SameUser).unwrap();
+        let (server, ids) = listener.accept().unwrap();
+        assert_eq!(ids, crate::get_peer_ids(&client).unwrap());
+        assert_eq!(ids, crate::get_peer_ids(&server).unwrap());
+    }
+
+    #[test]
+    fn test_listener_rejects() {
+        let name = test_name("rejects");
+        let listener = AbstractListener::bind(&name, PeerPolicy::Uids(Vec::new())).unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        let client = connect(&name).unwrap();
+        assert_eq!(
+            listener.accept().unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // The rejected connection was closed
+        let mut buf = [0; 1];
+        assert_eq!(io::Read::read(&mut &client, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_connect_checked() {
+        let name = test_name("connect");
+        let _listener = AbstractListener::bind(&name, PeerPolicy::AllowAnyone).unwrap();
+
+        assert_eq!(
+            connect_checked(&name, &PeerPolicy::Uids(Vec::new()))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EACCES)
+        );
+
+        assert_eq!(
+            connect(&test_name("missing")).unwrap_err().raw_os_error(),
+            Some(libc::ECONNREFUSED)
+        );
+    }
+}
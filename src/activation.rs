@@ -0,0 +1,457 @@
+//! The `activation` module supports systemd-style socket activation, with credential checks on
+//! every accepted connection.
+//!
+//! With socket activation, the service manager creates the listening sockets and passes them to
+//! the service starting at file descriptor 3, describing them with the `LISTEN_PID`,
+//! `LISTEN_FDS`, and `LISTEN_FDNAMES` environment variables. [`listen_fds()`] parses these
+//! variables and takes ownership of the file descriptors, and methods like
+//! [`ListenFd::into_unix_listener()`] check that each one is the expected kind of socket.
+//!
+//! [`serve()`] then accepts connections, dropping any from peers that are not allowed by a
+//! [`PeerPolicy`].
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use unix_cred::policy::PeerPolicy;
+//! use unix_cred::activation;
+//!
+//! let fd = activation::listen_fds().unwrap().pop().expect("not socket-activated");
+//! let listener = fd.into_unix_listener().unwrap();
+//!
+//! let policy = PeerPolicy::Predicate(Box::new(|uid, _gid| uid < 1000));
+//! activation::serve(&listener, &policy, |mut sock, (uid, _gid)| {
+//!     let _ = writeln!(sock, "hello, {}", uid);
+//! })
+//! .unwrap();
+//! ```
+//!
+//! [`listen_fds()`]: ./fn.listen_fds.html
+//! [`ListenFd::into_unix_listener()`]: ./struct.ListenFd.html#method.into_unix_listener
+//! [`serve()`]: ./fn.serve.html
+//! [`PeerPolicy`]: ../policy/enum.PeerPolicy.html
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::os::unix::prelude::*;
+
+use crate::policy::PeerPolicy;
+use crate::seqpacket::UnixSeqpacketListener;
+
+/// The first file descriptor passed by the service manager.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// The name given to file descriptors that `LISTEN_FDNAMES` doesn't name.
+const UNKNOWN_NAME: &str = "unknown";
+
+/// Parse the socket activation environment variables, returning the names of the passed file
+/// descriptors (or an empty list if they were not passed to this process).
+fn parse_env(
+    listen_pid: Option<&OsStr>,
+    listen_fds: Option<&OsStr>,
+    listen_fdnames: Option<&OsStr>,
+    pid: libc::pid_t,
+) -> io::Result<Vec<String>> {
+    fn parse<T: std::str::FromStr>(s: &OsStr) -> io::Result<T> {
+        s.to_str()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+    }
+
+    let (listen_pid, listen_fds) = match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(listen_fds)) => (listen_pid, listen_fds),
+        _ => return Ok(Vec::new()),
+    };
+
+    // The variables were meant for another process (e.g. our parent)
+    if parse::<libc::pid_t>(listen_pid)? != pid {
+        return Ok(Vec::new());
+    }
+
+    let nfds = parse::<usize>(listen_fds)?;
+    if nfds > (RawFd::MAX - LISTEN_FDS_START) as usize {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    match listen_fdnames {
+        Some(names) => {
+            let names: Vec<String> = names
+                .to_str()
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?
+                .split(':')
+                .map(String::from)
+                .collect();
+
+            if names.len() != nfds {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            Ok(names)
+        }
+        None => Ok(vec![UNKNOWN_NAME.into(); nfds]),
+    }
+}
+
+/// A file descriptor passed by the service manager.
+#[derive(Debug)]
+pub struct ListenFd {
+    fd: OwnedFd,
+    name: String,
+}
+
+impl ListenFd {
+    /// Get the name of this file descriptor, as given in `LISTEN_FDNAMES` (or `"unknown"` if it
+    /// was not named).
+    ///
+    /// With systemd, this is the `FileDescriptorName=` of the socket unit (which defaults to the
+    /// unit's name).
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn getsockopt_int(&self, optname: libc::c_int) -> io::Result<libc::c_int> {
+        let mut val = 0;
+        unsafe {
+            crate::util::getsockopt_raw(
+                self.fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                optname,
+                std::slice::from_mut(&mut val),
+            )?;
+        }
+        Ok(val)
+    }
+
+    /// Check that this file descriptor is an `AF_UNIX` socket of the given type (e.g.
+    /// `libc::SOCK_STREAM`), and that it is (or is not) listening for connections.
+    ///
+    /// This fails with `ENOTSOCK` if it is not a socket, `EAFNOSUPPORT` if it is not an `AF_UNIX`
+    /// socket, `EPROTOTYPE` if it is the wrong type of socket, and `EINVAL` if it is (or is not)
+    /// listening when it shouldn't be.
+    pub fn check_unix(&self, ty: libc::c_int, listening: bool) -> io::Result<()> {
+        let domain = self.getsockopt_int(libc::SO_DOMAIN)?;
+        if domain != libc::AF_UNIX {
+            return Err(io::Error::from_raw_os_error(libc::EAFNOSUPPORT));
+        }
+
+        if self.getsockopt_int(libc::SO_TYPE)? != ty {
+            return Err(io::Error::from_raw_os_error(libc::EPROTOTYPE));
+        }
+
+        if (self.getsockopt_int(libc::SO_ACCEPTCONN)? != 0) != listening {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        Ok(())
+    }
+
+    /// Convert this file descriptor into a `UnixListener`, after checking that it is a listening
+    /// `SOCK_STREAM` socket.
+    pub fn into_unix_listener(self) -> io::Result<UnixListener> {
+        self.check_unix(libc::SOCK_STREAM, true)?;
+        Ok(UnixListener::from(self.fd))
+    }
+
+    /// Convert this file descriptor into a `UnixSeqpacketListener`, after checking that it is a
+    /// listening `SOCK_SEQPACKET` socket.
+    pub fn into_seqpacket_listener(self) -> io::Result<UnixSeqpacketListener> {
+        self.check_unix(libc::SOCK_SEQPACKET, true)?;
+        Ok(UnixSeqpacketListener::from(self.fd))
+    }
+
+    /// Convert this file descriptor into a `UnixDatagram`, after checking that it is a
+    /// `SOCK_DGRAM` socket.
+    pub fn into_unix_datagram(self) -> io::Result<UnixDatagram> {
+        self.check_unix(libc::SOCK_DGRAM, false)?;
+        Ok(UnixDatagram::from(self.fd))
+    }
+}
+
+impl AsRawFd for ListenFd {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for ListenFd {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl From<ListenFd> for OwnedFd {
+    #[inline]
+    fn from(fd: ListenFd) -> OwnedFd {
+        fd.fd
+    }
+}
+
+/// Take ownership of the file descriptors passed by the service manager.
+///
+/// This returns an empty list if the process was not socket-activated (or if the environment
+/// variables were meant for another process). It fails with `EINVAL` if the environment variables
+/// are malformed, or with `EBADF` if one of the file descriptors is not open.
+///
+/// To make sure that the file descriptors are only owned once, this removes the `LISTEN_PID`,
+/// `LISTEN_FDS`, and `LISTEN_FDNAMES` environment variables (so it should be called early, before
+/// any other threads are started). It also sets the close-on-exec flag on the file descriptors.
+pub fn listen_fds() -> io::Result<Vec<ListenFd>> {
+    let vars = ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+    let [listen_pid, listen_fds, listen_fdnames] = vars.map(std::env::var_os);
+
+    for var in vars.iter() {
+        std::env::remove_var(var);
+    }
+
+    let names = parse_env(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        listen_fdnames.as_deref(),
+        unsafe { libc::getpid() },
+    )?;
+
+    // Check them all before taking ownership of any
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + names.len() as RawFd {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(names
+        .into_iter()
+        .zip(LISTEN_FDS_START..)
+        .map(|(name, fd)| ListenFd {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            name,
+        })
+        .collect())
+}
+
+/// Accept a connection from a peer that is allowed by the given policy, returning the connected
+/// socket and the peer's effective UID and GID.
+///
+/// Connections from peers that are not allowed (or whose credentials cannot be retrieved) are
+/// closed immediately, and this keeps waiting for another connection.
+pub fn accept_checked(
+    listener: &UnixListener,
+    policy: &PeerPolicy,
+) -> io::Result<(UnixStream, (libc::uid_t, libc::gid_t))> {
+    loop {
+        let (sock, _) = listener.accept()?;
+
+        if let Ok(ids) = policy.check(&sock) {
+            return Ok((sock, ids));
+        }
+    }
+}
+
+/// Accept connections forever, calling `handler` with each connection from a peer that is allowed
+/// by the given policy (along with the peer's effective UID and GID).
+///
+/// Transient errors from `accept()` (like `ECONNABORTED`) are ignored; other errors are returned.
+pub fn serve<F>(listener: &UnixListener, policy: &PeerPolicy, mut handler: F) -> io::Result<()>
+where
+    F: FnMut(UnixStream, (libc::uid_t, libc::gid_t)),
+{
+    loop {
+        match accept_checked(listener, policy) {
+            Ok((sock, ids)) => handler(sock, ids),
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::EINTR | libc::ECONNABORTED | libc::EPROTO)
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::process::Command;
+
+    const CHILD_ENV: &str = "UNIX_CRED_TEST_ACTIVATION_CHILD";
+
+    #[test]
+    fn test_parse_env() {
+        fn parse(
+            pid: Option<&str>,
+            fds: Option<&str>,
+            names: Option<&str>,
+        ) -> io::Result<Vec<String>> {
+            parse_env(
+                pid.map(OsStr::new),
+                fds.map(OsStr::new),
+                names.map(OsStr::new),
+                100,
+            )
+        }
+
+        assert_eq!(parse(None, None, None).unwrap(), Vec::<String>::new());
+        assert_eq!(parse(None, Some("1"), None).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            parse(Some("101"), Some("1"), None).unwrap(),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(
+            parse(Some("100"), Some("2"), None).unwrap(),
+            ["unknown", "unknown"]
+        );
+        assert_eq!(
+            parse(Some("100"), Some("2"), Some("a:b")).unwrap(),
+            ["a", "b"]
+        );
+        assert_eq!(
+            parse(Some("100"), Some("0"), None).unwrap(),
+            Vec::<String>::new()
+        );
+
+        for (pid, fds, names) in [
+            ("abc", "1", None),
+            ("100", "-1", None),
+            ("100", "x", None),
+            ("100", "2", Some("a")),
+            ("100", "1", Some("a:b")),
+        ] {
+            assert_eq!(
+                parse(Some(pid), Some(fds), names)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_unix() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let fd = |fd: OwnedFd| ListenFd {
+            fd,
+            name: UNKNOWN_NAME.into(),
+        };
+
+        let listener = UnixListener::bind(dir.path().join("sock")).unwrap();
+        fd(listener.into()).into_unix_listener().unwrap();
+
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert_eq!(
+            fd(a.into())
+                .into_unix_listener()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        let sock = UnixDatagram::unbound().unwrap();
+        assert_eq!(
+            fd(sock.try_clone().unwrap().into())
+                .into_seqpacket_listener()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EPROTOTYPE)
+        );
+        fd(sock.into()).into_unix_datagram().unwrap();
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert_eq!(
+            fd(tcp.into())
+                .into_unix_listener()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EAFNOSUPPORT)
+        );
+
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert_eq!(
+            fd(file.into())
+                .into_unix_listener()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOTSOCK)
+        );
+    }
+
+    /// The half of `test_activation()` that runs in the socket-activated child process.
+    #[test]
+    fn test_activation_child() {
+        if std::env::var_os(CHILD_ENV).is_none() {
+            return;
+        }
+
+        let mut fds = listen_fds().unwrap();
+        assert_eq!(fds.len(), 1);
+        assert_eq!(fds[0].name(), "test");
+        assert!(std::env::var_os("LISTEN_FDS").is_none());
+
+        let listener = fds.pop().unwrap().into_unix_listener().unwrap();
+
+        // The first connection is rejected; the second one is accepted
+        let rejected = std::sync::atomic::AtomicBool::new(false);
+        let policy = PeerPolicy::Predicate(Box::new(move |_, _| {
+            rejected.swap(true, std::sync::atomic::Ordering::SeqCst)
+        }));
+
+        let (mut sock, (uid, gid)) = accept_checked(&listener, &policy).unwrap();
+        writeln!(sock, "{} {}", uid, gid).unwrap();
+    }
+
+    #[test]
+    fn test_activation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let listener_fd = listener.as_raw_fd();
+
+        let mut cmd = Command::new("/bin/sh");
+        // Use the shell's PID, since exec() won't change it
+        cmd.args([
+            "-c",
+            "LISTEN_PID=$$ exec \"$0\" \"$@\"",
+            std::env::current_exe().unwrap().to_str().unwrap(),
+            "--exact",
+            "activation::tests::test_activation_child",
+            "--nocapture",
+        ])
+        .env(CHILD_ENV, "1")
+        .env("LISTEN_FDS", "1")
+        .env("LISTEN_FDNAMES", "test");
+
+        unsafe {
+            use std::os::unix::process::CommandExt;
+
+            cmd.pre_exec(move || {
+                if listener_fd == LISTEN_FDS_START {
+                    if libc::fcntl(listener_fd, libc::F_SETFD, 0) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                } else if libc::dup2(listener_fd, LISTEN_FDS_START) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let mut child = cmd.spawn().unwrap();
+        drop(listener);
+
+        let mut rejected = UnixStream::connect(&path).unwrap();
+        let mut accepted = UnixStream::connect(&path).unwrap();
+
+        let mut buf = String::new();
+        rejected.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "");
+        accepted.read_to_string(&mut buf).unwrap();
+        let (uid, gid) = crate::get_peer_ids(&accepted).unwrap();
+        assert_eq!(buf, format!("{} {}\n", uid, gid));
+
+        assert!(child.wait().unwrap().success());
+    }
+}
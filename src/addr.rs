@@ -0,0 +1,164 @@
+//! The `addr` module provides [`UnixSocketName`], which represents the address of a Unix socket
+//! (either a path or an abstract name).
+//!
+//! Unlike the standard library's `SocketAddr`, it can be used with the socket types that the
+//! standard library doesn't support (see `seqpacket`), and it is also what `diag` reports as the
+//! address of each socket.
+//!
+//! [`UnixSocketName`]: ./enum.UnixSocketName.html
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+
+/// The offset of `sun_path` in `struct sockaddr_un`.
+const SUN_PATH_OFFSET: usize = std::mem::size_of::<libc::sa_family_t>();
+
+/// The address that a Unix socket is bound to.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum UnixSocketName {
+    /// A path in the filesystem.
+    Path(PathBuf),
+    /// A name in the abstract namespace (not including the leading NUL byte).
+    Abstract(Vec<u8>),
+}
+
+impl UnixSocketName {
+    pub(crate) fn from_raw(name: &[u8]) -> Self {
+        match name.split_first() {
+            Some((0, name)) => Self::Abstract(name.to_vec()),
+            _ => {
+                let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                Self::Path(PathBuf::from(OsStr::from_bytes(&name[..len])))
+            }
+        }
+    }
+
+    /// Convert an address returned by the kernel (e.g. by `recvmsg()`) to a name, or `None` if
+    /// the address is unnamed.
+    pub(crate) fn from_sockaddr(addr: &libc::sockaddr_un, len: libc::socklen_t) -> Option<Self> {
+        let len = (len as usize)
+            .checked_sub(SUN_PATH_OFFSET)?
+            .min(addr.sun_path.len());
+        if len == 0 {
+            return None;
+        }
+
+        let name: Vec<u8> = addr.sun_path[..len].iter().map(|&c| c as u8).collect();
+        Some(Self::from_raw(&name))
+    }
+
+    /// Convert this name to an address that can be passed to `bind()`, `connect()`, etc.
+    ///
+    /// This fails with `EINVAL` if the name is too long, or if a path contains a NUL byte.
+    pub(crate) fn to_sockaddr(&self) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+        let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+        // Paths need room for a trailing NUL; abstract names start with a NUL
+        let (raw, len) = match self {
+            Self::Path(path) => {
+                let path = path.as_os_str().as_bytes();
+                if path.contains(&0) {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+                (path, path.len() + 1)
+            }
+            Self::Abstract(name) => (name.as_slice(), name.len() + 1),
+        };
+
+        if len > addr.sun_path.len() {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        let start = if matches!(self, Self::Abstract(_)) {
+            1
+        } else {
+            0
+        };
+        for (dst, &src) in addr.sun_path[start..].iter_mut().zip(raw.iter()) {
+            *dst = src as libc::c_char;
+        }
+
+        Ok((addr, (SUN_PATH_OFFSET + len) as libc::socklen_t))
+    }
+}
+
+/// Names are formatted like `ss` does: paths are shown as-is, and abstract names are prefixed with
+/// `@` (with any embedded NUL bytes also shown as `@`).
+impl fmt::Display for UnixSocketName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Abstract(name) => {
+                let name: Vec<u8> = name
+                    .iter()
+                    .map(|&c| if c == 0 { b'@' } else { c })
+                    .collect();
+                write!(f, "@{}", String::from_utf8_lossy(&name))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_name() {
+        assert_eq!(
+            UnixSocketName::from_raw(b"/run/foo.sock\0"),
+            UnixSocketName::Path("/run/foo.sock".into())
+        );
+        assert_eq!(
+            UnixSocketName::from_raw(b"/run/foo.sock"),
+            UnixSocketName::Path("/run/foo.sock".into())
+        );
+        assert_eq!(
+            UnixSocketName::from_raw(b"\0foo\0bar"),
+            UnixSocketName::Abstract(b"foo\0bar".to_vec())
+        );
+
+        assert_eq!(
+            UnixSocketName::Path("/run/foo.sock".into()).to_string(),
+            "/run/foo.sock"
+        );
+        assert_eq!(
+            UnixSocketName::Abstract(b"foo\0bar".to_vec()).to_string(),
+            "@foo@bar"
+        );
+        assert_eq!(UnixSocketName::Abstract(Vec::new()).to_string(), "@");
+    }
+
+    #[test]
+    fn test_socket_name_sockaddr() {
+        for name in [
+            UnixSocketName::Path("/run/foo.sock".into()),
+            UnixSocketName::Abstract(b"foo\0bar".to_vec()),
+            UnixSocketName::Abstract(Vec::new()),
+        ] {
+            let (addr, len) = name.to_sockaddr().unwrap();
+            assert_eq!(UnixSocketName::from_sockaddr(&addr, len), Some(name));
+        }
+
+        let (addr, _) = UnixSocketName::Abstract(Vec::new()).to_sockaddr().unwrap();
+        assert_eq!(
+            UnixSocketName::from_sockaddr(&addr, SUN_PATH_OFFSET as libc::socklen_t),
+            None
+        );
+
+        for name in [
+            UnixSocketName::Path("/run/foo\0.sock".into()),
+            UnixSocketName::Path(PathBuf::from("a".repeat(108))),
+            UnixSocketName::Abstract(vec![b'a'; 108]),
+        ] {
+            assert_eq!(
+                name.to_sockaddr().unwrap_err().raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! The `ancestry` module provides functions to walk the parent chain of a socket's peer.
+//!
+//! This can be used to implement policies like "only accept connections from processes that I
+//! spawned" or "only accept connections from processes started by `/usr/bin/foo`".
+//!
+//! The parent chain is read from the `ppid` fields in `/proc/<pid>/stat`. To guard against PID
+//! reuse, processes are identified by their PID *and* their start time (see [`ProcessId`]), and
+//! the walk stops if a parent appears to have started after its child (which means that the
+//! original parent has died and its PID has been reused).
+//!
+//! The starting PID is retrieved as in [`get_peer_pid_ids()`], so it is subject to the same PID
+//! reuse caveats.
+//!
+//! [`ProcessId`]: ./struct.ProcessId.html
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use crate::procfs;
+
+/// Identifies a specific process by its PID and start time.
+///
+/// Since the start time of a process never changes, and two processes with the same PID cannot
+/// have the same start time, this can be used to detect PID reuse.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ProcessId {
+    pid: libc::pid_t,
+    starttime: u64,
+}
+
+impl ProcessId {
+    /// Look up the start time of the process with the given PID.
+    #[inline]
+    pub fn from_pid(pid: libc::pid_t) -> io::Result<Self> {
+        let stat = procfs::read_stat(pid)?;
+
+        Ok(Self {
+            pid,
+            starttime: stat.starttime,
+        })
+    }
+
+    /// Get the `ProcessId` of the current process.
+    #[inline]
+    pub fn current() -> io::Result<Self> {
+        Self::from_pid(unsafe { libc::getpid() })
+    }
+
+    /// Get the PID of this process.
+    #[inline]
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Get the start time of this process, in clock ticks after system boot.
+    #[inline]
+    pub fn starttime(&self) -> u64 {
+        self.starttime
+    }
+
+    /// Check whether this process is still running (i.e. its PID has not been reused by another
+    /// process).
+    pub fn is_alive(&self) -> io::Result<bool> {
+        match procfs::read_stat(self.pid) {
+            Ok(stat) => Ok(stat.starttime == self.starttime),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Get the ancestors of the process with the given PID.
+///
+/// The returned list starts with the process's parent and ends with the top of the process tree
+/// (usually `init`). It does not include the process itself.
+///
+/// If a parent is found to have started after its child, the walk stops there, since that means
+/// the original parent has died and its PID has been reused. The list may also be cut short if a
+/// parent exits while the walk is in progress.
+pub fn get_ancestors(pid: libc::pid_t) -> io::Result<Vec<ProcessId>> {
+    let mut stat = procfs::read_stat(pid)?;
+    let mut ancestors = Vec::new();
+
+    while stat.ppid > 0 {
+        let parent = match procfs::read_stat(stat.ppid) {
+            Ok(parent) => parent,
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => break,
+            Err(e) => return Err(e),
+        };
+
+        if parent.starttime > stat.starttime {
+            break;
+        }
+
+        ancestors.push(ProcessId {
+            pid: stat.ppid,
+            starttime: parent.starttime,
+        });
+
+        // Guard against cycles, which should never happen
+        if ancestors[..ancestors.len() - 1].contains(ancestors.last().unwrap()) {
+            return Err(io::Error::from_raw_os_error(libc::ELOOP));
+        }
+
+        stat = parent;
+    }
+
+    Ok(ancestors)
+}
+
+/// Check whether the process with the given PID is a descendant of `ancestor`.
+///
+/// A process is not considered to be a descendant of itself.
+pub fn is_descendant_of(pid: libc::pid_t, ancestor: &ProcessId) -> io::Result<bool> {
+    Ok(get_ancestors(pid)?.contains(ancestor))
+}
+
+/// Check whether the process with the given PID has an ancestor that is running the executable at
+/// the given path.
+///
+/// `exe` is compared with the target of each ancestor's `/proc/<pid>/exe` link, so it should be
+/// an absolute, canonical path. Ancestors whose executable cannot be determined (for example,
+/// because they belong to another user) are treated as not matching.
+pub fn has_ancestor_exe<P: AsRef<Path>>(pid: libc::pid_t, exe: P) -> io::Result<bool> {
+    let exe = exe.as_ref();
+
+    for ancestor in get_ancestors(pid)? {
+        if procfs::read_exe(ancestor.pid)?.as_deref() == Some(exe) && ancestor.is_alive()? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Get the ancestors of the given socket's peer.
+///
+/// See [`get_ancestors()`] for more details.
+///
+/// [`get_ancestors()`]: ./fn.get_ancestors.html
+#[inline]
+pub fn get_peer_ancestors(sock: &UnixStream) -> io::Result<Vec<ProcessId>> {
+    let cred = unsafe { crate::ucred::get_ucred_raw(sock.as_raw_fd()) }?;
+    get_ancestors(cred.pid)
+}
+
+/// Check whether the given socket's peer is a descendant of `ancestor`.
+///
+/// For example, to check that the peer was spawned (directly or indirectly) by the current
+/// process:
+///
+/// ```
+/// # use std::os::unix::net::UnixStream;
+/// # use unix_cred::ancestry::{ProcessId, peer_is_descendant_of};
+/// # let (sock, _peer) = UnixStream::pair().unwrap();
+/// let me = ProcessId::current().unwrap();
+/// if !peer_is_descendant_of(&sock, &me).unwrap() {
+///     // Reject the connection
+/// }
+/// ```
+#[inline]
+pub fn peer_is_descendant_of(sock: &UnixStream, ancestor: &ProcessId) -> io::Result<bool> {
+    let cred = unsafe { crate::ucred::get_ucred_raw(sock.as_raw_fd()) }?;
+    is_descendant_of(cred.pid, ancestor)
+}
+
+/// Check whether the given socket's peer has an ancestor that is running the executable at the
+/// given path.
+///
+/// See [`has_ancestor_exe()`] for more details.
+///
+/// [`has_ancestor_exe()`]: ./fn.has_ancestor_exe.html
+#[inline]
+pub fn peer_has_ancestor_exe<P: AsRef<Path>>(sock: &UnixStream, exe: P) -> io::Result<bool> {
+    let cred = unsafe { crate::ucred::get_ucred_raw(sock.as_raw_fd()) }?;
+    has_ancestor_exe(cred.pid, exe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_process_id() {
+        let me = ProcessId::current().unwrap();
+        assert_eq!(me.pid(), unsafe { libc::getpid() });
+        assert_eq!(me, ProcessId::from_pid(me.pid()).unwrap());
+        assert!(me.is_alive().unwrap());
+
+        let fake = ProcessId {
+            starttime: me.starttime + 1,
+            ..me
+        };
+        assert!(!fake.is_alive().unwrap());
+
+        assert_eq!(
+            ProcessId::from_pid(-1).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    #[test]
+    fn test_get_peer_ancestors() {
+        let (a, _b) = UnixStream::pair().unwrap();
+
+        let ancestors = get_peer_ancestors(&a).unwrap();
+        assert_eq!(
+            ancestors[0],
+            ProcessId::from_pid(unsafe { libc::getppid() }).unwrap()
+        );
+        assert_eq!(ancestors, get_ancestors(unsafe { libc::getpid() }).unwrap());
+
+        for pair in ancestors.windows(2) {
+            assert!(pair[0].starttime() >= pair[1].starttime());
+        }
+
+        let me = ProcessId::current().unwrap();
+        assert!(!peer_is_descendant_of(&a, &me).unwrap());
+        assert!(peer_is_descendant_of(&a, &ancestors[0]).unwrap());
+        assert!(!peer_has_ancestor_exe(&a, std::env::current_exe().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_child_ancestry() {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        let me = ProcessId::current().unwrap();
+        assert_eq!(get_ancestors(pid).unwrap()[0], me);
+        assert!(is_descendant_of(pid, &me).unwrap());
+        assert!(has_ancestor_exe(pid, std::env::current_exe().unwrap()).unwrap());
+        assert!(!has_ancestor_exe(pid, "/nonexistent").unwrap());
+
+        // A process with the same PID but a different start time is not our ancestor
+        let fake = ProcessId {
+            starttime: me.starttime() + 1,
+            ..me
+        };
+        assert!(!is_descendant_of(pid, &fake).unwrap());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! The `apparmor` module provides a parser for AppArmor confinement labels.
+//!
+//! AppArmor labels consist of one or more profile names (multiple profiles are "stacked" with
+//! `//&`), each optionally prefixed with a `:namespace:`, followed by the confinement mode in
+//! parentheses. For example:
+//!
+//! - `unconfined`
+//! - `/usr/sbin/cupsd (enforce)`
+//! - `docker-default//&:lxd-c1:unconfined (complain)`
+//!
+//! [`get_peer_apparmor_label()`] retrieves the label of a socket's peer and parses it. The output
+//! of [`lsm::get_peersec()`] can also be parsed with `str::parse()` on systems where AppArmor is
+//! the LSM providing `SO_PEERSEC`.
+//!
+//! ```
+//! use unix_cred::apparmor::{AppArmorLabel, AppArmorMode};
+//!
+//! let label: AppArmorLabel = "/usr/bin/foo (enforce)".parse().unwrap();
+//! assert!(label.has_profile("/usr/bin/foo"));
+//! assert_eq!(label.mode, AppArmorMode::Enforce);
+//! ```
+//!
+//! [`get_peer_apparmor_label()`]: ./fn.get_peer_apparmor_label.html
+//! [`lsm::get_peersec()`]: ../lsm/fn.get_peersec.html
+
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+use std::str::FromStr;
+
+#[inline]
+fn einval() -> io::Error {
+    io::Error::from_raw_os_error(libc::EINVAL)
+}
+
+/// The name of the special profile used for processes that are not confined.
+const UNCONFINED: &str = "unconfined";
+
+/// Represents the confinement mode of an AppArmor label.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum AppArmorMode {
+    /// The policy is enforced, and violations are logged.
+    Enforce,
+    /// The policy is not enforced, but violations are logged.
+    Complain,
+    /// The policy is enforced, and violations cause the process to be killed.
+    Kill,
+    /// The policy is not enforced by the kernel; violations are reported to a userspace daemon,
+    /// which may mediate them.
+    User,
+    /// The policy is enforced, but a userspace agent may be prompted to allow operations that
+    /// it denies.
+    Prompt,
+    /// The process is unconfined (either because it has no profile, or because its profile is in
+    /// unconfined mode).
+    Unconfined,
+    /// The label contains stacked profiles that are in different modes.
+    Mixed,
+}
+
+impl AppArmorMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Enforce => "enforce",
+            Self::Complain => "complain",
+            Self::Kill => "kill",
+            Self::User => "user",
+            Self::Prompt => "prompt",
+            Self::Unconfined => "unconfined",
+            Self::Mixed => "mixed",
+        }
+    }
+}
+
+impl FromStr for AppArmorMode {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s {
+            "enforce" => Ok(Self::Enforce),
+            "complain" => Ok(Self::Complain),
+            "kill" => Ok(Self::Kill),
+            "user" => Ok(Self::User),
+            "prompt" => Ok(Self::Prompt),
+            "unconfined" => Ok(Self::Unconfined),
+            "mixed" => Ok(Self::Mixed),
+            _ => Err(einval()),
+        }
+    }
+}
+
+impl fmt::Display for AppArmorMode {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Represents a single AppArmor profile in a label.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct AppArmorProfile {
+    /// The namespace that the profile is in, or `None` if it is in the current namespace.
+    pub namespace: Option<String>,
+    /// The name of the profile (which may include `//` to separate child profiles or hats).
+    pub name: String,
+}
+
+impl FromStr for AppArmorProfile {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (namespace, name) = match s.strip_prefix(':') {
+            Some(rest) => {
+                let i = rest.find(':').ok_or_else(einval)?;
+                let name = &rest[i + 1..];
+                // The kernel may separate the namespace from the profile name with "://"
+                let name = name.strip_prefix("//").unwrap_or(name);
+                (Some(&rest[..i]), name)
+            }
+            None => (None, s),
+        };
+
+        if name.is_empty() || namespace == Some("") {
+            return Err(einval());
+        }
+
+        Ok(Self {
+            namespace: namespace.map(String::from),
+            name: name.into(),
+        })
+    }
+}
+
+impl fmt::Display for AppArmorProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ns) = self.namespace.as_ref() {
+            write!(f, ":{}:", ns)?;
+        }
+
+        f.write_str(&self.name)
+    }
+}
+
+/// Represents a parsed AppArmor confinement label.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct AppArmorLabel {
+    /// The profiles in the label. If profiles are stacked, there will be more than one.
+    pub profiles: Vec<AppArmorProfile>,
+    /// The confinement mode.
+    pub mode: AppArmorMode,
+}
+
+impl AppArmorLabel {
+    /// Check whether the label is confined (i.e. its mode is not `Unconfined`).
+    #[inline]
+    pub fn is_confined(&self) -> bool {
+        self.mode != AppArmorMode::Unconfined
+    }
+
+    /// Check whether the label contains a profile with the given name in the current namespace.
+    #[inline]
+    pub fn has_profile(&self, name: &str) -> bool {
+        self.profiles
+            .iter()
+            .any(|profile| profile.namespace.is_none() && profile.name == name)
+    }
+}
+
+impl FromStr for AppArmorLabel {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let s = s.trim_end_matches(['\n', '\0']);
+
+        // The plain "unconfined" label has no mode
+        if s == UNCONFINED {
+            return Ok(Self {
+                profiles: vec![AppArmorProfile {
+                    namespace: None,
+                    name: UNCONFINED.into(),
+                }],
+                mode: AppArmorMode::Unconfined,
+            });
+        }
+
+        let (profiles, mode) = match s
+            .strip_suffix(')')
+            .and_then(|s| s.rfind(" (").map(|i| (&s[..i], &s[i + 2..])))
+        {
+            Some((profiles, mode)) => (profiles, Some(mode.parse()?)),
+            None => (s, None),
+        };
+
+        let profiles = profiles
+            .split("//&")
+            .map(str::parse)
+            .collect::<io::Result<Vec<AppArmorProfile>>>()?;
+
+        let mode = match mode {
+            Some(mode) => mode,
+            // Namespaced (or stacked) "unconfined" profiles may also appear without a mode
+            None if profiles.iter().all(|profile| profile.name == UNCONFINED) => {
+                AppArmorMode::Unconfined
+            }
+            None => return Err(einval()),
+        };
+
+        Ok(Self { profiles, mode })
+    }
+}
+
+impl fmt::Display for AppArmorLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, profile) in self.profiles.iter().enumerate() {
+            if i > 0 {
+                f.write_str("//&")?;
+            }
+            write!(f, "{}", profile)?;
+        }
+
+        if self.mode != AppArmorMode::Unconfined
+            || !self
+                .profiles
+                .iter()
+                .all(|profile| profile.name == UNCONFINED)
+        {
+            write!(f, " ({})", self.mode)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the AppArmor label of the process with the given PID.
+///
+/// This reads `/proc/<pid>/attr/apparmor/current` (or `/proc/<pid>/attr/current` on older kernels;
+/// see [`lsm::get_lsm_labels()`]). If AppArmor is not active, this fails with `ENOPROTOOPT`.
+///
+/// [`lsm::get_lsm_labels()`]: ../lsm/fn.get_lsm_labels.html
+pub fn get_apparmor_label(pid: libc::pid_t) -> io::Result<AppArmorLabel> {
+    match crate::lsm::get_lsm_labels(pid)?.remove("apparmor") {
+        Some(label) => label.parse(),
+        None => Err(io::Error::from_raw_os_error(libc::ENOPROTOOPT)),
+    }
+}
+
+/// Get the AppArmor label of the given socket's peer.
+///
+/// See [`get_apparmor_label()`] for more details. Since this looks up the peer by PID, it is
+/// subject to the same PID reuse caveats as [`get_peer_pid_ids()`].
+///
+/// [`get_apparmor_label()`]: ./fn.get_apparmor_label.html
+/// [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+#[inline]
+pub fn get_peer_apparmor_label(sock: &UnixStream) -> io::Result<AppArmorLabel> {
+    let cred = unsafe { crate::ucred::get_ucred_raw(sock.as_raw_fd()) }?;
+    get_apparmor_label(cred.pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(namespace: Option<&str>, name: &str) -> AppArmorProfile {
+        AppArmorProfile {
+            namespace: namespace.map(String::from),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse_label() {
+        for (s, profiles, mode, display) in [
+            (
+                "unconfined",
+                vec![profile(None, "unconfined")],
+                AppArmorMode::Unconfined,
+                "unconfined",
+            ),
+            (
+                "unconfined\n",
+                vec![profile(None, "unconfined")],
+                AppArmorMode::Unconfined,
+                "unconfined",
+            ),
+            (
+                "/usr/sbin/cupsd (enforce)",
+                vec![profile(None, "/usr/sbin/cupsd")],
+                AppArmorMode::Enforce,
+                "/usr/sbin/cupsd (enforce)",
+            ),
+            (
+                "/usr/sbin/cupsd//third_party (complain)\n",
+                vec![profile(None, "/usr/sbin/cupsd//third_party")],
+                AppArmorMode::Complain,
+                "/usr/sbin/cupsd//third_party (complain)",
+            ),
+            (
+                "my profile (kill)",
+                vec![profile(None, "my profile")],
+                AppArmorMode::Kill,
+                "my profile (kill)",
+            ),
+            (
+                "snap.foo.bar (user)",
+                vec![profile(None, "snap.foo.bar")],
+                AppArmorMode::User,
+                "snap.foo.bar (user)",
+            ),
+            (
+                "/usr/bin/foo (prompt)\n",
+                vec![profile(None, "/usr/bin/foo")],
+                AppArmorMode::Prompt,
+                "/usr/bin/foo (prompt)",
+            ),
+            (
+                "firefox (unconfined)",
+                vec![profile(None, "firefox")],
+                AppArmorMode::Unconfined,
+                "firefox (unconfined)",
+            ),
+            (
+                "docker-default//&:lxd-c1:unconfined (mixed)",
+                vec![
+                    profile(None, "docker-default"),
+                    profile(Some("lxd-c1"), "unconfined"),
+                ],
+                AppArmorMode::Mixed,
+                "docker-default//&:lxd-c1:unconfined (mixed)",
+            ),
+            (
+                "a//&b (enforce)",
+                vec![profile(None, "a"), profile(None, "b")],
+                AppArmorMode::Enforce,
+                "a//&b (enforce)",
+            ),
+            (
+                ":ns1://foo (enforce)",
+                vec![profile(Some("ns1"), "foo")],
+                AppArmorMode::Enforce,
+                ":ns1:foo (enforce)",
+            ),
+            (
+                ":ns1:unconfined",
+                vec![profile(Some("ns1"), "unconfined")],
+                AppArmorMode::Unconfined,
+                ":ns1:unconfined",
+            ),
+        ]
+        .iter()
+        {
+            let label: AppArmorLabel = s.parse().unwrap();
+            assert_eq!(&label.profiles, profiles, "{:?}", s);
+            assert_eq!(label.mode, *mode, "{:?}", s);
+            assert_eq!(label.to_string(), *display);
+            assert_eq!(display.parse::<AppArmorLabel>().unwrap(), label);
+        }
+
+        for s in [
+            "",
+            "foo",
+            "foo (bogus)",
+            "foo//& (enforce)",
+            "(enforce)",
+            " (enforce)",
+            "::foo (enforce)",
+            ":ns (enforce)",
+            ":ns: (enforce)",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                s.parse::<AppArmorLabel>().unwrap_err().raw_os_error(),
+                Some(libc::EINVAL),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_label_methods() {
+        let label: AppArmorLabel = "unconfined".parse().unwrap();
+        assert!(!label.is_confined());
+        assert!(label.has_profile("unconfined"));
+
+        let label: AppArmorLabel = "a//&:ns:b (enforce)".parse().unwrap();
+        assert!(label.is_confined());
+        assert!(label.has_profile("a"));
+        assert!(!label.has_profile("b"));
+        assert!(!label.has_profile("c"));
+    }
+
+    #[test]
+    fn test_get_peer_apparmor_label() {
+        let (a, _b) = UnixStream::pair().unwrap();
+
+        match crate::lsm::get_peer_lsm_labels(&a).unwrap().get("apparmor") {
+            Some(label) => assert_eq!(get_peer_apparmor_label(&a).unwrap(), label.parse().unwrap()),
+            None => assert_eq!(
+                get_peer_apparmor_label(&a).unwrap_err().raw_os_error(),
+                Some(libc::ENOPROTOOPT)
+            ),
+        }
+    }
+}
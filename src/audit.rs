@@ -0,0 +1,143 @@
+//! The `audit` module provides access to the Linux audit login UID and session ID of a socket's
+//! peer.
+//!
+//! Unlike the effective UID returned by [`get_peer_pid_ids()`], the login UID records which user
+//! originally logged in, and it is preserved across `setuid()`, `su`, `sudo`, etc. This makes it
+//! useful for audit trails.
+//!
+//! These values are read from `/proc/<pid>/loginuid` and `/proc/<pid>/sessionid`, so they are
+//! subject to the same PID reuse caveats as [`get_peer_pid_ids()`].
+//!
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+
+use crate::procfs;
+
+/// The value used by the kernel to indicate that the login UID or session ID is unset.
+const AUDIT_UNSET: u32 = u32::MAX;
+
+/// Represents the credentials of a Unix socket's peer, along with its audit login UID and session
+/// ID.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PeerAuditIds {
+    /// The peer's PID.
+    ///
+    /// **WARNING**: This is the PID of the process that originally opened the socket. That process
+    /// may have died, and another process may now be running with that PID. Use with caution.
+    pub pid: libc::pid_t,
+    /// The peer's effective user ID.
+    pub uid: libc::uid_t,
+    /// The peer's effective group ID.
+    pub gid: libc::gid_t,
+    /// The peer's audit login UID, or `None` if it is unset.
+    pub loginuid: Option<libc::uid_t>,
+    /// The peer's audit session ID, or `None` if it is unset.
+    pub sessionid: Option<u32>,
+}
+
+fn parse_audit_id(data: &str) -> io::Result<Option<u32>> {
+    match procfs::parse_proc_int(data)? {
+        AUDIT_UNSET => Ok(None),
+        id => Ok(Some(id)),
+    }
+}
+
+/// Get the audit login UID of the process with the given PID.
+///
+/// Returns `None` if the login UID is unset (for example, for processes started at boot that are
+/// not part of a login session).
+#[inline]
+pub fn get_loginuid(pid: libc::pid_t) -> io::Result<Option<libc::uid_t>> {
+    parse_audit_id(&procfs::read_proc_string(pid, "loginuid")?)
+}
+
+/// Get the audit session ID of the process with the given PID.
+///
+/// Returns `None` if the session ID is unset.
+#[inline]
+pub fn get_sessionid(pid: libc::pid_t) -> io::Result<Option<u32>> {
+    parse_audit_id(&procfs::read_proc_string(pid, "sessionid")?)
+}
+
+unsafe fn get_peer_audit_ids_raw(sockfd: RawFd) -> io::Result<PeerAuditIds> {
+    let cred = crate::ucred::get_ucred_raw(sockfd)?;
+
+    Ok(PeerAuditIds {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid,
+        loginuid: get_loginuid(cred.pid)?,
+        sessionid: get_sessionid(cred.pid)?,
+    })
+}
+
+/// Get the PID, UID, and GID of the given socket's peer, along with its audit login UID and
+/// session ID.
+///
+/// **WARNING**: The login UID and session ID are looked up by PID after the credentials are
+/// retrieved. If the original peer process has died and its PID has been reused, they will
+/// describe the wrong process. Use with caution.
+#[inline]
+pub fn get_peer_audit_ids(sock: &UnixStream) -> io::Result<PeerAuditIds> {
+    unsafe { get_peer_audit_ids_raw(sock.as_raw_fd()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_self_id(name: &str) -> Option<u32> {
+        match std::fs::read_to_string(format!("/proc/self/{}", name))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap()
+        {
+            u32::MAX => None,
+            id => Some(id),
+        }
+    }
+
+    #[test]
+    fn test_parse_audit_id() {
+        assert_eq!(parse_audit_id("0").unwrap(), Some(0));
+        assert_eq!(parse_audit_id("1000").unwrap(), Some(1000));
+        assert_eq!(parse_audit_id("4294967295").unwrap(), None);
+
+        for s in ["", "-1", "abc", "4294967296"].iter() {
+            assert_eq!(
+                parse_audit_id(s).unwrap_err().raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_peer_audit_ids() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        for sock in [a, b].iter() {
+            let ids = get_peer_audit_ids(sock).unwrap();
+            assert_eq!(ids.pid, unsafe { libc::getpid() });
+            assert_eq!(ids.uid, unsafe { libc::geteuid() });
+            assert_eq!(ids.gid, unsafe { libc::getegid() });
+            assert_eq!(ids.loginuid, read_self_id("loginuid"));
+            assert_eq!(ids.sessionid, read_self_id("sessionid"));
+        }
+    }
+
+    #[test]
+    fn test_get_loginuid_error() {
+        assert_eq!(
+            get_loginuid(-1).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            get_sessionid(-1).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+}
@@ -0,0 +1,15 @@
+//! Argument parsing helpers shared by the server binaries.
+
+use std::io;
+
+/// Parse a numeric option argument, or fail with a usage error if it is missing or invalid.
+pub fn parse_num<T: std::str::FromStr>(arg: Option<&str>) -> Result<T, Option<io::Error>> {
+    arg.and_then(|arg| arg.parse().ok()).ok_or(None)
+}
+
+/// Parse an octal file mode option argument, or fail with a usage error if it is missing or
+/// invalid.
+pub fn parse_mode(arg: Option<&str>) -> Result<u32, Option<io::Error>> {
+    arg.and_then(|arg| u32::from_str_radix(arg, 8).ok())
+        .ok_or(None)
+}
@@ -0,0 +1,98 @@
+//! `unix-cred-http-proxy`: an HTTP reverse proxy that passes client credentials to the backend.
+
+#[cfg(target_os = "linux")]
+#[path = "../common/mod.rs"]
+mod common;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+
+    use unix_cred::http_proxy::{HttpProxy, Upstream, DEFAULT_MAX_CONNECTIONS};
+    use unix_cred::policy::PeerPolicy;
+    use unix_cred::secure_bind::SecureBind;
+
+    use crate::common::{parse_mode, parse_num};
+
+    pub const USAGE: &str = "\
+Usage: unix-cred-http-proxy [options] <path> <upstream>
+
+Accept HTTP/1.1 requests on the Unix socket at <path>, and forward them to <upstream> (a TCP
+address like 127.0.0.1:8080, or unix:<path> for a Unix socket). Any X-Peer-* headers sent by the
+client are replaced with the client's X-Peer-Uid, X-Peer-Gid, X-Peer-Pid, and X-Peer-User.
+
+Options:
+    -m, --mode <mode>  The mode of the socket, in octal (default: 600)
+    -c <n>             Handle at most <n> connections at once (default: 32)
+    --allow-uid <uid>  Only allow clients with the given effective UID (may be repeated)
+    --allow-gid <gid>  Only allow clients with the given effective GID (may be repeated)
+    -h, --help         Show this help message
+
+If both --allow-uid and --allow-gid are given, clients that match either are allowed. Other
+clients get a 403 Forbidden response.
+";
+
+    pub fn run(args: Vec<String>) -> Result<(), Option<io::Error>> {
+        let mut mode = 0o600;
+        let mut max_connections = DEFAULT_MAX_CONNECTIONS;
+        let mut uids = Vec::new();
+        let mut gids = Vec::new();
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-m" | "--mode" => mode = parse_mode(args.next().as_deref())?,
+                "-c" => max_connections = parse_num(args.next().as_deref())?,
+                "--allow-uid" => uids.push(parse_num(args.next().as_deref())?),
+                "--allow-gid" => gids.push(parse_num(args.next().as_deref())?),
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    return Ok(());
+                }
+                s if s.starts_with('-') => return Err(None),
+                _ => positional.push(arg),
+            }
+        }
+
+        let (path, upstream) = match positional.as_slice() {
+            [path, upstream] => (path, upstream.parse::<Upstream>().unwrap()),
+            _ => return Err(None),
+        };
+
+        let policy = if uids.is_empty() && gids.is_empty() {
+            PeerPolicy::AllowAnyone
+        } else {
+            PeerPolicy::any_of(uids, gids)
+        };
+
+        let listener = SecureBind::new(path).mode(mode).bind().map_err(Some)?;
+
+        HttpProxy::new(listener, upstream)
+            .max_connections(max_connections)
+            .policy(policy)
+            .serve()
+            .map_err(Some)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    match imp::run(std::env::args().skip(1).collect()) {
+        Ok(()) => (),
+        Err(Some(e)) => {
+            eprintln!("unix-cred-http-proxy: {}", e);
+            std::process::exit(1);
+        }
+        Err(None) => {
+            eprint!("{}", imp::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("unix-cred-http-proxy: this tool is only supported on Linux");
+    std::process::exit(1);
+}
@@ -0,0 +1,82 @@
+//! `unix-cred-identd`: an RFC 1413 ident server for loopback TCP connections.
+
+#[cfg(target_os = "linux")]
+#[path = "../common/mod.rs"]
+#[allow(dead_code)] // parse_mode() is not used here
+mod common;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+    use std::net::TcpListener;
+
+    use unix_cred::ident::{IdentMode, IdentServer, DEFAULT_MAX_CONNECTIONS};
+
+    use crate::common::parse_num;
+
+    pub const USAGE: &str = "\
+Usage: unix-cred-identd [options]
+
+Answer ident (RFC 1413) queries about loopback TCP connections. Each reply is logged to stderr.
+
+Options:
+    -l, --listen <addr>  The address to listen on (default: 127.0.0.1:113)
+    --hide-user          Reply with HIDDEN-USER instead of the user name
+    --random-token       Reply with a random token instead of the user name (the user is logged)
+    -c <n>               Handle at most <n> connections at once (default: 32)
+    -h, --help           Show this help message
+";
+
+    pub fn run(args: Vec<String>) -> Result<(), Option<io::Error>> {
+        let mut addr = "127.0.0.1:113".to_string();
+        let mut mode = IdentMode::User;
+        let mut max_connections = DEFAULT_MAX_CONNECTIONS;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-l" | "--listen" => addr = args.next().ok_or(None)?,
+                "--hide-user" => mode = IdentMode::HiddenUser,
+                "--random-token" => mode = IdentMode::RandomToken,
+                "-c" => max_connections = parse_num(args.next().as_deref())?,
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    return Ok(());
+                }
+                _ => return Err(None),
+            }
+        }
+
+        let listener = TcpListener::bind(&addr).map_err(Some)?;
+
+        IdentServer::new(listener, mode)
+            .max_connections(max_connections)
+            .on_reply(|addr, reply| match &reply.result {
+                Ok(user) => eprintln!("{}: {} (uid {})", addr, reply, user.uid),
+                Err(_) => eprintln!("{}: {}", addr, reply),
+            })
+            .serve()
+            .map_err(Some)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    match imp::run(std::env::args().skip(1).collect()) {
+        Ok(()) => (),
+        Err(Some(e)) => {
+            eprintln!("unix-cred-identd: {}", e);
+            std::process::exit(1);
+        }
+        Err(None) => {
+            eprint!("{}", imp::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("unix-cred-identd: this tool is only supported on Linux");
+    std::process::exit(1);
+}
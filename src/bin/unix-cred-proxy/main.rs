@@ -0,0 +1,164 @@
+//! `unix-cred-proxy`: an authorizing proxy for sensitive Unix sockets.
+
+#[cfg(target_os = "linux")]
+#[path = "../common/mod.rs"]
+mod common;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+
+    use unix_cred::policy::PeerPolicy;
+    use unix_cred::proxy::{self, DatagramRelay, Route, SocketProxy};
+    use unix_cred::secure_bind::SecureBind;
+
+    use crate::common::{parse_mode, parse_num};
+
+    pub const USAGE: &str = "\
+Usage: unix-cred-proxy [options] <path> <route>...
+
+Accept connections on the Unix socket at <path>, and forward each one to the upstream socket of
+the first <route> that allows the client. Clients that no route allows are disconnected. Each
+connection is logged to standard error.
+
+Each <route> has the form <who>=<upstream>, where <who> is a comma-separated list of:
+    uid:<uid>  Allow clients with the given effective UID
+    gid:<gid>  Allow clients with the given effective GID
+    any        Allow any client
+    max:<n>    Forward at most <n> connections over this route at once
+
+For example, uid:0,gid:998,max:4=/run/docker.sock.
+
+Options:
+    -m, --mode <mode>  The mode of the socket, in octal (default: 600)
+    -c <n>             Forward at most <n> connections at once (over all routes)
+    --relay            Relay datagrams instead, re-sending each one with the original sender's
+                       credentials (requires CAP_SETUID, CAP_SETGID, and CAP_SYS_ADMIN; max:<n>
+                       and -c cannot be used)
+    -h, --help         Show this help message
+";
+
+    fn parse_route(spec: &str, relay: bool) -> Result<Route, Option<io::Error>> {
+        let (who, upstream) = spec.split_once('=').ok_or(None)?;
+        if upstream.is_empty() {
+            return Err(None);
+        }
+
+        let mut uids = Vec::new();
+        let mut gids = Vec::new();
+        let mut any = false;
+        let mut max = None;
+
+        for item in who.split(',') {
+            match item.split_once(':') {
+                Some(("uid", uid)) => uids.push(parse_num(Some(uid))?),
+                Some(("gid", gid)) => gids.push(parse_num(Some(gid))?),
+                Some(("max", n)) => max = Some(parse_num(Some(n))?),
+                None if item == "any" => any = true,
+                _ => return Err(None),
+            }
+        }
+
+        let policy = if any {
+            PeerPolicy::AllowAnyone
+        } else if uids.is_empty() && gids.is_empty() {
+            return Err(None);
+        } else {
+            PeerPolicy::any_of(uids, gids)
+        };
+
+        let route = Route::new(policy, upstream);
+        Ok(match max {
+            Some(_) if relay => return Err(None),
+            Some(max) => route.max_connections(max),
+            None => route,
+        })
+    }
+
+    pub fn run(args: Vec<String>) -> Result<(), Option<io::Error>> {
+        let mut mode = 0o600;
+        let mut max = None;
+        let mut relay = false;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-m" | "--mode" => mode = parse_mode(args.next().as_deref())?,
+                "-c" => max = Some(parse_num(args.next().as_deref())?),
+                "--relay" => relay = true,
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    return Ok(());
+                }
+                s if s.starts_with('-') => return Err(None),
+                _ => positional.push(arg),
+            }
+        }
+
+        if relay && max.is_some() {
+            return Err(None);
+        }
+
+        let (path, routes) = match positional.split_first() {
+            Some((path, routes)) if !routes.is_empty() => (path, routes),
+            _ => return Err(None),
+        };
+
+        let routes = routes
+            .iter()
+            .map(|spec| parse_route(spec, relay))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if relay {
+            // Fail before creating the socket
+            proxy::check_relay_capabilities().map_err(Some)?;
+
+            let mut relay = DatagramRelay::new(
+                SecureBind::new(path)
+                    .mode(mode)
+                    .bind_datagram()
+                    .map_err(Some)?,
+            )
+            .map_err(Some)?
+            .on_event(|event| eprintln!("unix-cred-proxy: {}", event));
+            for route in routes {
+                relay = relay.route(route);
+            }
+
+            return relay.serve().map_err(Some);
+        }
+
+        let mut proxy = SocketProxy::new(SecureBind::new(path).mode(mode).bind().map_err(Some)?)
+            .on_event(|event| eprintln!("unix-cred-proxy: {}", event));
+        if let Some(max) = max {
+            proxy = proxy.max_connections(max);
+        }
+        for route in routes {
+            proxy = proxy.route(route);
+        }
+
+        proxy.serve().map_err(Some)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    match imp::run(std::env::args().skip(1).collect()) {
+        Ok(()) => (),
+        Err(Some(e)) => {
+            eprintln!("unix-cred-proxy: {}", e);
+            std::process::exit(1);
+        }
+        Err(None) => {
+            eprint!("{}", imp::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("unix-cred-proxy: this tool is only supported on Linux");
+    std::process::exit(1);
+}
@@ -0,0 +1,98 @@
+//! `unix-cred-unixserver`: a `unixserver`-compatible UCSPI server.
+
+#[cfg(target_os = "linux")]
+#[path = "../common/mod.rs"]
+mod common;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::ffi::OsString;
+    use std::io;
+
+    use unix_cred::policy::PeerPolicy;
+    use unix_cred::secure_bind::SecureBind;
+    use unix_cred::ucspi::{UnixServer, DEFAULT_MAX_CONNECTIONS};
+
+    use crate::common::{parse_mode, parse_num};
+
+    pub const USAGE: &str = "\
+Usage: unix-cred-unixserver [options] <path> <program> [args...]
+
+Listen on the Unix socket at <path>, and run <program> for each connection with its standard input
+and output connected to the socket. The client's credentials are passed in the UNIXREMOTEEUID,
+UNIXREMOTEEGID, and UNIXREMOTEPID environment variables.
+
+Options:
+    -m, --mode <mode>  The mode of the socket, in octal (default: 600)
+    -c <n>             Handle at most <n> connections at once (default: 10)
+    --allow-uid <uid>  Only allow clients with the given effective UID (may be repeated)
+    --allow-gid <gid>  Only allow clients with the given effective GID (may be repeated)
+    -h, --help         Show this help message
+
+If both --allow-uid and --allow-gid are given, clients that match either are allowed. Clients also
+need write permission on the socket (see --mode). A stale socket at <path> is replaced.
+";
+
+    pub fn run(args: Vec<OsString>) -> Result<(), Option<io::Error>> {
+        let mut mode = 0o600;
+        let mut max_connections = DEFAULT_MAX_CONNECTIONS;
+        let mut uids = Vec::new();
+        let mut gids = Vec::new();
+
+        let mut args = args.into_iter();
+        let path = loop {
+            let arg = args.next().ok_or(None)?;
+            let mut next_str = || args.next().and_then(|arg| arg.into_string().ok());
+
+            match arg.to_str() {
+                Some("-m" | "--mode") => mode = parse_mode(next_str().as_deref())?,
+                Some("-c") => max_connections = parse_num(next_str().as_deref())?,
+                Some("--allow-uid") => uids.push(parse_num(next_str().as_deref())?),
+                Some("--allow-gid") => gids.push(parse_num(next_str().as_deref())?),
+                Some("-h" | "--help") => {
+                    print!("{}", USAGE);
+                    return Ok(());
+                }
+                Some("--") => break args.next().ok_or(None)?,
+                Some(s) if s.starts_with('-') => return Err(None),
+                _ => break arg,
+            }
+        };
+        let program = args.next().ok_or(None)?;
+
+        let policy = if uids.is_empty() && gids.is_empty() {
+            PeerPolicy::AllowAnyone
+        } else {
+            PeerPolicy::any_of(uids, gids)
+        };
+
+        let listener = SecureBind::new(path).mode(mode).bind().map_err(Some)?;
+
+        UnixServer::new(listener, program, args)
+            .max_connections(max_connections)
+            .policy(policy)
+            .serve()
+            .map_err(Some)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    match imp::run(std::env::args_os().skip(1).collect()) {
+        Ok(()) => (),
+        Err(Some(e)) => {
+            eprintln!("unix-cred-unixserver: {}", e);
+            std::process::exit(1);
+        }
+        Err(None) => {
+            eprint!("{}", imp::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("unix-cred-unixserver: this tool is only supported on Linux");
+    std::process::exit(1);
+}
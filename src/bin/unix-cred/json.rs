@@ -0,0 +1,125 @@
+use std::fmt;
+
+/// A minimal JSON value, used for the `--json` output format.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Array(Vec<Value>),
+    Object(Vec<(&'static str, Value)>),
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(val: Option<T>) -> Self {
+        val.map_or(Self::Null, Into::into)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        Self::Bool(val)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(val: i32) -> Self {
+        Self::Int(val.into())
+    }
+}
+
+impl From<u32> for Value {
+    fn from(val: u32) -> Self {
+        Self::Int(val.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Self::Str(val)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        Self::Str(val.into())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(val: Vec<T>) -> Self {
+        Self::Array(val.into_iter().map(Into::into).collect())
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Str(s) => write_str(f, s),
+            Self::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let val = Value::Object(vec![
+            ("null", Value::Null),
+            ("bool", true.into()),
+            ("int", (-3).into()),
+            ("opt", Some(5u32).into()),
+            ("str", "a\"b\\c\n\u{1}é".into()),
+            ("arr", vec![1u32, 2].into()),
+            ("empty", Value::Array(Vec::new())),
+        ]);
+
+        assert_eq!(
+            val.to_string(),
+            r#"{"null":null,"bool":true,"int":-3,"opt":5,"str":"a\"b\\c\n\u0001é","arr":[1,2],"empty":[]}"#
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! `unix-cred`: a command-line tool to inspect Unix sockets and the credentials of their peers.
+
+#[cfg(target_os = "linux")]
+mod json;
+#[cfg(target_os = "linux")]
+mod serve;
+#[cfg(target_os = "linux")]
+mod table;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+    use std::os::unix::net::UnixStream;
+
+    use unix_cred::diag::{self, SocketHolder, UnixSocketInfo};
+
+    use crate::json::Value;
+    use crate::table::Table;
+
+    pub const USAGE: &str = "\
+Usage: unix-cred [--json] [--type <type>] <command> [args...]
+
+Commands:
+    peer <path>      Connect to the socket at <path> and show the server's credentials
+    ls               List all Unix sockets, with their peers and owning processes
+    whois <inode>    Show the socket with the given inode and the processes that have it open
+    serve <addr>     Listen on <addr> (a path, or @name for an abstract socket) and report the
+                     credentials of each client back to the client and to stdout
+
+Options:
+    --json           Output JSON instead of a table
+    --type <type>    The socket type for `serve`: stream (default), dgram, or seqpacket
+    -h, --help       Show this help message
+";
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum Format {
+        Table,
+        Json,
+    }
+
+    fn opt_to_string<T: ToString>(val: Option<T>) -> String {
+        val.map_or_else(|| "-".into(), |val| val.to_string())
+    }
+
+    fn holders_to_string(holders: &[SocketHolder]) -> String {
+        let procs: Vec<String> = holders
+            .iter()
+            .map(|holder| {
+                let fds: Vec<String> = holder.fds.iter().map(|fd| fd.to_string()).collect();
+                format!("{}({},fd={})", holder.name, holder.pid, fds.join(","))
+            })
+            .collect();
+
+        if procs.is_empty() {
+            "-".into()
+        } else {
+            procs.join(" ")
+        }
+    }
+
+    fn holder_to_json(holder: &SocketHolder) -> Value {
+        Value::Object(vec![
+            ("pid", holder.pid.into()),
+            ("name", holder.name.as_str().into()),
+            ("fds", holder.fds.clone().into()),
+            ("ruid", holder.ruid.into()),
+            ("uid", holder.uid.into()),
+            ("rgid", holder.rgid.into()),
+            ("gid", holder.gid.into()),
+            ("groups", holder.groups.clone().into()),
+        ])
+    }
+
+    fn socket_to_json(info: &UnixSocketInfo, holders: &[SocketHolder]) -> Value {
+        Value::Object(vec![
+            ("inode", info.inode.into()),
+            ("type", info.sock_type.to_string().into()),
+            ("state", info.state.to_string().into()),
+            (
+                "address",
+                info.name.as_ref().map(|name| name.to_string()).into(),
+            ),
+            ("peer_inode", info.peer_inode.into()),
+            ("rqueue", info.rqueue.into()),
+            ("wqueue", info.wqueue.into()),
+            ("uid", info.uid.into()),
+            (
+                "processes",
+                Value::Array(holders.iter().map(holder_to_json).collect()),
+            ),
+        ])
+    }
+
+    fn socket_table() -> Table {
+        Table::new(&[
+            "TYPE",
+            "STATE",
+            "RECV-Q",
+            "SEND-Q",
+            "INODE",
+            "PEER",
+            "UID",
+            "ADDRESS",
+            "PROCESSES",
+        ])
+    }
+
+    fn push_socket(table: &mut Table, info: &UnixSocketInfo, holders: &[SocketHolder]) {
+        table.push(vec![
+            info.sock_type.to_string(),
+            info.state.to_string(),
+            opt_to_string(info.rqueue),
+            opt_to_string(info.wqueue),
+            info.inode.to_string(),
+            opt_to_string(info.peer_inode),
+            opt_to_string(info.uid),
+            opt_to_string(info.name.as_ref()),
+            holders_to_string(holders),
+        ]);
+    }
+
+    pub fn cmd_peer(path: &str, format: Format) -> io::Result<()> {
+        let sock = UnixStream::connect(path)?;
+        let (pid, uid, gid) = unix_cred::get_peer_pid_ids(&sock)?;
+
+        match format {
+            Format::Table => {
+                let mut table = Table::new(&["PID", "UID", "GID"]);
+                table.push(vec![opt_to_string(pid), uid.to_string(), gid.to_string()]);
+                print!("{}", table);
+            }
+            Format::Json => println!(
+                "{}",
+                Value::Object(vec![
+                    ("pid", pid.into()),
+                    ("uid", uid.into()),
+                    ("gid", gid.into()),
+                ])
+            ),
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_ls(format: Format) -> io::Result<()> {
+        let sockets = diag::list_unix_sockets()?;
+        let mut holders = diag::list_socket_holders()?;
+
+        let mut entries: Vec<(UnixSocketInfo, Vec<SocketHolder>)> = sockets
+            .into_iter()
+            .map(|info| {
+                let h = holders.remove(&info.inode).unwrap_or_default();
+                (info, h)
+            })
+            .collect();
+        entries.sort_by_key(|(info, _)| info.inode);
+
+        match format {
+            Format::Table => {
+                let mut table = socket_table();
+                for (info, holders) in entries.iter() {
+                    push_socket(&mut table, info, holders);
+                }
+                print!("{}", table);
+            }
+            Format::Json => println!(
+                "{}",
+                Value::Array(
+                    entries
+                        .iter()
+                        .map(|(info, holders)| socket_to_json(info, holders))
+                        .collect()
+                )
+            ),
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_whois(inode: &str, format: Format) -> io::Result<()> {
+        let inode: u32 = inode
+            .strip_prefix("socket:[")
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(inode)
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid inode number"))?;
+
+        // The socket may not be a Unix socket, in which case we can still show its holders
+        let info = match diag::get_unix_socket_info(inode) {
+            Ok(info) => Some(info),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => None,
+            Err(e) => return Err(e),
+        };
+        let holders = diag::find_socket_holders(inode)?;
+
+        if info.is_none() && holders.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no socket with inode {} found", inode),
+            ));
+        }
+
+        match format {
+            Format::Table => {
+                if let Some(info) = info.as_ref() {
+                    let mut table = socket_table();
+                    push_socket(&mut table, info, &[]);
+                    println!("{}", table);
+                }
+
+                let mut table = Table::new(&["PID", "NAME", "FDS", "UID", "GID", "GROUPS"]);
+                for holder in holders.iter() {
+                    let fds: Vec<String> = holder.fds.iter().map(|fd| fd.to_string()).collect();
+                    let groups: Vec<String> =
+                        holder.groups.iter().map(|gid| gid.to_string()).collect();
+
+                    table.push(vec![
+                        holder.pid.to_string(),
+                        holder.name.clone(),
+                        fds.join(","),
+                        holder.uid.to_string(),
+                        holder.gid.to_string(),
+                        groups.join(","),
+                    ]);
+                }
+                print!("{}", table);
+            }
+            Format::Json => {
+                let val = match info.as_ref() {
+                    Some(info) => socket_to_json(info, &holders),
+                    None => Value::Object(vec![
+                        ("inode", inode.into()),
+                        (
+                            "processes",
+                            Value::Array(holders.iter().map(holder_to_json).collect()),
+                        ),
+                    ]),
+                };
+                println!("{}", val);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn run(args: Vec<String>) -> Result<(), Option<io::Error>> {
+        let mut format = Format::Table;
+        let mut sock_type = libc::SOCK_STREAM;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => format = Format::Json,
+                "--type" => {
+                    sock_type = args
+                        .next()
+                        .and_then(|ty| crate::serve::parse_type(&ty))
+                        .ok_or(None)?;
+                }
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    return Ok(());
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => return Err(None),
+                _ => positional.push(arg),
+            }
+        }
+
+        let positional: Vec<&str> = positional.iter().map(String::as_str).collect();
+
+        match positional.as_slice() {
+            ["peer", path] => cmd_peer(path, format),
+            ["ls"] => cmd_ls(format),
+            ["whois", inode] => cmd_whois(inode, format),
+            ["serve", addr] => crate::serve::cmd_serve(addr, sock_type, format),
+            _ => return Err(None),
+        }
+        .map_err(Some)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    // Exit quietly if stdout is closed early (e.g. when piped to `head`)
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+
+    match imp::run(std::env::args().skip(1).collect()) {
+        Ok(()) => (),
+        Err(Some(e)) => {
+            eprintln!("unix-cred: {}", e);
+            std::process::exit(1);
+        }
+        Err(None) => {
+            eprint!("{}", imp::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("unix-cred: this tool is only supported on Linux");
+    std::process::exit(1);
+}
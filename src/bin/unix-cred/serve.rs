@@ -0,0 +1,301 @@
+//! The `serve` command: a debugging server that reports the credentials of each client back to
+//! the client (and to stdout).
+
+use std::io::{self, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener, UnixStream};
+
+use unix_cred::addr::UnixSocketName;
+use unix_cred::seqpacket::{UnixSeqpacket, UnixSeqpacketListener};
+use unix_cred::ucred::{self, Ucred};
+use unix_cred::{lsm, scm, PeerSocket};
+
+use crate::imp::Format;
+use crate::json::Value;
+
+/// Parse a socket address given on the command line: `@name` is an abstract name, and anything
+/// else is a path.
+pub fn parse_name(name: &str) -> UnixSocketName {
+    match name.strip_prefix('@') {
+        Some(name) => UnixSocketName::Abstract(name.as_bytes().to_vec()),
+        None => UnixSocketName::Path(name.into()),
+    }
+}
+
+pub fn parse_type(ty: &str) -> Option<libc::c_int> {
+    match ty {
+        "stream" => Some(libc::SOCK_STREAM),
+        "dgram" => Some(libc::SOCK_DGRAM),
+        "seqpacket" => Some(libc::SOCK_SEQPACKET),
+        _ => None,
+    }
+}
+
+fn type_name(ty: libc::c_int) -> &'static str {
+    match ty {
+        libc::SOCK_DGRAM => "dgram",
+        libc::SOCK_SEQPACKET => "seqpacket",
+        _ => "stream",
+    }
+}
+
+/// Convert a name to a `SocketAddr` that the standard library's socket types can bind to.
+fn std_addr(name: &UnixSocketName) -> io::Result<SocketAddr> {
+    match name {
+        UnixSocketName::Path(path) => SocketAddr::from_pathname(path),
+        UnixSocketName::Abstract(name) => SocketAddr::from_abstract_name(name),
+    }
+}
+
+/// A connected socket that `handle_connection()` can serve.
+trait Connection: PeerSocket + Send + 'static {
+    fn send_all(&self, buf: &[u8]) -> io::Result<()>;
+}
+
+impl Connection for UnixStream {
+    fn send_all(&self, buf: &[u8]) -> io::Result<()> {
+        (&*self).write_all(buf)
+    }
+}
+
+impl Connection for UnixSeqpacket {
+    fn send_all(&self, buf: &[u8]) -> io::Result<()> {
+        self.send(buf).map(drop)
+    }
+}
+
+/// Returns `None` if the error just means that the information is unavailable (e.g. because no
+/// LSM that supports `SO_PEERSEC` is active).
+fn ignore_unavailable<T>(res: io::Result<T>) -> io::Result<Option<T>> {
+    match res {
+        Ok(val) => Ok(Some(val)),
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENOPROTOOPT) | Some(libc::EINVAL)
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// A single line of output, as a list of fields.
+struct Report(Vec<(&'static str, Value)>);
+
+impl Report {
+    fn new(ty: libc::c_int, event: &str) -> Self {
+        Self(vec![
+            ("type", type_name(ty).into()),
+            ("event", event.into()),
+        ])
+    }
+
+    fn push<T: Into<Value>>(&mut self, key: &'static str, val: T) {
+        self.0.push((key, val.into()));
+    }
+
+    fn push_cred(&mut self, cred: Option<&Ucred>) {
+        self.push("pid", cred.map(|cred| cred.pid));
+        self.push("uid", cred.map(|cred| cred.uid));
+        self.push("gid", cred.map(|cred| cred.gid));
+    }
+
+    fn format(self, format: Format) -> String {
+        match format {
+            Format::Json => format!("{}\n", Value::Object(self.0)),
+            Format::Table => {
+                let fields: Vec<String> = self
+                    .0
+                    .into_iter()
+                    .map(|(key, val)| format!("{}={}", key, text_value(val)))
+                    .collect();
+                format!("{}\n", fields.join(" "))
+            }
+        }
+    }
+}
+
+fn text_value(val: Value) -> String {
+    match val {
+        Value::Null => "-".into(),
+        Value::Str(s) => s,
+        Value::Array(items) => {
+            let items: Vec<String> = items.into_iter().map(text_value).collect();
+            items.join(",")
+        }
+        val => val.to_string(),
+    }
+}
+
+fn emit(report: Report, format: Format) -> String {
+    let line = report.format(format);
+    io::stdout().lock().write_all(line.as_bytes()).ok();
+    line
+}
+
+fn handle_connection<S: Connection>(sock: S, ty: libc::c_int, format: Format) -> io::Result<()> {
+    let mut report = Report::new(ty, "connect");
+    report.push_cred(Some(&sock.peer_ucred()?));
+    report.push("groups", ignore_unavailable(ucred::get_peer_groups(&sock))?);
+    report.push("label", ignore_unavailable(lsm::get_peersec(&sock))?);
+    sock.send_all(emit(report, format).as_bytes())?;
+
+    let mut buf = vec![0; 65536];
+    loop {
+        let (n, cred) = scm::recv_with_creds(&sock, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        let mut report = Report::new(ty, "message");
+        report.push("bytes", n as u32);
+        report.push_cred(cred.as_ref());
+        sock.send_all(emit(report, format).as_bytes())?;
+    }
+
+    emit(Report::new(ty, "disconnect"), format);
+
+    Ok(())
+}
+
+fn serve_datagram(sock: UnixDatagram, format: Format) -> io::Result<()> {
+    let mut buf = vec![0; 65536];
+    loop {
+        let (n, cred, addr) = scm::recv_from_with_creds(&sock, &mut buf)?;
+
+        let mut report = Report::new(libc::SOCK_DGRAM, "message");
+        report.push("bytes", n as u32);
+        report.push_cred(cred.as_ref());
+        report.push("address", addr.as_ref().map(|addr| addr.to_string()));
+        let line = emit(report, format);
+
+        // Unbound clients can't receive a reply
+        if let Some(addr) = addr {
+            if let Err(e) =
+                scm::send_to_with_creds(&sock, line.as_bytes(), &addr, &scm::current_creds())
+            {
+                eprintln!("unix-cred: {}: {}", addr, e);
+            }
+        }
+    }
+}
+
+fn serve_connections<S: Connection>(
+    mut accept: impl FnMut() -> io::Result<S>,
+    ty: libc::c_int,
+    format: Format,
+) -> io::Result<()> {
+    loop {
+        let sock = accept()?;
+
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(sock, ty, format) {
+                eprintln!("unix-cred: {}", e);
+            }
+        });
+    }
+}
+
+pub fn cmd_serve(name: &str, ty: libc::c_int, format: Format) -> io::Result<()> {
+    let name = parse_name(name);
+
+    match ty {
+        libc::SOCK_DGRAM => {
+            let sock = UnixDatagram::bind_addr(&std_addr(&name)?)?;
+            scm::set_passcred(&sock, true)?;
+            serve_datagram(sock, format)
+        }
+        libc::SOCK_SEQPACKET => {
+            let listener = UnixSeqpacketListener::bind_addr(&name)?;
+            // Accepted sockets inherit SO_PASSCRED, so messages sent right after connecting
+            // still carry credentials
+            scm::set_passcred(&listener, true)?;
+            serve_connections(|| listener.accept().map(|(sock, _)| sock), ty, format)
+        }
+        _ => {
+            let listener = UnixListener::bind_addr(&std_addr(&name)?)?;
+            scm::set_passcred(&listener, true)?;
+            serve_connections(|| listener.accept().map(|(sock, _)| sock), ty, format)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(
+            parse_name("/run/foo.sock"),
+            UnixSocketName::Path("/run/foo.sock".into())
+        );
+        assert_eq!(
+            parse_name("@foo"),
+            UnixSocketName::Abstract(b"foo".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        let (a, b) = UnixStream::pair().unwrap();
+        scm::set_passcred(&a, true).unwrap();
+        let thread =
+            std::thread::spawn(move || handle_connection(a, libc::SOCK_STREAM, Format::Table));
+
+        let mut reader = BufReader::new(&b);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with(&format!(
+            "type=stream event=connect pid={} uid={} gid={} groups=",
+            std::process::id(),
+            unsafe { libc::geteuid() },
+            unsafe { libc::getegid() },
+        )));
+
+        (&b).write_all(b"hello").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(
+            line,
+            format!(
+                "type=stream event=message bytes=5 pid={} uid={} gid={}\n",
+                std::process::id(),
+                unsafe { libc::getuid() },
+                unsafe { libc::getgid() },
+            )
+        );
+
+        b.shutdown(std::net::Shutdown::Write).unwrap();
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_handle_seqpacket() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        scm::set_passcred(&a, true).unwrap();
+        let thread =
+            std::thread::spawn(move || handle_connection(a, libc::SOCK_SEQPACKET, Format::Json));
+
+        let mut buf = [0; 1024];
+        let n = b.recv(&mut buf).unwrap();
+        let line = std::str::from_utf8(&buf[..n]).unwrap();
+        assert!(line.starts_with(&format!(
+            "{{\"type\":\"seqpacket\",\"event\":\"connect\",\"pid\":{},",
+            std::process::id()
+        )));
+
+        b.send(b"hello").unwrap();
+        let n = b.recv(&mut buf).unwrap();
+        assert!(std::str::from_utf8(&buf[..n])
+            .unwrap()
+            .contains("\"event\":\"message\",\"bytes\":5,"));
+
+        b.shutdown(std::net::Shutdown::Write).unwrap();
+        thread.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// A simple table with left-aligned columns, used for the default output format.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self {
+            headers: headers.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.headers.len());
+        self.rows.push(row);
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.len()).collect();
+        for row in self.rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let headers = self.headers.iter().map(|h| h.to_string()).collect();
+        for row in std::iter::once(&headers).chain(self.rows.iter()) {
+            let mut line = String::new();
+            for (i, (cell, width)) in row.iter().zip(widths.iter()).enumerate() {
+                if i + 1 == row.len() {
+                    line.push_str(cell);
+                } else {
+                    line.push_str(&format!("{:width$}  ", cell, width = width));
+                }
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut table = Table::new(&["A", "LONGER", "C"]);
+        table.push(vec!["xyz".into(), "1".into(), "last column".into()]);
+        table.push(vec!["".into(), "".into(), "".into()]);
+
+        assert_eq!(
+            table.to_string(),
+            "A    LONGER  C\nxyz  1       last column\n\n"
+        );
+    }
+}
@@ -5,3 +5,37 @@ pub const LOCAL_PEEREID: libc::c_int = 0x0003;
 pub const XU_NGROUPS: usize = libc::XU_NGROUPS as usize;
 #[cfg(any(target_os = "dragonfly", target_os = "macos", target_os = "ios"))]
 pub const XU_NGROUPS: usize = 16;
+
+#[cfg(target_os = "linux")]
+pub const SOCK_DIAG_BY_FAMILY: u16 = 20;
+
+#[cfg(target_os = "linux")]
+pub const UDIAG_SHOW_NAME: u32 = 0x01;
+#[cfg(target_os = "linux")]
+pub const UDIAG_SHOW_VFS: u32 = 0x02;
+#[cfg(target_os = "linux")]
+pub const UDIAG_SHOW_PEER: u32 = 0x04;
+#[cfg(target_os = "linux")]
+pub const UDIAG_SHOW_RQLEN: u32 = 0x10;
+#[cfg(target_os = "linux")]
+pub const UDIAG_SHOW_UID: u32 = 0x40;
+
+#[cfg(target_os = "linux")]
+pub const UNIX_DIAG_NAME: u16 = 0;
+#[cfg(target_os = "linux")]
+pub const UNIX_DIAG_VFS: u16 = 1;
+#[cfg(target_os = "linux")]
+pub const UNIX_DIAG_PEER: u16 = 2;
+#[cfg(target_os = "linux")]
+pub const UNIX_DIAG_RQLEN: u16 = 4;
+#[cfg(target_os = "linux")]
+pub const UNIX_DIAG_UID: u16 = 7;
+
+#[cfg(target_os = "linux")]
+pub const TCP_ESTABLISHED: u8 = 1;
+#[cfg(target_os = "linux")]
+pub const TCP_SYN_SENT: u8 = 2;
+#[cfg(target_os = "linux")]
+pub const TCP_CLOSE: u8 = 7;
+#[cfg(target_os = "linux")]
+pub const TCP_LISTEN: u8 = 10;
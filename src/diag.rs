@@ -0,0 +1,610 @@
+//! The `diag` module lists the Unix sockets on the system using the Linux `NETLINK_SOCK_DIAG`
+//! interface (the same interface used by `ss -x`).
+//!
+//! Unlike the other interfaces in this crate, this is not limited to sockets that the current
+//! process has open: it can describe every Unix socket in the current network namespace,
+//! including its type, state, address, peer, queue lengths, and owner.
+//!
+//! Sockets are identified by their inode numbers, which can be found for sockets that the current
+//! process has open using [`socket_inode()`], and for sockets that other processes have open by
+//! looking at the targets of the `socket:[<inode>]` links in `/proc/<pid>/fd`.
+//!
+//! [`current_peer_holders()`] builds on this to find the processes that currently have the peer
+//! of a connected socket open, along with their *current* credentials. This complements
+//! [`get_peer_pid_ids()`], which returns credentials that were cached at connection time.
+//!
+//! [`socket_inode()`]: ./fn.socket_inode.html
+//! [`current_peer_holders()`]: ./fn.current_peer_holders.html
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+
+use crate::constants;
+use crate::netlink;
+use crate::procfs;
+
+pub use crate::addr::UnixSocketName;
+
+/// The size of `struct unix_diag_msg`.
+const UNIX_DIAG_MSG_LEN: usize = 16;
+
+/// The type of a Unix socket.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum UnixSocketType {
+    /// A `SOCK_STREAM` socket.
+    Stream,
+    /// A `SOCK_DGRAM` socket.
+    Datagram,
+    /// A `SOCK_SEQPACKET` socket.
+    Seqpacket,
+    /// Another socket type (which should never happen for Unix sockets).
+    Other(u8),
+}
+
+impl UnixSocketType {
+    fn from_raw(ty: u8) -> Self {
+        match ty as libc::c_int {
+            libc::SOCK_STREAM => Self::Stream,
+            libc::SOCK_DGRAM => Self::Datagram,
+            libc::SOCK_SEQPACKET => Self::Seqpacket,
+            _ => Self::Other(ty),
+        }
+    }
+}
+
+impl fmt::Display for UnixSocketType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Stream => f.write_str("stream"),
+            Self::Datagram => f.write_str("dgram"),
+            Self::Seqpacket => f.write_str("seqpacket"),
+            Self::Other(ty) => write!(f, "{}", ty),
+        }
+    }
+}
+
+/// The state of a Unix socket.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum UnixSocketState {
+    /// The socket is not connected (or its peer has been closed).
+    Unconnected,
+    /// The socket is in the process of connecting.
+    Connecting,
+    /// The socket is connected.
+    Connected,
+    /// The socket is listening for connections.
+    Listening,
+    /// Another state (which should never happen for Unix sockets).
+    Other(u8),
+}
+
+impl UnixSocketState {
+    fn from_raw(state: u8) -> Self {
+        match state {
+            constants::TCP_CLOSE => Self::Unconnected,
+            constants::TCP_SYN_SENT => Self::Connecting,
+            constants::TCP_ESTABLISHED => Self::Connected,
+            constants::TCP_LISTEN => Self::Listening,
+            _ => Self::Other(state),
+        }
+    }
+}
+
+impl fmt::Display for UnixSocketState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unconnected => f.write_str("unconnected"),
+            Self::Connecting => f.write_str("connecting"),
+            Self::Connected => f.write_str("connected"),
+            Self::Listening => f.write_str("listening"),
+            Self::Other(state) => write!(f, "{}", state),
+        }
+    }
+}
+
+/// Represents information about a Unix socket, as returned by `NETLINK_SOCK_DIAG`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct UnixSocketInfo {
+    /// The socket's inode number.
+    pub inode: u32,
+    /// The socket's type.
+    pub sock_type: UnixSocketType,
+    /// The socket's state.
+    pub state: UnixSocketState,
+    /// The address that the socket is bound to, or `None` if it is not bound.
+    pub name: Option<UnixSocketName>,
+    /// The device and inode numbers of the socket file in the filesystem (as they would be
+    /// reported by `stat()`), or `None` if it is not bound to a path.
+    pub vfs: Option<(libc::dev_t, u32)>,
+    /// The inode number of the socket's peer, or `None` if it is not connected.
+    pub peer_inode: Option<u32>,
+    /// The length of the socket's receive queue.
+    ///
+    /// For listening sockets, this is the number of pending connections.
+    pub rqueue: Option<u32>,
+    /// The length of the socket's send queue.
+    ///
+    /// For listening sockets, this is the maximum number of pending connections (the backlog).
+    pub wqueue: Option<u32>,
+    /// The effective UID of the process that created the socket. This requires Linux 5.3+.
+    pub uid: Option<libc::uid_t>,
+}
+
+impl UnixSocketInfo {
+    fn parse(msg: &[u8]) -> io::Result<Self> {
+        if msg.len() < UNIX_DIAG_MSG_LEN || msg[0] as libc::c_int != libc::AF_UNIX {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        let mut info = Self {
+            inode: netlink::read_u32(msg, 4)?,
+            sock_type: UnixSocketType::from_raw(msg[1]),
+            state: UnixSocketState::from_raw(msg[2]),
+            name: None,
+            vfs: None,
+            peer_inode: None,
+            rqueue: None,
+            wqueue: None,
+            uid: None,
+        };
+
+        for (ty, payload) in netlink::attrs(&msg[UNIX_DIAG_MSG_LEN..]) {
+            match ty {
+                constants::UNIX_DIAG_NAME => info.name = Some(UnixSocketName::from_raw(payload)),
+                constants::UNIX_DIAG_VFS => {
+                    let inode = netlink::read_u32(payload, 0)?;
+                    // The kernel reports its internal device number (MAJOR << 20 | MINOR)
+                    let dev = netlink::read_u32(payload, 4)?;
+                    info.vfs = Some((libc::makedev(dev >> 20, dev & 0xfffff), inode));
+                }
+                constants::UNIX_DIAG_PEER => {
+                    info.peer_inode = match netlink::read_u32(payload, 0)? {
+                        0 => None,
+                        ino => Some(ino),
+                    };
+                }
+                constants::UNIX_DIAG_RQLEN => {
+                    info.rqueue = Some(netlink::read_u32(payload, 0)?);
+                    info.wqueue = Some(netlink::read_u32(payload, 4)?);
+                }
+                constants::UNIX_DIAG_UID => info.uid = Some(netlink::read_u32(payload, 0)?),
+                _ => (),
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+fn unix_diag_request(inode: u32, show: u32) -> Vec<u8> {
+    let mut req = Vec::with_capacity(24);
+    req.push(libc::AF_UNIX as u8);
+    req.push(0);
+    req.extend_from_slice(&0u16.to_ne_bytes());
+    // All states
+    req.extend_from_slice(&u32::MAX.to_ne_bytes());
+    req.extend_from_slice(&inode.to_ne_bytes());
+    req.extend_from_slice(&show.to_ne_bytes());
+    // INET_DIAG_NOCOOKIE
+    req.extend_from_slice(&u32::MAX.to_ne_bytes());
+    req.extend_from_slice(&u32::MAX.to_ne_bytes());
+    req
+}
+
+const SHOW_ALL: u32 = constants::UDIAG_SHOW_NAME
+    | constants::UDIAG_SHOW_VFS
+    | constants::UDIAG_SHOW_PEER
+    | constants::UDIAG_SHOW_RQLEN
+    | constants::UDIAG_SHOW_UID;
+
+/// List all of the Unix sockets in the current network namespace.
+pub fn list_unix_sockets() -> io::Result<Vec<UnixSocketInfo>> {
+    netlink::sock_diag_request(&unix_diag_request(0, SHOW_ALL), true)?
+        .iter()
+        .map(|msg| UnixSocketInfo::parse(msg))
+        .collect()
+}
+
+/// Get information about the Unix socket with the given inode number.
+///
+/// If there is no such socket, this fails with `ENOENT`.
+pub fn get_unix_socket_info(inode: u32) -> io::Result<UnixSocketInfo> {
+    match netlink::sock_diag_request(&unix_diag_request(inode, SHOW_ALL), false)?.first() {
+        Some(msg) => UnixSocketInfo::parse(msg),
+        None => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+    }
+}
+
+/// Get the inode number of the given socket.
+///
+/// This can be used with [`get_unix_socket_info()`], or to find the socket in the output of
+/// [`list_unix_sockets()`].
+///
+/// [`get_unix_socket_info()`]: ./fn.get_unix_socket_info.html
+/// [`list_unix_sockets()`]: ./fn.list_unix_sockets.html
+pub fn socket_inode<S: AsRawFd>(sock: &S) -> io::Result<u32> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(sock.as_raw_fd(), &mut st) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if st.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(io::Error::from_raw_os_error(libc::ENOTSOCK));
+    }
+
+    Ok(st.st_ino as u32)
+}
+
+/// Represents a process that has a particular socket open, along with its current credentials.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SocketHolder {
+    /// The process's PID.
+    pub pid: libc::pid_t,
+    /// The process's command name (as shown in `/proc/<pid>/comm`).
+    pub name: String,
+    /// The file descriptors (in the process) that refer to the socket.
+    pub fds: Vec<RawFd>,
+    /// The process's current real user ID.
+    pub ruid: libc::uid_t,
+    /// The process's current effective user ID.
+    pub uid: libc::uid_t,
+    /// The process's current real group ID.
+    pub rgid: libc::gid_t,
+    /// The process's current effective group ID.
+    pub gid: libc::gid_t,
+    /// The process's current supplementary group list.
+    pub groups: Vec<libc::gid_t>,
+}
+
+/// Returns true if the error indicates that the process has exited or that we do not have
+/// permission to inspect it.
+fn is_skippable(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::ENOENT | libc::ESRCH | libc::EACCES | libc::EPERM)
+    )
+}
+
+fn read_holder(pid: libc::pid_t, fds: Vec<RawFd>) -> io::Result<SocketHolder> {
+    let status = procfs::read_proc_string(pid, "status")?;
+    let uids = procfs::parse_status_ids(procfs::status_field(&status, "Uid")?)?;
+    let gids = procfs::parse_status_ids(procfs::status_field(&status, "Gid")?)?;
+
+    let groups = procfs::status_field(&status, "Groups")?
+        .split_whitespace()
+        .map(procfs::parse_proc_int)
+        .collect::<io::Result<Vec<libc::gid_t>>>()?;
+
+    Ok(SocketHolder {
+        pid,
+        name: procfs::status_field(&status, "Name")?.to_string(),
+        fds,
+        ruid: uids[0],
+        uid: uids[1],
+        rgid: gids[0],
+        gid: gids[1],
+        groups,
+    })
+}
+
+/// List the socket file descriptors that the given process has open, as `(fd, inode)` pairs.
+fn socket_fds(pid: libc::pid_t) -> io::Result<Vec<(RawFd, u32)>> {
+    let mut fds = Vec::new();
+
+    for entry in std::fs::read_dir(procfs::proc_path(pid, "fd"))? {
+        let entry = entry?;
+
+        let fd = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+
+        let link = match std::fs::read_link(entry.path()) {
+            Ok(link) => link,
+            // The file descriptor may have been closed
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+            Err(e) => return Err(e),
+        };
+
+        if let Some(inode) = link
+            .to_str()
+            .and_then(|s| s.strip_prefix("socket:["))
+            .and_then(|s| s.strip_suffix(']'))
+            .and_then(|s| s.parse().ok())
+        {
+            fds.push((fd, inode));
+        }
+    }
+
+    fds.sort_unstable();
+    Ok(fds)
+}
+
+/// Scan `/proc/<pid>/fd` for every process, and find the holders of all sockets whose inode
+/// numbers satisfy the given predicate.
+fn scan_socket_holders<F: FnMut(u32) -> bool>(
+    mut pred: F,
+) -> io::Result<BTreeMap<u32, Vec<SocketHolder>>> {
+    let mut holders: BTreeMap<u32, Vec<SocketHolder>> = BTreeMap::new();
+
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+
+        let pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        let res = socket_fds(pid).and_then(|fds| {
+            let mut by_inode: BTreeMap<u32, Vec<RawFd>> = BTreeMap::new();
+            for (fd, inode) in fds {
+                if pred(inode) {
+                    by_inode.entry(inode).or_default().push(fd);
+                }
+            }
+
+            if by_inode.is_empty() {
+                return Ok(());
+            }
+
+            let holder = read_holder(pid, Vec::new())?;
+            for (inode, fds) in by_inode {
+                holders.entry(inode).or_default().push(SocketHolder {
+                    fds,
+                    ..holder.clone()
+                });
+            }
+
+            Ok(())
+        });
+
+        match res {
+            Ok(()) => (),
+            Err(e) if is_skippable(&e) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    for list in holders.values_mut() {
+        list.sort_unstable_by_key(|holder| holder.pid);
+    }
+
+    Ok(holders)
+}
+
+/// Find all of the processes that currently have the socket with the given inode number open,
+/// along with their current credentials.
+///
+/// This scans `/proc/<pid>/fd` for every process. Processes that we do not have permission to
+/// inspect (usually, processes owned by other users if we are not running as root) are silently
+/// skipped, so the results may be incomplete if this is not run with sufficient privileges.
+pub fn find_socket_holders(inode: u32) -> io::Result<Vec<SocketHolder>> {
+    Ok(scan_socket_holders(|i| i == inode)?
+        .remove(&inode)
+        .unwrap_or_default())
+}
+
+/// Find the processes that have each socket on the system open, along with their current
+/// credentials.
+///
+/// The returned map is keyed by socket inode number. This is much faster than calling
+/// [`find_socket_holders()`] for each socket, since `/proc` only has to be scanned once. The same
+/// caveats apply, and note that the map includes all sockets, not just Unix sockets.
+///
+/// [`find_socket_holders()`]: ./fn.find_socket_holders.html
+pub fn list_socket_holders() -> io::Result<BTreeMap<u32, Vec<SocketHolder>>> {
+    scan_socket_holders(|_| true)
+}
+
+/// Find all of the processes that currently have the peer of the given socket open, along with
+/// their current credentials.
+///
+/// The peer is located with [`get_unix_socket_info()`], and then [`find_socket_holders()`] is
+/// used to find the processes that have it open (see that function for caveats). If the socket is
+/// not connected, this fails with `ENOTCONN`.
+///
+/// Unlike [`get_peer_pid_ids()`], this reflects the current state of the system: if the process
+/// that created the peer socket has since passed it to another process, or changed its
+/// credentials, that will be visible here. However, the list may change as soon as this function
+/// returns.
+///
+/// [`get_unix_socket_info()`]: ./fn.get_unix_socket_info.html
+/// [`find_socket_holders()`]: ./fn.find_socket_holders.html
+/// [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+pub fn current_peer_holders(sock: &UnixStream) -> io::Result<Vec<SocketHolder>> {
+    let info = get_unix_socket_info(socket_inode(sock)?)?;
+
+    match info.peer_inode {
+        Some(peer_inode) => find_socket_holders(peer_inode),
+        None => Err(io::Error::from_raw_os_error(libc::ENOTCONN)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+
+    fn find(sockets: &[UnixSocketInfo], inode: u32) -> &UnixSocketInfo {
+        sockets.iter().find(|s| s.inode == inode).unwrap()
+    }
+
+    #[test]
+    fn test_list_unix_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.write_all(b"hello").unwrap();
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let dgram = UnixDatagram::unbound().unwrap();
+
+        let listener_ino = socket_inode(&listener).unwrap();
+        let client_ino = socket_inode(&client).unwrap();
+        let server_ino = socket_inode(&server).unwrap();
+        let a_ino = socket_inode(&a).unwrap();
+        let b_ino = socket_inode(&b).unwrap();
+        let dgram_ino = socket_inode(&dgram).unwrap();
+
+        let sockets = list_unix_sockets().unwrap();
+        let uid = unsafe { libc::geteuid() };
+
+        let info = find(&sockets, listener_ino);
+        assert_eq!(info.sock_type, UnixSocketType::Stream);
+        assert_eq!(info.state, UnixSocketState::Listening);
+        assert_eq!(info.name, Some(UnixSocketName::Path(path.clone())));
+        assert_eq!(info.peer_inode, None);
+        assert_eq!(info.uid, Some(uid));
+
+        let st = std::fs::metadata(&path).unwrap();
+        let (dev, ino) = info.vfs.unwrap();
+        assert_eq!(dev, st.dev());
+        assert_eq!(ino as u64, st.ino());
+
+        let info = find(&sockets, client_ino);
+        assert_eq!(info.state, UnixSocketState::Connected);
+        assert_eq!(info.name, None);
+        assert_eq!(info.peer_inode, Some(server_ino));
+
+        let info = find(&sockets, server_ino);
+        assert_eq!(info.state, UnixSocketState::Connected);
+        assert_eq!(info.name, Some(UnixSocketName::Path(path.clone())));
+        assert_eq!(info.peer_inode, Some(client_ino));
+        assert_eq!(info.rqueue, Some(5));
+
+        assert_eq!(find(&sockets, a_ino).peer_inode, Some(b_ino));
+        assert_eq!(find(&sockets, b_ino).peer_inode, Some(a_ino));
+
+        let info = find(&sockets, dgram_ino);
+        assert_eq!(info.sock_type, UnixSocketType::Datagram);
+        assert_eq!(info.state, UnixSocketState::Unconnected);
+        assert_eq!(info.name, None);
+    }
+
+    #[test]
+    fn test_get_unix_socket_info() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let info = get_unix_socket_info(socket_inode(&a).unwrap()).unwrap();
+        assert_eq!(info.inode, socket_inode(&a).unwrap());
+        assert_eq!(info.peer_inode, Some(socket_inode(&b).unwrap()));
+        assert_eq!(info.uid, Some(unsafe { libc::geteuid() }));
+
+        // Regular files aren't sockets
+        let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        assert_eq!(
+            socket_inode(&file).unwrap_err().raw_os_error(),
+            Some(libc::ENOTSOCK)
+        );
+
+        drop(a);
+        drop(b);
+        let dgram = UnixDatagram::unbound().unwrap();
+        let ino = socket_inode(&dgram).unwrap();
+        drop(dgram);
+        assert_eq!(
+            get_unix_socket_info(ino).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    fn getgroups() -> Vec<libc::gid_t> {
+        let mut groups = vec![0; 65536];
+        let n = unsafe { libc::getgroups(groups.len() as libc::c_int, groups.as_mut_ptr()) };
+        assert!(n >= 0, "{:?}", io::Error::last_os_error());
+        groups.truncate(n as usize);
+        groups
+    }
+
+    #[test]
+    fn test_current_peer_holders() {
+        use std::process::{Command, Stdio};
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let pid = unsafe { libc::getpid() };
+
+        // Other threads in the test binary may briefly hold copies of the socket while spawning
+        // child processes, so don't assume that we're the only holder
+        let holders = current_peer_holders(&a).unwrap();
+        let ours = holders.iter().find(|holder| holder.pid == pid).unwrap();
+        assert_eq!(ours.fds, vec![b.as_raw_fd()]);
+        assert_eq!(ours.uid, unsafe { libc::geteuid() });
+        assert_eq!(ours.ruid, unsafe { libc::getuid() });
+        assert_eq!(ours.gid, unsafe { libc::getegid() });
+        assert_eq!(ours.rgid, unsafe { libc::getgid() });
+        assert_eq!(ours.groups, getgroups());
+        assert_eq!(
+            ours.name,
+            std::fs::read_to_string("/proc/self/comm")
+                .unwrap()
+                .trim_end()
+        );
+
+        let all_holders = list_socket_holders().unwrap();
+        assert!(all_holders[&socket_inode(&b).unwrap()].contains(ours));
+        assert!(all_holders[&socket_inode(&a).unwrap()]
+            .iter()
+            .any(|holder| holder.pid == pid && holder.fds == vec![a.as_raw_fd()]));
+
+        // Pass the peer to a child process, then close our copy
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .stdin(Stdio::from(OwnedFd::from(b)))
+            .spawn()
+            .unwrap();
+
+        let holders = current_peer_holders(&a).unwrap();
+        assert!(holders.iter().all(|holder| holder.pid != pid));
+        assert!(holders
+            .iter()
+            .any(|holder| holder.pid == child.id() as libc::pid_t
+                && holder.fds == vec![0]
+                && holder.name == "sleep"));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        // Once every copy of the peer has been closed, we are no longer connected
+        assert_eq!(
+            current_peer_holders(&a).unwrap_err().raw_os_error(),
+            Some(libc::ENOTCONN)
+        );
+    }
+
+    #[test]
+    fn test_current_peer_holders_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = std::os::unix::net::UnixListener::bind(dir.path().join("sock")).unwrap();
+
+        assert_eq!(
+            current_peer_holders(unsafe { &UnixStream::from_raw_fd(listener.into_raw_fd()) })
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOTCONN)
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            UnixSocketInfo::parse(&[0; 8]).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        let mut msg = [0; UNIX_DIAG_MSG_LEN];
+        msg[0] = libc::AF_INET as u8;
+        assert_eq!(
+            UnixSocketInfo::parse(&msg).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+}
@@ -0,0 +1,833 @@
+//! The `http_proxy` module implements an HTTP/1.1 reverse proxy that listens on a Unix socket and
+//! tells the backend who the client is (only with the `http-proxy` feature).
+//!
+//! For each request, [`HttpProxy`] reads the client's credentials with
+//! [`get_peer_pid_ids()`](../fn.get_peer_pid_ids.html), removes any `X-Peer-*` headers (and
+//! trailers) sent by the client, and adds trusted `X-Peer-Uid`, `X-Peer-Gid`, `X-Peer-Pid`, and `X-Peer-User`
+//! headers (see [`peer_headers()`]) before forwarding the request to the backend over TCP or a
+//! Unix socket. Clients that are not allowed by the proxy's [`PeerPolicy`] get a
+//! `403 Forbidden` response.
+//!
+//! To keep the framing simple (and to make sure nothing reaches the backend without going through
+//! the header rewriting), each connection carries a single request: the proxy sends
+//! `Connection: close` in both directions.
+//!
+//! ```no_run
+//! use std::os::unix::net::UnixListener;
+//! use unix_cred::http_proxy::{HttpProxy, Upstream};
+//!
+//! let listener = UnixListener::bind("/run/app/http.sock").unwrap();
+//! HttpProxy::new(listener, Upstream::Tcp("127.0.0.1:8080".into())).serve().unwrap();
+//! ```
+//!
+//! [`HttpProxy`]: ./struct.HttpProxy.html
+//! [`peer_headers()`]: ./fn.peer_headers.html
+//! [`PeerPolicy`]: ../policy/enum.PeerPolicy.html
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::policy::PeerPolicy;
+use crate::util::ConnectionLimit;
+
+/// The default maximum number of connections that are handled at once.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+/// How long to wait for the client to send (each part of) its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The maximum size of a request or response head (the start line and headers).
+const MAX_HEAD_LEN: u64 = 64 * 1024;
+
+/// Headers that only apply to a single connection, which are not forwarded.
+const HOP_BY_HOP: &[&str] = &["connection", "keep-alive", "proxy-connection", "upgrade"];
+
+/// Where the proxy forwards requests to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Upstream {
+    /// A TCP address (e.g. `127.0.0.1:8080`).
+    Tcp(String),
+    /// The path to a Unix socket.
+    Unix(PathBuf),
+}
+
+impl Upstream {
+    fn connect(&self) -> io::Result<Box<dyn Conn>> {
+        Ok(match self {
+            Self::Tcp(addr) => Box::new(TcpStream::connect(addr.as_str())?),
+            Self::Unix(path) => Box::new(UnixStream::connect(path)?),
+        })
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = std::convert::Infallible;
+
+    /// Parse an upstream address: `unix:<path>` or an absolute path for a Unix socket, or
+    /// anything else for a TCP address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(if let Some(path) = s.strip_prefix("unix:") {
+            Self::Unix(path.into())
+        } else if s.starts_with('/') {
+            Self::Unix(s.into())
+        } else {
+            Self::Tcp(s.into())
+        })
+    }
+}
+
+trait Conn: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Conn for T {}
+
+/// Check whether the given header name is one of the `X-Peer-*` headers that the proxy sets.
+///
+/// This is case-insensitive, and treats `_` like `-` (since some backends, like CGI-style
+/// frameworks, treat them the same).
+pub fn is_peer_header(name: &str) -> bool {
+    name.len() >= 7
+        && name.as_bytes()[..7]
+            .iter()
+            .map(|&b| {
+                if b == b'_' {
+                    b'-'
+                } else {
+                    b.to_ascii_lowercase()
+                }
+            })
+            .eq(b"x-peer-".iter().copied())
+}
+
+fn peer_headers_for(
+    pid: Option<libc::pid_t>,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+) -> Vec<(&'static str, String)> {
+    let mut headers = vec![
+        ("X-Peer-Uid", uid.to_string()),
+        ("X-Peer-Gid", gid.to_string()),
+    ];
+
+    if let Some(pid) = pid {
+        headers.push(("X-Peer-Pid", pid.to_string()));
+    }
+    if let Some(user) = crate::util::user_name(uid) {
+        headers.push(("X-Peer-User", user));
+    }
+
+    headers
+}
+
+/// Get the headers that the proxy adds to requests from the given socket's peer.
+///
+/// `X-Peer-Pid` is omitted if the PID is not available, and `X-Peer-User` is omitted if the UID
+/// has no user name.
+pub fn peer_headers(sock: &UnixStream) -> io::Result<Vec<(&'static str, String)>> {
+    let (pid, uid, gid) = crate::get_peer_pid_ids(sock)?;
+    Ok(peer_headers_for(pid, uid, gid))
+}
+
+#[inline]
+fn malformed() -> io::Error {
+    io::Error::from_raw_os_error(libc::EPROTO)
+}
+
+/// Check whether a line contains control characters other than HTAB (e.g. NUL or a bare CR),
+/// which backends may interpret differently (RFC 9112 section 2.2).
+#[inline]
+fn has_ctl(line: &[u8]) -> bool {
+    line.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7f)
+}
+
+/// Parse a header (or trailer) line, without the line terminator, into a name and a value.
+fn parse_field(line: &str) -> io::Result<(String, String)> {
+    let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+
+    // This also rejects obsolete line folding (continuation lines starting with whitespace)
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+        return Err(malformed());
+    }
+
+    Ok((name.into(), value.trim().into()))
+}
+
+/// The start line and headers of a request or response.
+#[derive(Debug, Default)]
+struct Head {
+    start: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// Read a head, returning `None` on EOF before the first byte.
+    fn read<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut reader = reader.take(MAX_HEAD_LEN);
+        let mut head: Option<Self> = None;
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            reader.read_until(b'\n', &mut line)?;
+
+            if line.is_empty() && head.is_none() {
+                return Ok(None);
+            } else if line.pop() != Some(b'\n') {
+                // EOF in the middle of the head, or the head is too long
+                return Err(malformed());
+            }
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if has_ctl(&line) {
+                return Err(malformed());
+            }
+
+            let line = std::str::from_utf8(&line).map_err(|_| malformed())?;
+
+            match &mut head {
+                None => {
+                    head = Some(Self {
+                        start: line.into(),
+                        headers: Vec::new(),
+                    })
+                }
+                Some(head) if line.is_empty() => return Ok(Some(std::mem::take(head))),
+                Some(head) => head.headers.push(parse_field(line)?),
+            }
+        }
+    }
+
+    fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Replace the value of the (first) `Content-Length` header with the given length, and remove
+    /// any duplicates.
+    fn set_content_length(&mut self, len: u64) {
+        let mut seen = false;
+        self.headers.retain_mut(|(name, value)| {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                true
+            } else if seen {
+                false
+            } else {
+                seen = true;
+                *value = len.to_string();
+                true
+            }
+        });
+    }
+
+    /// Remove hop-by-hop headers, and mark the connection to be closed.
+    fn set_close(&mut self) {
+        self.headers
+            .retain(|(name, _)| !HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop)));
+        self.headers.push(("Connection".into(), "close".into()));
+    }
+
+    fn write<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buf = format!("{}\r\n", self.start);
+        for (name, value) in self.headers.iter() {
+            buf.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        buf.push_str("\r\n");
+
+        writer.write_all(buf.as_bytes())
+    }
+}
+
+/// How the body of a request is delimited.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Body {
+    Length(u64),
+    Chunked,
+}
+
+impl Body {
+    fn from_request(head: &Head) -> io::Result<Self> {
+        let mut lengths = head.get_all("Content-Length");
+        let mut encodings = head.get_all("Transfer-Encoding");
+
+        match (lengths.next(), encodings.next()) {
+            (None, None) => Ok(Self::Length(0)),
+            (Some(len), None) => {
+                // Reject conflicting lengths, and anything but plain digits (e.g. "+5"), which
+                // could be interpreted differently by the backend
+                if lengths.any(|l| l != len)
+                    || len.is_empty()
+                    || !len.bytes().all(|b| b.is_ascii_digit())
+                {
+                    return Err(malformed());
+                }
+                len.parse().map(Self::Length).map_err(|_| malformed())
+            }
+            (None, Some(enc))
+                if encodings.next().is_none() && enc.eq_ignore_ascii_case("chunked") =>
+            {
+                Ok(Self::Chunked)
+            }
+            // Both Content-Length and Transfer-Encoding, or an unsupported encoding
+            _ => Err(malformed()),
+        }
+    }
+
+    fn copy<R: BufRead, W: Write + ?Sized>(self, reader: &mut R, writer: &mut W) -> io::Result<()> {
+        match self {
+            Self::Length(len) => copy_exact(reader, writer, len),
+            Self::Chunked => copy_chunked(reader, writer),
+        }
+    }
+}
+
+fn copy_exact<R: Read, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    len: u64,
+) -> io::Result<()> {
+    if io::copy(&mut reader.take(len), writer)? != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Read a line, including the line terminator.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    reader.take(MAX_HEAD_LEN).read_until(b'\n', &mut line)?;
+
+    match line.strip_suffix(b"\n") {
+        Some(content) if !has_ctl(content.strip_suffix(b"\r").unwrap_or(content)) => Ok(line),
+        _ => Err(malformed()),
+    }
+}
+
+fn copy_line<R: BufRead, W: Write + ?Sized>(reader: &mut R, writer: &mut W) -> io::Result<Vec<u8>> {
+    let line = read_line(reader)?;
+    writer.write_all(&line)?;
+    Ok(line)
+}
+
+/// Parse a chunk size line, returning the size and the extensions (if any, without the `;`).
+fn parse_chunk_size(line: &[u8]) -> Option<(u64, Option<&str>)> {
+    let line = std::str::from_utf8(line)
+        .ok()?
+        .trim_end_matches(['\r', '\n']);
+    let (size, ext) = match line.split_once(';') {
+        Some((size, ext)) => (size.trim_end_matches([' ', '\t']), Some(ext)),
+        None => (line, None),
+    };
+
+    // from_str_radix() accepts a sign, which the backend might not
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((u64::from_str_radix(size, 16).ok()?, ext))
+}
+
+#[inline]
+fn is_blank(line: &[u8]) -> bool {
+    line == b"\r\n" || line == b"\n"
+}
+
+fn copy_chunked<R: BufRead, W: Write + ?Sized>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    loop {
+        let line = read_line(reader)?;
+        let (size, ext) = parse_chunk_size(&line).ok_or_else(malformed)?;
+
+        // Re-encode the size, so the backend can't parse it differently (e.g. with leading zeros)
+        match ext {
+            Some(ext) => write!(writer, "{:x};{}\r\n", size, ext)?,
+            None => write!(writer, "{:x}\r\n", size)?,
+        }
+
+        if size == 0 {
+            // Trailers, then an empty line. Some backends merge trailers into the headers, so
+            // they're checked like headers and any X-Peer-* fields are dropped.
+            loop {
+                let line = read_line(reader)?;
+                if is_blank(&line) {
+                    return writer.write_all(b"\r\n");
+                }
+
+                let line = std::str::from_utf8(&line).map_err(|_| malformed())?;
+                let (name, value) = parse_field(line.trim_end_matches(['\r', '\n']))?;
+                if !is_peer_header(&name) {
+                    write!(writer, "{}: {}\r\n", name, value)?;
+                }
+            }
+        }
+
+        copy_exact(reader, writer, size)?;
+        if !is_blank(&copy_line(reader, writer)?) {
+            return Err(malformed());
+        }
+    }
+}
+
+fn send_error<W: Write + ?Sized>(writer: &mut W, status: u16, reason: &str) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        reason,
+        reason.len() + 1,
+        reason
+    )
+}
+
+/// Send a `403 Forbidden` response without waiting for the request.
+///
+/// Anything that the client has already sent is discarded first (up to a limit), since closing a
+/// socket with unread data makes the client's reads fail with `ECONNRESET`.
+fn reject(sock: &UnixStream) -> io::Result<()> {
+    send_error(&mut &*sock, 403, "Forbidden")?;
+
+    sock.set_nonblocking(true)?;
+    let mut buf = [0; 4096];
+    let mut remaining = MAX_HEAD_LEN;
+    while remaining > 0 {
+        match (&*sock).read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => remaining = remaining.saturating_sub(n as u64),
+        }
+    }
+
+    Ok(())
+}
+
+/// An HTTP reverse proxy that adds the client's credentials to each request.
+///
+/// See the [module-level documentation](./index.html) for more information.
+#[derive(Debug)]
+pub struct HttpProxy {
+    listener: UnixListener,
+    upstream: Upstream,
+    max_connections: usize,
+    policy: PeerPolicy,
+}
+
+impl HttpProxy {
+    /// Create a new proxy that accepts connections on the given listener and forwards requests
+    /// to the given upstream.
+    ///
+    /// By default, requests are accepted from any client that can connect to the socket, and at
+    /// most [`DEFAULT_MAX_CONNECTIONS`](./constant.DEFAULT_MAX_CONNECTIONS.html) connections are
+    /// handled at once.
+    pub fn new(listener: UnixListener, upstream: Upstream) -> Self {
+        Self {
+            listener,
+            upstream,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            policy: PeerPolicy::AllowAnyone,
+        }
+    }
+
+    /// Set the maximum number of connections that are handled at once.
+    ///
+    /// Once this many connections are being handled, no more connections are accepted until one
+    /// of them is closed. A limit of 0 is treated as 1.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Set the policy that clients must be allowed by. Requests from other clients get a
+    /// `403 Forbidden` response.
+    pub fn policy(mut self, policy: PeerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Get the listener that this proxy accepts connections on.
+    #[inline]
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    /// Handle a single request on the given connection, and then close it.
+    ///
+    /// Clients that are not allowed by the policy get a `403 Forbidden` response right away,
+    /// without reading the request. Malformed requests get a `400 Bad Request` response, and
+    /// failures to connect to the upstream (or invalid responses from it) get a
+    /// `502 Bad Gateway` response. The connection is closed if the client stops sending its
+    /// request for 60 seconds.
+    pub fn handle(&self, sock: UnixStream) -> io::Result<()> {
+        let (pid, uid, gid) = crate::get_peer_pid_ids(&sock)?;
+        if !self.policy.allows(uid, gid) {
+            return reject(&sock);
+        }
+
+        sock.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+        let mut reader = BufReader::new(&sock);
+        let mut writer = &sock;
+
+        let res = Head::read(&mut reader).and_then(|head| match head {
+            Some(head) => Body::from_request(&head).map(|body| Some((head, body))),
+            None => Ok(None),
+        });
+
+        let (mut head, body) = match res {
+            Ok(Some(req)) => req,
+            Ok(None) => return Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EPROTO) => {
+                return send_error(&mut writer, 400, "Bad Request")
+            }
+            Err(e) => return Err(e),
+        };
+
+        if !head.start.ends_with(" HTTP/1.1") && !head.start.ends_with(" HTTP/1.0") {
+            return send_error(&mut writer, 400, "Bad Request");
+        }
+
+        if let Body::Length(len) = body {
+            head.set_content_length(len);
+        }
+        head.headers.retain(|(name, _)| !is_peer_header(name));
+        head.set_close();
+        for (name, value) in peer_headers_for(pid, uid, gid) {
+            head.headers.push((name.into(), value));
+        }
+
+        let mut upstream = match self.upstream.connect() {
+            Ok(upstream) => upstream,
+            Err(_) => return send_error(&mut writer, 502, "Bad Gateway"),
+        };
+
+        head.write(&mut upstream)?;
+        body.copy(&mut reader, &mut upstream)?;
+        upstream.flush()?;
+
+        let mut upstream = BufReader::new(upstream);
+        let mut resp = match Head::read(&mut upstream) {
+            Ok(Some(resp)) if resp.start.starts_with("HTTP/1.") => resp,
+            Ok(_) => return send_error(&mut writer, 502, "Bad Gateway"),
+            Err(e) if e.raw_os_error() == Some(libc::EPROTO) => {
+                return send_error(&mut writer, 502, "Bad Gateway")
+            }
+            Err(e) => return Err(e),
+        };
+
+        resp.set_close();
+        resp.write(&mut writer)?;
+        io::copy(&mut upstream, &mut writer)?;
+
+        Ok(())
+    }
+
+    /// Accept connections forever, handling each one in a new thread (up to the
+    /// [maximum](#method.max_connections) at once).
+    pub fn serve(self) -> io::Result<()> {
+        let limit = ConnectionLimit::new(self.max_connections);
+        let proxy = Arc::new(self);
+
+        loop {
+            limit.wait();
+
+            let sock = match proxy.listener.accept() {
+                Ok((sock, _)) => sock,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let guard = limit.enter();
+            let proxy = proxy.clone();
+            std::thread::spawn(move || {
+                let _ = proxy.handle(sock);
+                drop(guard);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Shutdown;
+    use std::path::Path;
+
+    /// Start a backend that responds to each request with the request it received.
+    fn start_backend(dir: &Path) -> PathBuf {
+        let path = dir.join("backend");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        std::thread::spawn(move || {
+            for sock in listener.incoming() {
+                let sock = sock.unwrap();
+                let mut reader = BufReader::new(&sock);
+
+                let head = Head::read(&mut reader).unwrap().unwrap();
+                let mut req = Vec::new();
+                head.write(&mut req).unwrap();
+                Body::from_request(&head)
+                    .unwrap()
+                    .copy(&mut reader, &mut req)
+                    .unwrap();
+
+                write!(
+                    &sock,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nKeep-Alive: timeout=5\r\n\r\n",
+                    req.len()
+                )
+                .unwrap();
+                (&sock).write_all(&req).unwrap();
+            }
+        });
+
+        path
+    }
+
+    fn request(proxy: &HttpProxy, req: &[u8]) -> String {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(req).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        proxy.handle(server).unwrap();
+
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    fn proxy(dir: &Path, policy: PeerPolicy) -> HttpProxy {
+        let listener = UnixListener::bind(dir.join("proxy")).unwrap();
+        HttpProxy::new(listener, Upstream::Unix(start_backend(dir))).policy(policy)
+    }
+
+    #[test]
+    fn test_is_peer_header() {
+        for name in [
+            "X-Peer-Uid",
+            "x-peer-user",
+            "X_PEER_UID",
+            "X-Peer_Gid",
+            "x-peer-",
+        ] {
+            assert!(is_peer_header(name), "{}", name);
+        }
+        for name in ["X-Peer", "X-Peers-Uid", "Host", "X-Forwarded-For", ""] {
+            assert!(!is_peer_header(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_upstream_from_str() {
+        assert_eq!(
+            "unix:/run/app.sock".parse::<Upstream>().unwrap(),
+            Upstream::Unix("/run/app.sock".into())
+        );
+        assert_eq!(
+            "/run/app.sock".parse::<Upstream>().unwrap(),
+            Upstream::Unix("/run/app.sock".into())
+        );
+        assert_eq!(
+            "127.0.0.1:8080".parse::<Upstream>().unwrap(),
+            Upstream::Tcp("127.0.0.1:8080".into())
+        );
+    }
+
+    #[test]
+    fn test_proxy() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = proxy(dir.path(), PeerPolicy::SameUser);
+
+        let resp = request(
+            &proxy,
+            b"POST /path HTTP/1.1\r\nHost: x\r\nX-Peer-Uid: 12345\r\nx_peer_user: evil\r\n\
+              Connection: keep-alive\r\nContent-Length: 5\r\n\r\nhello\
+              GET /smuggled HTTP/1.1\r\nX-Peer-Uid: 12345\r\n\r\n",
+        );
+
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.ends_with("\r\nConnection: close"));
+        assert!(!head.contains("Keep-Alive"));
+
+        let (pid, uid, gid) = {
+            let (a, _b) = UnixStream::pair().unwrap();
+            crate::get_peer_pid_ids(&a).unwrap()
+        };
+        let mut expected = format!(
+            "POST /path HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nConnection: close\r\n\
+             X-Peer-Uid: {}\r\nX-Peer-Gid: {}\r\nX-Peer-Pid: {}\r\n",
+            uid,
+            gid,
+            pid.unwrap()
+        );
+        if let Some(user) = crate::util::user_name(uid) {
+            expected.push_str(&format!("X-Peer-User: {}\r\n", user));
+        }
+        expected.push_str("\r\nhello");
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_proxy_chunked() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = proxy(dir.path(), PeerPolicy::SameUser);
+
+        let resp = request(
+            &proxy,
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              05;ext=1\r\nhello\r\n00A\r\n0123456789\r\n0\r\nTrailer: x\r\n\r\nextra",
+        );
+        let body = resp.split_once("\r\n\r\n").unwrap().1;
+        assert!(body
+            .ends_with("\r\n\r\n5;ext=1\r\nhello\r\na\r\n0123456789\r\n0\r\nTrailer: x\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_proxy_chunked_trailers() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = proxy(dir.path(), PeerPolicy::SameUser);
+
+        // Forged identity trailers are removed
+        let resp = request(
+            &proxy,
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              0\r\nX-Peer-Uid: 0\r\nA: b\r\nx_peer_user:root\r\n\r\n",
+        );
+        let body = resp.split_once("\r\n\r\n").unwrap().1;
+        assert!(body.ends_with("\r\n\r\n0\r\nA: b\r\n\r\n"), "{:?}", body);
+    }
+
+    #[test]
+    fn test_proxy_content_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy = proxy(dir.path(), PeerPolicy::SameUser);
+
+        // The length is forwarded in a normalized form
+        let resp = request(
+            &proxy,
+            b"POST / HTTP/1.1\r\nContent-Length: 005\r\ncontent-length: 005\r\n\r\nhello",
+        );
+        let body = resp.split_once("\r\n\r\n").unwrap().1;
+        assert!(body.starts_with("POST / HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n"));
+        assert!(body.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_serve_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy");
+        let proxy = proxy(dir.path(), PeerPolicy::SameUser).max_connections(1);
+        std::thread::spawn(move || proxy.serve());
+
+        // An idle connection takes up the only slot
+        let first = UnixStream::connect(&path).unwrap();
+
+        let mut second = UnixStream::connect(&path).unwrap();
+        second
+            .set_read_timeout(Some(std::time::Duration::from_millis(100)))
+            .unwrap();
+        second.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(
+            second.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        drop(first);
+        second.set_read_timeout(None).unwrap();
+        let mut resp = String::new();
+        second.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{:?}", resp);
+    }
+
+    #[test]
+    fn test_serve_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy");
+        let proxy = proxy(dir.path(), PeerPolicy::Uids(Vec::new())).max_connections(1);
+        std::thread::spawn(move || proxy.serve());
+
+        // Rejected clients are answered without waiting for a request, so they can't hold on to
+        // the only slot
+        let mut first = UnixStream::connect(&path).unwrap();
+        let mut second = UnixStream::connect(&path).unwrap();
+        for sock in [&mut second, &mut first] {
+            let mut resp = String::new();
+            sock.read_to_string(&mut resp).unwrap();
+            assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{:?}", resp);
+        }
+    }
+
+    #[test]
+    fn test_copy_chunked_invalid() {
+        for body in [
+            &b"+5\r\nhello\r\n0\r\n\r\n"[..],
+            b"-0\r\n\r\n",
+            b" 5\r\nhello\r\n0\r\n\r\n",
+            b"0x5\r\nhello\r\n0\r\n\r\n",
+            b"\r\n",
+            b"5\rhello\r\n0\r\n\r\n",
+            b"5;a\0\r\nhello\r\n0\r\n\r\n",
+            b"10000000000000000\r\n",
+            b"0\r\nTrailer: \x01\r\n\r\n",
+            b"0\r\nTrailer\r\n\r\n",
+            b"0\r\n x: y\r\n\r\n",
+        ] {
+            let err = copy_chunked(&mut &body[..], &mut Vec::new()).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EPROTO), "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn test_proxy_errors() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let proxy = proxy(dir.path(), PeerPolicy::Uids(Vec::new()));
+        let resp = request(&proxy, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+
+        let proxy = proxy.policy(PeerPolicy::AllowAnyone);
+        for req in [
+            &b"GET / HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+            b"GET / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            b"GET / HTTP/1.1\r\nBad Header: x\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: b\r\n folded\r\n\r\n",
+            b"GET / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            b"GET / HTTP/1.1\r\nContent-Length: 0x5\r\n\r\nhello",
+            b"GET / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 05\r\n\r\nhello",
+            b"GET /\0 HTTP/1.1\r\n\r\n",
+            b"GET /\r HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: b\rX-Peer-Uid: 0\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: b\0\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: \x7f\r\n\r\n",
+            b"GET / HTTP/2.0\r\n\r\n",
+            b"GET / HTTP/1.1\r\n",
+        ] {
+            let resp = request(&proxy, req);
+            assert!(
+                resp.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{:?}",
+                req
+            );
+        }
+
+        // HTAB is allowed in header values
+        let resp = request(&proxy, b"GET / HTTP/1.1\r\nA: b\tc\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("\r\nA: b\tc\r\n"));
+
+        // Nothing at all
+        assert_eq!(request(&proxy, b""), "");
+
+        let proxy = HttpProxy::new(
+            UnixListener::bind(dir.path().join("proxy2")).unwrap(),
+            Upstream::Unix(dir.path().join("missing")),
+        );
+        let resp = request(&proxy, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+    }
+}
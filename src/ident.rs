@@ -0,0 +1,469 @@
+//! The `ident` module implements an [RFC 1413](https://tools.ietf.org/html/rfc1413) ident server
+//! for loopback TCP connections (Linux-specific, and only available with the `ident` feature).
+//!
+//! Each query names a TCP connection by its port on this host and its port on the querying host.
+//! The owner of the connection is looked up with
+//! [`get_tcp_loopback_socket_ids()`](../tcp/fn.get_tcp_loopback_socket_ids.html), so only
+//! connections over the loopback interface can be resolved; queries about any other connections
+//! are answered with `NO-USER` (or `UNKNOWN-ERROR`).
+//!
+//! The [`IdentMode`] controls how much is revealed about the owner: its user name, nothing at
+//! all, or a random token that can later be matched against the server's logs.
+//!
+//! [`IdentMode`]: ./enum.IdentMode.html
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::tcp;
+use crate::util::ConnectionLimit;
+
+/// The maximum length of a query line. RFC 1413 queries are tiny, so anything longer than this is
+/// garbage.
+const MAX_QUERY_LEN: u64 = 1000;
+
+/// How long to wait for a query before closing the connection.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The default limit on the number of concurrent connections.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+/// Controls what an [`IdentServer`] reveals about the owner of a connection.
+///
+/// [`IdentServer`]: ./struct.IdentServer.html
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub enum IdentMode {
+    /// Reply with the owner's user name (or its UID, if it has no name).
+    #[default]
+    User,
+    /// Reply with `HIDDEN-USER` for every connection that could be resolved.
+    HiddenUser,
+    /// Reply with a random token (with the operating system reported as `OTHER`). The token is
+    /// included in the [`IdentReply`], so it can be logged along with the real UID.
+    ///
+    /// [`IdentReply`]: ./struct.IdentReply.html
+    RandomToken,
+}
+
+/// An ident error, as sent to the client.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum IdentError {
+    /// The query was malformed, or a port was out of range.
+    InvalidPort,
+    /// The connection could not be found.
+    NoUser,
+    /// The connection was found, but the server is configured not to reveal its owner.
+    HiddenUser,
+    /// Some other error occurred.
+    UnknownError,
+}
+
+impl fmt::Display for IdentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidPort => "INVALID-PORT",
+            Self::NoUser => "NO-USER",
+            Self::HiddenUser => "HIDDEN-USER",
+            Self::UnknownError => "UNKNOWN-ERROR",
+        })
+    }
+}
+
+/// The owner of a connection, as reported to the client.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IdentUser {
+    /// The UID that owns the connection. This is never sent to the client.
+    pub uid: libc::uid_t,
+    /// The operating system name sent to the client (`UNIX`, or `OTHER` for random tokens).
+    pub os: &'static str,
+    /// The user ID sent to the client.
+    pub userid: String,
+}
+
+/// The reply to a single ident query.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct IdentReply {
+    /// The port on this host, as given in the query (or 0 if the query was malformed).
+    pub local_port: u16,
+    /// The port on the querying host, as given in the query (or 0 if the query was malformed).
+    pub remote_port: u16,
+    /// The owner of the connection, or an error.
+    ///
+    /// In [`IdentMode::HiddenUser`] mode, this is `Err(IdentError::HiddenUser)` even though the
+    /// owner was found.
+    ///
+    /// [`IdentMode::HiddenUser`]: ./enum.IdentMode.html#variant.HiddenUser
+    pub result: Result<IdentUser, IdentError>,
+}
+
+/// Formats the reply as sent to the client (without the trailing CRLF).
+impl fmt::Display for IdentReply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {} : ", self.local_port, self.remote_port)?;
+
+        match &self.result {
+            Ok(user) => write!(f, "USERID : {} : {}", user.os, user.userid),
+            Err(err) => write!(f, "ERROR : {}", err),
+        }
+    }
+}
+
+/// Parse an ident query of the form `<port-on-server> , <port-on-client>`.
+///
+/// Returns `None` if the query is malformed or either port is 0.
+pub fn parse_query(query: &str) -> Option<(u16, u16)> {
+    let (local, remote) = query.split_once(',')?;
+
+    let local: u16 = local.trim().parse().ok()?;
+    let remote: u16 = remote.trim().parse().ok()?;
+
+    if local == 0 || remote == 0 {
+        return None;
+    }
+
+    Some((local, remote))
+}
+
+/// Answer a single ident query.
+///
+/// `local` and `remote` are the addresses of the ident connection itself (i.e. this host's
+/// address and the querying host's address); the connection named by the query is assumed to be
+/// between the same two addresses, as RFC 1413 requires.
+pub fn answer_query(query: &str, local: IpAddr, remote: IpAddr, mode: IdentMode) -> IdentReply {
+    let (local_port, remote_port) = match parse_query(query) {
+        Some(ports) => ports,
+        None => {
+            return IdentReply {
+                local_port: 0,
+                remote_port: 0,
+                result: Err(IdentError::InvalidPort),
+            }
+        }
+    };
+
+    let result = match tcp::get_tcp_loopback_socket_ids(
+        SocketAddr::new(local, local_port),
+        SocketAddr::new(remote, remote_port),
+    ) {
+        Ok(ids) => match mode {
+            IdentMode::User => Ok(IdentUser {
+                uid: ids.uid,
+                os: "UNIX",
+                userid: crate::util::user_name(ids.uid).unwrap_or_else(|| ids.uid.to_string()),
+            }),
+            IdentMode::HiddenUser => Err(IdentError::HiddenUser),
+            IdentMode::RandomToken => match crate::util::random_hex(8) {
+                Ok(token) => Ok(IdentUser {
+                    uid: ids.uid,
+                    os: "OTHER",
+                    userid: token,
+                }),
+                Err(_) => Err(IdentError::UnknownError),
+            },
+        },
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENOENT) | Some(libc::EADDRNOTAVAIL)
+            ) =>
+        {
+            Err(IdentError::NoUser)
+        }
+        Err(_) => Err(IdentError::UnknownError),
+    };
+
+    IdentReply {
+        local_port,
+        remote_port,
+        result,
+    }
+}
+
+type ReplyCallback = dyn Fn(SocketAddr, &IdentReply) + Send + Sync;
+
+/// An ident server.
+///
+/// ```no_run
+/// use std::net::TcpListener;
+/// use unix_cred::ident::{IdentMode, IdentServer};
+///
+/// let listener = TcpListener::bind("127.0.0.1:113").unwrap();
+/// IdentServer::new(listener, IdentMode::User)
+///     .on_reply(|addr, reply| eprintln!("{}: {}", addr, reply))
+///     .serve()
+///     .unwrap();
+/// ```
+pub struct IdentServer {
+    listener: TcpListener,
+    mode: IdentMode,
+    max_connections: usize,
+    on_reply: Option<Arc<ReplyCallback>>,
+}
+
+impl IdentServer {
+    /// Create a new ident server that accepts connections on the given listener.
+    ///
+    /// By default, at most [`DEFAULT_MAX_CONNECTIONS`](./constant.DEFAULT_MAX_CONNECTIONS.html)
+    /// connections are handled at once.
+    pub fn new(listener: TcpListener, mode: IdentMode) -> Self {
+        Self {
+            listener,
+            mode,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            on_reply: None,
+        }
+    }
+
+    /// Set the maximum number of connections that are handled at once.
+    ///
+    /// Once this many connections are open, no more are accepted until one of them is closed. A
+    /// limit of 0 is treated as 1.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Set a callback to be called with the address of the client and the reply to each query
+    /// (e.g. for logging).
+    pub fn on_reply<F: Fn(SocketAddr, &IdentReply) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.on_reply = Some(Arc::new(callback));
+        self
+    }
+
+    /// Get the listener that this server accepts connections on.
+    #[inline]
+    pub fn listener(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// Answer queries on a single connection until the client closes it.
+    pub fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        handle_connection(stream, self.mode, self.on_reply.as_deref())
+    }
+
+    /// Accept connections forever, handling each one in a new thread.
+    pub fn serve(self) -> io::Result<()> {
+        let limit = ConnectionLimit::new(self.max_connections);
+
+        loop {
+            limit.wait();
+
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let guard = limit.enter();
+            let mode = self.mode;
+            let on_reply = self.on_reply.clone();
+            std::thread::spawn(move || {
+                let _guard = guard;
+                handle_connection(stream, mode, on_reply.as_deref())
+            });
+        }
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    mode: IdentMode,
+    on_reply: Option<&ReplyCallback>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+
+    let local = stream.local_addr()?;
+    let remote = stream.peer_addr()?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        (&mut reader)
+            .take(MAX_QUERY_LEN)
+            .read_until(b'\n', &mut line)?;
+        if line.last() != Some(&b'\n') {
+            // EOF, or an overly long line
+            return Ok(());
+        }
+
+        let reply = answer_query(
+            &String::from_utf8_lossy(&line),
+            local.ip(),
+            remote.ip(),
+            mode,
+        );
+        if let Some(on_reply) = on_reply {
+            on_reply(remote, &reply);
+        }
+
+        (&stream).write_all(format!("{}\r\n", reply).as_bytes())?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse_query("6191, 23\r\n"), Some((6191, 23)));
+        assert_eq!(parse_query("6191,23"), Some((6191, 23)));
+        assert_eq!(parse_query("  6191 ,   23  "), Some((6191, 23)));
+
+        assert_eq!(parse_query(""), None);
+        assert_eq!(parse_query("6191"), None);
+        assert_eq!(parse_query("6191, 0"), None);
+        assert_eq!(parse_query("6191, 65536"), None);
+        assert_eq!(parse_query("a, b"), None);
+    }
+
+    #[test]
+    fn test_reply_display() {
+        let reply = IdentReply {
+            local_port: 6193,
+            remote_port: 23,
+            result: Ok(IdentUser {
+                uid: 1000,
+                os: "UNIX",
+                userid: "stjohns".into(),
+            }),
+        };
+        assert_eq!(reply.to_string(), "6193, 23 : USERID : UNIX : stjohns");
+
+        let reply = IdentReply {
+            local_port: 6195,
+            remote_port: 26,
+            result: Err(IdentError::NoUser),
+        };
+        assert_eq!(reply.to_string(), "6195, 26 : ERROR : NO-USER");
+    }
+
+    #[test]
+    fn test_answer_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_server, _) = listener.accept().unwrap();
+
+        // The "query" is about the client's side of the connection
+        let query = format!(
+            "{}, {}",
+            client.local_addr().unwrap().port(),
+            client.peer_addr().unwrap().port()
+        );
+        let local = client.local_addr().unwrap().ip();
+        let remote = client.peer_addr().unwrap().ip();
+        let euid = unsafe { libc::geteuid() };
+
+        let reply = answer_query(&query, local, remote, IdentMode::User);
+        let user = reply.result.unwrap();
+        assert_eq!(user.uid, euid);
+        assert_eq!(user.os, "UNIX");
+        assert_eq!(
+            user.userid,
+            crate::util::user_name(euid).unwrap_or_else(|| euid.to_string())
+        );
+
+        let reply = answer_query(&query, local, remote, IdentMode::HiddenUser);
+        assert_eq!(reply.result, Err(IdentError::HiddenUser));
+
+        let reply = answer_query(&query, local, remote, IdentMode::RandomToken);
+        let user = reply.result.unwrap();
+        assert_eq!(user.uid, euid);
+        assert_eq!(user.os, "OTHER");
+        assert_eq!(user.userid.len(), 16);
+
+        let reply = answer_query("1, 1", local, remote, IdentMode::User);
+        assert_eq!(reply.result, Err(IdentError::NoUser));
+
+        // Once the client closes its end, its socket is left without an owner (and reported as
+        // owned by root), so the query must not be answered
+        drop(client);
+        std::thread::sleep(Duration::from_millis(50));
+        let reply = answer_query(&query, local, remote, IdentMode::User);
+        assert_eq!(reply.result, Err(IdentError::NoUser));
+
+        let reply = answer_query("garbage", local, remote, IdentMode::User);
+        assert_eq!(reply.to_string(), "0, 0 : ERROR : INVALID-PORT");
+    }
+
+    #[test]
+    fn test_server_limit() {
+        let server = IdentServer::new(TcpListener::bind("127.0.0.1:0").unwrap(), IdentMode::User)
+            .max_connections(1);
+        let addr = server.listener().local_addr().unwrap();
+        std::thread::spawn(move || server.serve());
+
+        let query = |client: &TcpStream| {
+            let query = format!(
+                "{}, {}\r\n",
+                client.local_addr().unwrap().port(),
+                client.peer_addr().unwrap().port()
+            );
+            (&*client).write_all(query.as_bytes()).unwrap();
+        };
+
+        let first = TcpStream::connect(addr).unwrap();
+        query(&first);
+        let mut reply = String::new();
+        BufReader::new(&first).read_line(&mut reply).unwrap();
+        assert!(reply.contains("USERID"), "{:?}", reply);
+
+        // The second connection isn't accepted until the first one is closed
+        let second = TcpStream::connect(addr).unwrap();
+        second
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        query(&second);
+        let mut buf = [0; 1];
+        assert_eq!(
+            (&second).read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        drop(first);
+        second.set_read_timeout(None).unwrap();
+        let mut reply = String::new();
+        BufReader::new(&second).read_line(&mut reply).unwrap();
+        assert!(reply.contains("USERID"), "{:?}", reply);
+    }
+
+    #[test]
+    fn test_server() {
+        let server = IdentServer::new(
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            IdentMode::HiddenUser,
+        );
+        let client = TcpStream::connect(server.listener().local_addr().unwrap()).unwrap();
+        let (stream, _) = server.listener().accept().unwrap();
+        let thread = std::thread::spawn(move || server.handle(stream));
+
+        let query = format!(
+            "{}, {}\r\n",
+            client.local_addr().unwrap().port(),
+            client.peer_addr().unwrap().port()
+        );
+        (&client).write_all(query.as_bytes()).unwrap();
+
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert_eq!(
+            reply,
+            format!(
+                "{}, {} : ERROR : HIDDEN-USER\r\n",
+                client.local_addr().unwrap().port(),
+                client.peer_addr().unwrap().port()
+            )
+        );
+
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        thread.join().unwrap().unwrap();
+    }
+}
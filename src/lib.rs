@@ -6,7 +6,8 @@
 //! # Stream vs. Datagram sockets
 //!
 //! Some platforms support reading peer credentials from datagram sockets using ancillary messages.
-//! Currently, `unix-cred` does not support this; only stream sockets are supported.
+//! The cross-platform interfaces only support stream sockets, but on Linux, the `scm` module
+//! supports sending and receiving `SCM_CREDENTIALS` messages.
 //!
 //! # Which credentials am I getting?
 //!
@@ -26,15 +27,111 @@
 //!
 //! `ucred` is not particularly useful; in most cases you should use `get_peer_ids()` or
 //! `get_peer_pid_ids()`, which are more cross-platform. However, `xucred` can be helpful since it
-//! provides access to the process's full supplementary group list.
+//! provides access to the process's full supplementary group list. (On Linux, `ucred` also
+//! provides `get_peer_groups()` for this.)
+//!
+//! On Linux, some additional modules provide information about the peer process that is not
+//! available from the socket itself (usually by looking it up in `/proc`):
+//!
+//! - `addr` represents the addresses of Unix sockets (paths and abstract names).
+//! - `abstract_ns` binds and connects to abstract-namespace sockets, checking peer credentials
+//!   (since abstract sockets have no filesystem permissions).
+//! - `activation` takes over sockets passed by systemd-style socket activation, and accepts
+//!   connections only from allowed peers.
+//! - `ancestry` walks the peer's parent chain (e.g. to check that it was spawned by a particular
+//!   process).
+//! - `apparmor` parses the peer's AppArmor label into profiles and a confinement mode.
+//! - `audit` retrieves the peer's audit login UID and session ID.
+//! - `diag` lists all of the Unix sockets on the system (not just the current process's) using
+//!   `NETLINK_SOCK_DIAG`.
+//! - `http_proxy` (only with the `http-proxy` feature) implements an HTTP reverse proxy on a Unix
+//!   socket that passes each client's credentials to the backend in `X-Peer-*` headers.
+//! - `ident` (only with the `ident` feature) implements an RFC 1413 ident server for loopback TCP
+//!   connections, using `tcp`.
+//! - `lsm` retrieves the peer's LSM labels (SELinux, AppArmor, Smack, etc.).
+//! - `policy` decides which peers are allowed, based on their credentials (shared by the servers
+//!   in this crate).
+//! - `proxy` (only with the `proxy` feature) forwards connections to other Unix sockets, choosing
+//!   the upstream (or rejecting the client) based on the client's credentials. It can also relay
+//!   datagrams with the original sender's `SCM_CREDENTIALS`.
+//! - `sasl` implements the D-Bus-style SASL `EXTERNAL` handshake, checking the client's claimed UID
+//!   against its credentials.
+//! - `scm` sends and receives credentials as `SCM_CREDENTIALS` ancillary messages.
+//! - `secure_bind` creates listening sockets with controlled ownership and permissions (e.g. in
+//!   `$XDG_RUNTIME_DIR`).
+//! - `selinux` parses the peer's SELinux context and matches it against patterns.
+//! - `seqpacket` provides `SOCK_SEQPACKET` sockets, which the standard library does not support.
+//! - `tcp` looks up the owner of the other end of a loopback TCP connection (which has no
+//!   equivalent of `SO_PEERCRED`).
+//! - `terminal` retrieves the peer's session, process group, and controlling terminal.
+//! - `tracer` detects whether the peer is being traced, and by whom.
+//! - `ucspi` (only with the `ucspi` feature) runs a program for each connection, passing the
+//!   peer's credentials in the environment like `ucspi-unix`'s `unixserver`.
+//! - `varlink` (only with the `varlink` feature) implements a minimal varlink server that passes
+//!   the caller's credentials to each method.
+//! - `verify` lets clients check that they are connected to the server they expect, and that the
+//!   path leading to its socket is safe.
 
 use std::io;
 use std::os::unix::net::UnixStream;
 use std::os::unix::prelude::*;
 
 mod constants;
+#[cfg(target_os = "linux")]
+mod netlink;
+#[cfg(target_os = "linux")]
+mod procfs;
+#[cfg(target_os = "linux")]
+mod sock;
 mod util;
 
+#[cfg(target_os = "linux")]
+pub mod abstract_ns;
+#[cfg(target_os = "linux")]
+pub mod activation;
+#[cfg(target_os = "linux")]
+pub mod addr;
+#[cfg(target_os = "linux")]
+pub mod ancestry;
+#[cfg(target_os = "linux")]
+pub mod apparmor;
+#[cfg(target_os = "linux")]
+pub mod audit;
+#[cfg(target_os = "linux")]
+pub mod diag;
+#[cfg(all(target_os = "linux", feature = "http-proxy"))]
+pub mod http_proxy;
+#[cfg(all(target_os = "linux", feature = "ident"))]
+pub mod ident;
+#[cfg(target_os = "linux")]
+pub mod lsm;
+#[cfg(target_os = "linux")]
+pub mod policy;
+#[cfg(all(target_os = "linux", feature = "proxy"))]
+pub mod proxy;
+#[cfg(target_os = "linux")]
+pub mod sasl;
+#[cfg(target_os = "linux")]
+pub mod scm;
+#[cfg(target_os = "linux")]
+pub mod secure_bind;
+#[cfg(target_os = "linux")]
+pub mod selinux;
+#[cfg(target_os = "linux")]
+pub mod seqpacket;
+#[cfg(target_os = "linux")]
+pub mod tcp;
+#[cfg(target_os = "linux")]
+pub mod terminal;
+#[cfg(target_os = "linux")]
+pub mod tracer;
+#[cfg(all(target_os = "linux", feature = "ucspi"))]
+pub mod ucspi;
+#[cfg(all(target_os = "linux", feature = "varlink"))]
+pub mod varlink;
+#[cfg(target_os = "linux")]
+pub mod verify;
+
 #[cfg(any(target_os = "linux", target_os = "openbsd", target_os = "netbsd"))]
 pub mod ucred;
 #[cfg(any(
@@ -45,6 +142,76 @@ pub mod ucred;
 ))]
 pub mod xucred;
 
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for std::os::unix::net::UnixStream {}
+}
+
+/// A connected Unix socket whose peer's credentials can be retrieved.
+///
+/// This is implemented for `UnixStream`, and on Linux, for [`seqpacket::UnixSeqpacket`]. It is
+/// sealed, so it cannot be implemented outside of this crate.
+///
+/// The methods are equivalent to the free functions of the same names (e.g.
+/// [`peer_ids()`](#method.peer_ids) is equivalent to [`get_peer_ids()`]), which only accept
+/// `UnixStream`s. They are mainly useful for code that is generic over the socket type.
+///
+/// [`seqpacket::UnixSeqpacket`]: ./seqpacket/struct.UnixSeqpacket.html
+/// [`get_peer_ids()`]: ./fn.get_peer_ids.html
+pub trait PeerSocket: AsRawFd + private::Sealed {
+    /// Get the PID of this socket's peer (see [`get_peerpid()`](./fn.get_peerpid.html)).
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    #[inline]
+    fn peerpid(&self) -> io::Result<libc::pid_t> {
+        unsafe { get_peerpid_raw(self.as_raw_fd()) }
+    }
+
+    /// Get the UID and GID of this socket's peer (see [`get_peer_ids()`](./fn.get_peer_ids.html)).
+    #[inline]
+    fn peer_ids(&self) -> io::Result<(libc::uid_t, libc::gid_t)> {
+        unsafe { get_peer_ids_raw(self.as_raw_fd()) }
+    }
+
+    /// Get the PID, UID, and GID of this socket's peer (see
+    /// [`get_peer_pid_ids()`](./fn.get_peer_pid_ids.html)).
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "ios",
+    ))]
+    #[inline]
+    fn peer_pid_ids(&self) -> io::Result<(Option<libc::pid_t>, libc::uid_t, libc::gid_t)> {
+        unsafe { get_peer_pid_ids_raw(self.as_raw_fd()) }
+    }
+
+    /// Get the credentials of this socket's peer (see
+    /// [`ucred::get_ucred()`](./ucred/fn.get_ucred.html)).
+    #[cfg(any(target_os = "linux", target_os = "openbsd", target_os = "netbsd"))]
+    #[inline]
+    fn peer_ucred(&self) -> io::Result<ucred::Ucred> {
+        unsafe { ucred::get_ucred_raw(self.as_raw_fd()) }
+    }
+
+    /// Get the credentials of this socket's peer (see
+    /// [`xucred::get_xucred()`](./xucred/fn.get_xucred.html)).
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    #[inline]
+    fn peer_xucred(&self) -> io::Result<xucred::Xucred> {
+        unsafe { xucred::get_xucred_raw(self.as_raw_fd()) }
+    }
+}
+
+impl PeerSocket for UnixStream {}
+
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 #[inline]
 unsafe fn get_peerpid_raw(sockfd: RawFd) -> io::Result<libc::pid_t> {
@@ -162,6 +329,16 @@ pub fn get_peer_pid_ids(
 mod tests {
     use super::*;
 
+    use std::mem::ManuallyDrop;
+
+    /// Get a `UnixStream` wrapping an invalid file descriptor.
+    ///
+    /// It must never be dropped: closing a descriptor that isn't open is an I/O safety violation,
+    /// which aborts the process in debug builds (as of Rust 1.80).
+    unsafe fn bad_fd_stream() -> ManuallyDrop<UnixStream> {
+        ManuallyDrop::new(UnixStream::from_raw_fd(libc::c_int::MAX))
+    }
+
     #[cfg(any(target_os = "macos", target_os = "ios"))]
     #[test]
     fn test_get_peerpid() {
@@ -178,7 +355,7 @@ mod tests {
     #[test]
     fn test_get_peerpid_bad_fd() {
         assert_eq!(
-            get_peerpid(unsafe { &UnixStream::from_raw_fd(libc::c_int::MAX) })
+            get_peerpid(unsafe { &bad_fd_stream() })
                 .unwrap_err()
                 .raw_os_error(),
             Some(libc::EBADF),
@@ -225,7 +402,7 @@ mod tests {
     #[test]
     fn test_get_peer_ids_bad_fd() {
         assert_eq!(
-            get_peer_ids(unsafe { &UnixStream::from_raw_fd(libc::c_int::MAX) })
+            get_peer_ids(unsafe { &bad_fd_stream() })
                 .unwrap_err()
                 .raw_os_error(),
             Some(libc::EBADF),
@@ -274,7 +451,7 @@ mod tests {
     #[test]
     fn test_get_peer_pid_ids_bad_fd() {
         assert_eq!(
-            get_peer_pid_ids(unsafe { &UnixStream::from_raw_fd(libc::c_int::MAX) })
+            get_peer_pid_ids(unsafe { &bad_fd_stream() })
                 .unwrap_err()
                 .raw_os_error(),
             Some(libc::EBADF),
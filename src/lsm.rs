@@ -0,0 +1,216 @@
+//! The `lsm` module provides access to the Linux Security Module (LSM) labels of a socket's peer.
+//!
+//! [`get_peersec()`] retrieves the peer's label using `SO_PEERSEC`. However, when multiple LSMs are
+//! stacked, this single string can be ambiguous, so [`get_peer_lsm_labels()`] retrieves the label
+//! for each LSM separately from `/proc/<pid>/attr/<lsm>/current`. Since it looks up the peer by
+//! PID, it is subject to the same PID reuse caveats as [`get_peer_pid_ids()`].
+//!
+//! [`get_peersec()`]: ./fn.get_peersec.html
+//! [`get_peer_lsm_labels()`]: ./fn.get_peer_lsm_labels.html
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+
+use crate::procfs;
+
+/// The LSMs whose labels are looked up by [`get_lsm_labels()`].
+///
+/// [`get_lsm_labels()`]: ./fn.get_lsm_labels.html
+pub const LSM_NAMES: &[&str] = &["selinux", "apparmor", "smack"];
+
+/// The key used by [`get_lsm_labels()`] for the label in `/proc/<pid>/attr/current` when the LSM
+/// that it belongs to cannot be identified.
+///
+/// [`get_lsm_labels()`]: ./fn.get_lsm_labels.html
+pub const LSM_UNKNOWN: &str = "current";
+
+fn decode_label(mut label: Vec<u8>) -> io::Result<String> {
+    // SELinux includes a trailing NUL; AppArmor includes a trailing newline
+    while matches!(label.last(), Some(b'\0') | Some(b'\n')) {
+        label.pop();
+    }
+
+    String::from_utf8(label).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+unsafe fn get_peersec_raw(sockfd: RawFd) -> io::Result<String> {
+    let mut buf = vec![0u8; 256];
+
+    loop {
+        let mut len = buf.len() as libc::socklen_t;
+
+        if libc::getsockopt(
+            sockfd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERSEC,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        ) < 0
+        {
+            let err = io::Error::last_os_error();
+
+            // If the buffer was too small, the kernel tells us how large it needs to be
+            if err.raw_os_error() == Some(libc::ERANGE) && len as usize > buf.len() {
+                buf.resize(len as usize, 0);
+                continue;
+            }
+
+            return Err(err);
+        }
+
+        buf.truncate(len as usize);
+        return decode_label(buf);
+    }
+}
+
+/// Get the security label of the given socket's peer using `SO_PEERSEC`.
+///
+/// If no LSM that supports `SO_PEERSEC` is active, this fails with `ENOPROTOOPT`.
+#[inline]
+pub fn get_peersec<S: crate::PeerSocket>(sock: &S) -> io::Result<String> {
+    unsafe { get_peersec_raw(sock.as_raw_fd()) }
+}
+
+fn read_label(pid: libc::pid_t, name: &str) -> io::Result<Option<String>> {
+    match std::fs::read(procfs::proc_path(pid, name)) {
+        Ok(label) => decode_label(label).map(Some),
+        // ENOENT means this kernel doesn't know about the LSM, and EINVAL means it isn't active
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT | libc::EINVAL)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Try to identify the LSM that provides `/proc/<pid>/attr/current`.
+fn identify_current_lsm() -> Option<&'static str> {
+    let lsms = std::fs::read_to_string("/sys/kernel/security/lsm").ok()?;
+
+    lsms.trim()
+        .split(',')
+        .find_map(|lsm| LSM_NAMES.iter().copied().find(|&name| name == lsm))
+}
+
+/// Get the LSM labels of the process with the given PID.
+///
+/// The returned map is keyed by LSM name, and it contains an entry for each of the LSMs in
+/// [`LSM_NAMES`] that is active.
+///
+/// On older kernels without per-LSM `attr` directories, this falls back to reading
+/// `/proc/<pid>/attr/current`. In that case, the label is keyed by the name of the LSM that
+/// provides it if it can be identified from `/sys/kernel/security/lsm`, or by [`LSM_UNKNOWN`] if it
+/// cannot.
+///
+/// [`LSM_NAMES`]: ./constant.LSM_NAMES.html
+/// [`LSM_UNKNOWN`]: ./constant.LSM_UNKNOWN.html
+pub fn get_lsm_labels(pid: libc::pid_t) -> io::Result<BTreeMap<String, String>> {
+    let mut labels = BTreeMap::new();
+
+    for &lsm in LSM_NAMES {
+        if let Some(label) = read_label(pid, &format!("attr/{}/current", lsm))? {
+            labels.insert(lsm.to_string(), label);
+        }
+    }
+
+    if labels.is_empty() {
+        if let Some(label) = read_label(pid, "attr/current")? {
+            let lsm = identify_current_lsm().unwrap_or(LSM_UNKNOWN);
+            labels.insert(lsm.to_string(), label);
+        }
+    }
+
+    Ok(labels)
+}
+
+/// Get the LSM labels of the given socket's peer.
+///
+/// See [`get_lsm_labels()`] for more details.
+///
+/// [`get_lsm_labels()`]: ./fn.get_lsm_labels.html
+#[inline]
+pub fn get_peer_lsm_labels(sock: &UnixStream) -> io::Result<BTreeMap<String, String>> {
+    let cred = unsafe { crate::ucred::get_ucred_raw(sock.as_raw_fd()) }?;
+    get_lsm_labels(cred.pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixDatagram;
+
+    fn read_self_label(name: &str) -> Option<String> {
+        let label = std::fs::read(format!("/proc/self/{}", name)).ok()?;
+        Some(decode_label(label).unwrap())
+    }
+
+    #[test]
+    fn test_decode_label() {
+        assert_eq!(
+            decode_label(b"system_u:system_r:init_t:s0\0".to_vec()).unwrap(),
+            "system_u:system_r:init_t:s0"
+        );
+        assert_eq!(
+            decode_label(b"unconfined\n".to_vec()).unwrap(),
+            "unconfined"
+        );
+        assert_eq!(decode_label(b"_".to_vec()).unwrap(), "_");
+        assert_eq!(decode_label(Vec::new()).unwrap(), "");
+
+        assert_eq!(
+            decode_label(b"\xff\0".to_vec()).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_get_peersec() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        for sock in [a, b].iter() {
+            match get_peersec(sock) {
+                Ok(label) => {
+                    let labels = get_peer_lsm_labels(sock).unwrap();
+                    assert!(labels.values().any(|l| *l == label), "{:?}", labels);
+                }
+                Err(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOPROTOOPT)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_peersec_error() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let sock = UnixDatagram::bind(dir.path().join("sock")).unwrap();
+
+        let eno = get_peersec(unsafe { &UnixStream::from_raw_fd(sock.into_raw_fd()) })
+            .unwrap_err()
+            .raw_os_error()
+            .unwrap();
+
+        assert!(matches!(
+            eno,
+            libc::ENOPROTOOPT | libc::ENOTCONN | libc::EINVAL
+        ));
+    }
+
+    #[test]
+    fn test_get_peer_lsm_labels() {
+        let (a, _b) = UnixStream::pair().unwrap();
+
+        let labels = get_peer_lsm_labels(&a).unwrap();
+        assert_eq!(labels, get_lsm_labels(unsafe { libc::getpid() }).unwrap());
+
+        for &lsm in LSM_NAMES {
+            if let Some(label) = read_self_label(&format!("attr/{}/current", lsm)) {
+                assert_eq!(labels.get(lsm), Some(&label));
+            }
+        }
+
+        if let Some(label) = read_self_label("attr/current") {
+            assert!(labels.values().any(|l| *l == label), "{:?}", labels);
+        }
+    }
+}
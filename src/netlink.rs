@@ -0,0 +1,193 @@
+//! A minimal `NETLINK_SOCK_DIAG` client, shared by the Unix and inet socket diagnostics code.
+
+use std::io;
+use std::os::unix::prelude::*;
+
+const NLMSG_HDRLEN: usize = 16;
+const NLA_HDRLEN: usize = 4;
+/// The type of an attribute, with the `NLA_F_NESTED` and `NLA_F_NET_BYTEORDER` flags masked out.
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const RECV_BUFSIZE: usize = 65536;
+
+#[inline]
+pub fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+#[inline]
+pub fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+#[inline]
+pub fn read_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+/// Iterate over the netlink attributes in `data`, yielding `(type, payload)` pairs.
+pub fn attrs(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = read_u16(data, 0).ok()? as usize;
+        let ty = read_u16(data, 2).ok()? & NLA_TYPE_MASK;
+
+        if len < NLA_HDRLEN || len > data.len() {
+            return None;
+        }
+
+        let payload = &data[NLA_HDRLEN..len];
+        data = &data[align(len).min(data.len())..];
+        Some((ty, payload))
+    })
+}
+
+/// Send a `SOCK_DIAG_BY_FAMILY` request with the given payload, and return the payloads of the
+/// response messages.
+///
+/// If `dump` is true, `NLM_F_DUMP` is set, and all messages up to `NLMSG_DONE` are collected.
+/// Otherwise, only the first response message is returned.
+pub fn sock_diag_request(req: &[u8], dump: bool) -> io::Result<Vec<Vec<u8>>> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_SOCK_DIAG,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut flags = libc::NLM_F_REQUEST as u16;
+    if dump {
+        flags |= libc::NLM_F_DUMP as u16;
+    }
+
+    let seq = 1u32;
+
+    let mut msg = Vec::with_capacity(NLMSG_HDRLEN + req.len());
+    msg.extend_from_slice(&((NLMSG_HDRLEN + req.len()) as u32).to_ne_bytes());
+    msg.extend_from_slice(&crate::constants::SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    msg.extend_from_slice(&flags.to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(req);
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+    if unsafe {
+        libc::sendto(
+            fd.as_raw_fd(),
+            msg.as_ptr() as *const libc::c_void,
+            msg.len(),
+            0,
+            &addr as *const _ as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buf = vec![0u8; RECV_BUFSIZE];
+    let mut responses = Vec::new();
+
+    loop {
+        let n = unsafe {
+            libc::recv(
+                fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        let mut data = &buf[..n as usize];
+
+        while data.len() >= NLMSG_HDRLEN {
+            let len = read_u32(data, 0)? as usize;
+            let ty = read_u16(data, 4)?;
+            let msg_seq = read_u32(data, 8)?;
+
+            if len < NLMSG_HDRLEN || len > data.len() {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+
+            let payload = &data[NLMSG_HDRLEN..len];
+            data = &data[align(len).min(data.len())..];
+
+            if msg_seq != seq {
+                continue;
+            }
+
+            match ty as libc::c_int {
+                libc::NLMSG_DONE => return Ok(responses),
+                libc::NLMSG_ERROR => {
+                    let errno = read_u32(payload, 0)? as i32;
+                    if errno != 0 {
+                        return Err(io::Error::from_raw_os_error(-errno));
+                    }
+                    return Ok(responses);
+                }
+                _ if ty == crate::constants::SOCK_DIAG_BY_FAMILY => {
+                    responses.push(payload.to_vec());
+                    if !dump {
+                        return Ok(responses);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attrs() {
+        let mut data = Vec::new();
+        // Attribute 1 with a 3-byte payload (padded to 4 bytes)
+        data.extend_from_slice(&7u16.to_ne_bytes());
+        data.extend_from_slice(&1u16.to_ne_bytes());
+        data.extend_from_slice(b"abc\0");
+        // Attribute 2 with the NLA_F_NESTED flag set and a 4-byte payload
+        data.extend_from_slice(&8u16.to_ne_bytes());
+        data.extend_from_slice(&(2u16 | 0x8000).to_ne_bytes());
+        data.extend_from_slice(&42u32.to_ne_bytes());
+        // Truncated attribute
+        data.extend_from_slice(&100u16.to_ne_bytes());
+        data.extend_from_slice(&3u16.to_ne_bytes());
+
+        let attrs: Vec<(u16, &[u8])> = attrs(&data).collect();
+        assert_eq!(attrs, vec![(1, &b"abc"[..]), (2, &42u32.to_ne_bytes()[..])]);
+    }
+
+    #[test]
+    fn test_read_int() {
+        let data = [1u8, 0, 0, 0, 2, 0];
+        assert_eq!(
+            read_u32(&data, 0).unwrap(),
+            u32::from_ne_bytes([1, 0, 0, 0])
+        );
+        assert_eq!(read_u16(&data, 4).unwrap(), u16::from_ne_bytes([2, 0]));
+        assert_eq!(
+            read_u32(&data, 4).unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+}
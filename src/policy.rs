@@ -0,0 +1,140 @@
+//! The `policy` module decides which peers are allowed to connect, based on their credentials.
+//!
+//! A [`PeerPolicy`] is shared by the servers in this crate ([`abstract_ns`], [`activation`],
+//! [`ucspi`], [`http_proxy`], and [`proxy`]), and by [`verify`] to check the server on the other
+//! end of a client's connection.
+//!
+//! ```
+//! use std::os::unix::net::UnixStream;
+//! use unix_cred::policy::PeerPolicy;
+//!
+//! let (a, _b) = UnixStream::pair().unwrap();
+//! let (uid, _gid) = PeerPolicy::SameUser.check(&a).unwrap();
+//! assert_eq!(uid, unsafe { libc::geteuid() });
+//!
+//! let policy = PeerPolicy::any_of(vec![0], vec![100]);
+//! assert!(policy.allows(0, 1000));
+//! assert!(!policy.allows(1000, 1000));
+//! ```
+//!
+//! [`PeerPolicy`]: ./enum.PeerPolicy.html
+//! [`abstract_ns`]: ../abstract_ns/index.html
+//! [`activation`]: ../activation/index.html
+//! [`ucspi`]: ../ucspi/index.html
+//! [`http_proxy`]: ../http_proxy/index.html
+//! [`proxy`]: ../proxy/index.html
+//! [`verify`]: ../verify/index.html
+
+use std::fmt;
+use std::io;
+
+/// A policy that decides which peers are allowed, based on their credentials (as returned by
+/// [`get_peer_ids()`](../fn.get_peer_ids.html)).
+pub enum PeerPolicy {
+    /// Allow peers with the same effective UID as the current process.
+    SameUser,
+    /// Allow peers whose effective UID is in the list.
+    Uids(Vec<libc::uid_t>),
+    /// Allow peers whose effective GID is in the list.
+    Gids(Vec<libc::gid_t>),
+    /// Allow peers for which the given predicate, called with the peer's effective UID and GID,
+    /// returns `true`.
+    Predicate(Box<dyn Fn(libc::uid_t, libc::gid_t) -> bool + Send + Sync>),
+    /// Allow any peer.
+    ///
+    /// **WARNING**: Any process that can reach the socket will be able to connect (for abstract
+    /// sockets, that is any process in the same network namespace). Only use this if the protocol
+    /// has its own authentication.
+    AllowAnyone,
+}
+
+impl PeerPolicy {
+    /// Create a policy that allows peers whose effective UID is in `uids`, or whose effective GID
+    /// is in `gids`.
+    ///
+    /// If both lists are empty, no peers are allowed.
+    pub fn any_of(uids: Vec<libc::uid_t>, gids: Vec<libc::gid_t>) -> Self {
+        if gids.is_empty() {
+            Self::Uids(uids)
+        } else if uids.is_empty() {
+            Self::Gids(gids)
+        } else {
+            Self::Predicate(Box::new(move |uid, gid| {
+                uids.contains(&uid) || gids.contains(&gid)
+            }))
+        }
+    }
+
+    /// Check whether a peer with the given effective UID and GID is allowed.
+    pub fn allows(&self, uid: libc::uid_t, gid: libc::gid_t) -> bool {
+        match self {
+            Self::SameUser => uid == unsafe { libc::geteuid() },
+            Self::Uids(uids) => uids.contains(&uid),
+            Self::Gids(gids) => gids.contains(&gid),
+            Self::Predicate(pred) => pred(uid, gid),
+            Self::AllowAnyone => true,
+        }
+    }
+
+    /// Check whether the given socket's peer is allowed, returning its effective UID and GID if
+    /// so.
+    ///
+    /// If the peer is not allowed, this fails with `EACCES`.
+    pub fn check<S: crate::PeerSocket>(&self, sock: &S) -> io::Result<(libc::uid_t, libc::gid_t)> {
+        let (uid, gid) = sock.peer_ids()?;
+
+        if self.allows(uid, gid) {
+            Ok((uid, gid))
+        } else {
+            Err(io::Error::from_raw_os_error(libc::EACCES))
+        }
+    }
+}
+
+impl fmt::Debug for PeerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SameUser => f.write_str("SameUser"),
+            Self::Uids(uids) => f.debug_tuple("Uids").field(uids).finish(),
+            Self::Gids(gids) => f.debug_tuple("Gids").field(gids).finish(),
+            Self::Predicate(_) => f.write_str("Predicate(..)"),
+            Self::AllowAnyone => f.write_str("AllowAnyone"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_allows() {
+        let euid = unsafe { libc::geteuid() };
+
+        assert!(PeerPolicy::SameUser.allows(euid, 0));
+        assert!(!PeerPolicy::SameUser.allows(euid.wrapping_add(1), 0));
+        assert!(PeerPolicy::Uids(vec![1, 2]).allows(2, 100));
+        assert!(!PeerPolicy::Uids(vec![1, 2]).allows(3, 1));
+        assert!(PeerPolicy::Gids(vec![100]).allows(2, 100));
+        assert!(!PeerPolicy::Gids(vec![100]).allows(100, 2));
+        assert!(PeerPolicy::Predicate(Box::new(|uid, gid| uid == gid)).allows(5, 5));
+        assert!(!PeerPolicy::Predicate(Box::new(|uid, gid| uid == gid)).allows(5, 6));
+        assert!(PeerPolicy::AllowAnyone.allows(12345, 12345));
+    }
+
+    #[test]
+    fn test_policy_any_of() {
+        let policy = PeerPolicy::any_of(vec![1, 2], vec![100]);
+        assert!(policy.allows(2, 5));
+        assert!(policy.allows(5, 100));
+        assert!(!policy.allows(5, 5));
+
+        assert!(
+            matches!(PeerPolicy::any_of(vec![1], Vec::new()), PeerPolicy::Uids(uids) if uids == [1])
+        );
+        assert!(
+            matches!(PeerPolicy::any_of(Vec::new(), vec![1]), PeerPolicy::Gids(gids) if gids == [1])
+        );
+        assert!(!PeerPolicy::any_of(Vec::new(), Vec::new()).allows(0, 0));
+    }
+}
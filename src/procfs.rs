@@ -0,0 +1,209 @@
+use std::io;
+use std::path::PathBuf;
+
+#[inline]
+pub fn proc_path(pid: libc::pid_t, name: &str) -> PathBuf {
+    PathBuf::from(format!("/proc/{}/{}", pid, name))
+}
+
+/// Read the given file from `/proc/<pid>/`, stripping any trailing newline.
+pub fn read_proc_string(pid: libc::pid_t, name: &str) -> io::Result<String> {
+    let mut data = std::fs::read_to_string(proc_path(pid, name))?;
+
+    if data.ends_with('\n') {
+        data.pop();
+    }
+
+    Ok(data)
+}
+
+/// Parse an integer from a file in `/proc`, returning `EINVAL` if it is malformed.
+pub fn parse_proc_int<T: std::str::FromStr>(data: &str) -> io::Result<T> {
+    data.trim()
+        .parse()
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+/// The fields of `/proc/<pid>/stat` that are used in this crate.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProcStat {
+    pub comm: String,
+    pub state: char,
+    pub ppid: libc::pid_t,
+    pub pgrp: libc::pid_t,
+    pub session: libc::pid_t,
+    pub tty_nr: u32,
+    pub tpgid: libc::pid_t,
+    pub starttime: u64,
+}
+
+pub fn parse_stat(data: &str) -> io::Result<ProcStat> {
+    let einval = || io::Error::from_raw_os_error(libc::EINVAL);
+
+    // The command name may contain spaces and parentheses, so we look for the *last* closing
+    // parenthesis to find its end.
+    let comm_start = data.find('(').ok_or_else(einval)?;
+    let comm_end = data.rfind(')').ok_or_else(einval)?;
+    if comm_end < comm_start {
+        return Err(einval());
+    }
+
+    let comm = data[comm_start + 1..comm_end].to_string();
+
+    // Fields are numbered from 1 in proc(5); the first field after the command name is field 3
+    let fields: Vec<&str> = data[comm_end + 1..].split_whitespace().collect();
+    let field = |n: usize| fields.get(n - 3).copied().ok_or_else(einval);
+
+    let mut state_chars = field(3)?.chars();
+    let state = match (state_chars.next(), state_chars.next()) {
+        (Some(c), None) => c,
+        _ => return Err(einval()),
+    };
+
+    Ok(ProcStat {
+        comm,
+        state,
+        ppid: parse_proc_int(field(4)?)?,
+        pgrp: parse_proc_int(field(5)?)?,
+        session: parse_proc_int(field(6)?)?,
+        // tty_nr is printed as a signed integer, but it's really a 32-bit device number
+        tty_nr: parse_proc_int::<i32>(field(7)?)? as u32,
+        tpgid: parse_proc_int(field(8)?)?,
+        starttime: parse_proc_int(field(22)?)?,
+    })
+}
+
+#[inline]
+pub fn read_stat(pid: libc::pid_t) -> io::Result<ProcStat> {
+    parse_stat(&read_proc_string(pid, "stat")?)
+}
+
+/// Find the value of the given field in the contents of `/proc/<pid>/status`.
+pub fn status_field<'a>(data: &'a str, key: &str) -> io::Result<&'a str> {
+    data.lines()
+        .find_map(|line| {
+            let (k, v) = line.split_at(line.find(':')?);
+            if k == key {
+                Some(v[1..].trim())
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+/// Parse the four IDs (real, effective, saved, and filesystem) in the `Uid` or `Gid` field of
+/// `/proc/<pid>/status`.
+pub fn parse_status_ids(data: &str) -> io::Result<[u32; 4]> {
+    let mut ids = [0; 4];
+    let mut it = data.split_whitespace();
+
+    for id in ids.iter_mut() {
+        *id = parse_proc_int(it.next().unwrap_or(""))?;
+    }
+
+    if it.next().is_some() {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    Ok(ids)
+}
+
+/// Read the target of `/proc/<pid>/exe`, or `None` if the process has no executable or we do not
+/// have permission to see it.
+pub fn read_exe(pid: libc::pid_t) -> io::Result<Option<PathBuf>> {
+    match std::fs::read_link(proc_path(pid, "exe")) {
+        Ok(exe) => Ok(Some(exe)),
+        // Kernel threads have no executable, and we may not have permission to see other users'
+        // executables
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::ENOENT | libc::EACCES | libc::EPERM)
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = parse_stat(
+            "1234 (a) b (c)) S 1 1234 1234 34817 1240 4194304 100 0 0 0 0 0 0 0 20 0 1 0 \
+             56789 1000 10 18446744073709551615 0 0 0 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0",
+        )
+        .unwrap();
+
+        assert_eq!(
+            stat,
+            ProcStat {
+                comm: "a) b (c)".into(),
+                state: 'S',
+                ppid: 1,
+                pgrp: 1234,
+                session: 1234,
+                tty_nr: 34817,
+                tpgid: 1240,
+                starttime: 56789,
+            }
+        );
+
+        for s in [
+            "",
+            "1234 (abc",
+            "1234 abc) S 1",
+            "1234 (abc) S 1 2 3",
+            "1234 (abc) SS 1 1234 1234 0 -1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 56789",
+            "1234 (abc) S x 1234 1234 0 -1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 56789",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                parse_stat(s).unwrap_err().raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+    }
+
+    #[test]
+    fn test_status_field() {
+        let data = "Name:\tcat\nUmask:\t0022\nTracerPid:\t0\nUid:\t1000\t1001\t1002\t1003\n\
+                    Groups:\t\n";
+
+        assert_eq!(status_field(data, "Name").unwrap(), "cat");
+        assert_eq!(status_field(data, "TracerPid").unwrap(), "0");
+        assert_eq!(status_field(data, "Groups").unwrap(), "");
+        assert_eq!(
+            parse_status_ids(status_field(data, "Uid").unwrap()).unwrap(),
+            [1000, 1001, 1002, 1003]
+        );
+
+        assert_eq!(
+            status_field(data, "Tracer").unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        for s in ["", "1 2 3", "1 2 3 4 5", "1 2 3 x"].iter() {
+            assert_eq!(
+                parse_status_ids(s).unwrap_err().raw_os_error(),
+                Some(libc::EINVAL)
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_stat() {
+        let pid = unsafe { libc::getpid() };
+        let stat = read_stat(pid).unwrap();
+
+        assert_eq!(stat.ppid, unsafe { libc::getppid() });
+        assert_eq!(stat.pgrp, unsafe { libc::getpgrp() });
+        assert_eq!(stat.session, unsafe { libc::getsid(0) });
+    }
+}
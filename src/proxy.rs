@@ -0,0 +1,938 @@
+//! The `proxy` module implements an authorizing proxy for sensitive Unix sockets (only with the
+//! `proxy` feature).
+//!
+//! Sockets like the Docker API or `ssh-agent` are all-or-nothing: anyone who can connect has full
+//! access. [`SocketProxy`] listens on a separate socket, checks each client's credentials with
+//! [`get_peer_pid_ids()`](../fn.get_peer_pid_ids.html), and picks the first [`Route`] whose
+//! policy allows the client. The connection is then forwarded to that route's upstream socket
+//! byte-for-byte. Clients that no route allows are disconnected immediately.
+//!
+//! Every connection (and rejection) is reported to an optional callback as an [`Event`], e.g. for
+//! logging.
+//!
+//! ```no_run
+//! use std::os::unix::net::UnixListener;
+//! use unix_cred::policy::PeerPolicy;
+//! use unix_cred::proxy::{Route, SocketProxy};
+//!
+//! let listener = UnixListener::bind("/run/docker-proxy.sock").unwrap();
+//! SocketProxy::new(listener)
+//!     .route(Route::new(PeerPolicy::Uids(vec![1000]), "/run/docker.sock").max_connections(4))
+//!     .on_event(|event| eprintln!("{}", event))
+//!     .serve()
+//!     .unwrap();
+//! ```
+//!
+//! [`DatagramRelay`] does the same for datagram sockets, but since datagrams carry their own
+//! `SCM_CREDENTIALS`, it can do better than forwarding the bytes: it re-sends each datagram with
+//! the original sender's credentials, so the backend sees the sender instead of the relay. This
+//! requires privileges (see [`check_relay_capabilities()`]).
+//!
+//! [`SocketProxy`]: ./struct.SocketProxy.html
+//! [`DatagramRelay`]: ./struct.DatagramRelay.html
+//! [`Route`]: ./struct.Route.html
+//! [`Event`]: ./struct.Event.html
+//! [`check_relay_capabilities()`]: ./fn.check_relay_capabilities.html
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::addr::UnixSocketName;
+use crate::policy::PeerPolicy;
+use crate::scm;
+use crate::ucred::Ucred;
+
+/// A connection slot, which is released when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn acquire(active: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+        active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| match max {
+                Some(max) if n >= max => None,
+                _ => Some(n + 1),
+            })
+            .ok()?;
+
+        Some(Self(active.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A route that forwards connections from clients allowed by a policy to an upstream socket.
+#[derive(Debug)]
+pub struct Route {
+    policy: PeerPolicy,
+    upstream: PathBuf,
+    max_connections: Option<usize>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Route {
+    /// Create a new route that forwards connections from clients allowed by the given policy to
+    /// the socket at the given path.
+    pub fn new<P: AsRef<Path>>(policy: PeerPolicy, upstream: P) -> Self {
+        Self {
+            policy,
+            upstream: upstream.as_ref().to_path_buf(),
+            max_connections: None,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Set the maximum number of connections that can be forwarded over this route at once.
+    ///
+    /// Connections over the limit are closed immediately (they do not fall through to later
+    /// routes). This has no effect on a [`DatagramRelay`](./struct.DatagramRelay.html).
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Get the policy that clients must be allowed by to use this route.
+    #[inline]
+    pub fn policy(&self) -> &PeerPolicy {
+        &self.policy
+    }
+
+    /// Get the path of the upstream socket.
+    #[inline]
+    pub fn upstream(&self) -> &Path {
+        &self.upstream
+    }
+}
+
+/// What happened to a connection.
+#[derive(Debug)]
+pub enum EventKind<'a> {
+    /// No route allowed the client, so the connection was closed.
+    Rejected,
+    /// A connection limit was reached, so the connection was closed.
+    Busy,
+    /// Connecting to the upstream socket failed, so the connection was closed.
+    UpstreamError(&'a io::Error),
+    /// The client was connected to the upstream socket.
+    Connected,
+    /// The connection was closed, after forwarding the given number of bytes from the client to
+    /// the upstream (`sent`) and back (`received`).
+    Closed {
+        /// The number of bytes forwarded from the client to the upstream.
+        sent: u64,
+        /// The number of bytes forwarded from the upstream to the client.
+        received: u64,
+    },
+    /// A datagram of the given length was relayed to the upstream socket.
+    Relayed(usize),
+    /// A datagram was too large to be relayed, so it was dropped.
+    Truncated,
+}
+
+/// An event reported to the callback set with
+/// [`SocketProxy::on_event()`](./struct.SocketProxy.html#method.on_event).
+#[derive(Debug)]
+pub struct Event<'a> {
+    /// The client's PID (if available).
+    pub pid: Option<libc::pid_t>,
+    /// The client's effective UID.
+    pub uid: libc::uid_t,
+    /// The client's effective GID.
+    pub gid: libc::gid_t,
+    /// The upstream socket that the client was routed to (`None` if no route allowed it).
+    pub upstream: Option<&'a Path>,
+    /// What happened.
+    pub kind: EventKind<'a>,
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "pid={} ", pid)?,
+            None => f.write_str("pid=? ")?,
+        }
+        write!(f, "uid={} gid={}: ", self.uid, self.gid)?;
+
+        let upstream = self.upstream.unwrap_or_else(|| Path::new("-")).display();
+
+        match &self.kind {
+            EventKind::Rejected => f.write_str("rejected"),
+            EventKind::Busy => write!(f, "busy ({})", upstream),
+            EventKind::UpstreamError(e) => write!(f, "error connecting to {}: {}", upstream, e),
+            EventKind::Connected => write!(f, "connected to {}", upstream),
+            EventKind::Closed { sent, received } => write!(
+                f,
+                "closed ({}; sent {} bytes, received {} bytes)",
+                upstream, sent, received
+            ),
+            EventKind::Relayed(len) => write!(f, "relayed {} bytes to {}", len, upstream),
+            EventKind::Truncated => write!(f, "dropped oversized datagram for {}", upstream),
+        }
+    }
+}
+
+type EventCallback = dyn Fn(&Event) + Send + Sync;
+
+/// An authorizing proxy for Unix sockets.
+///
+/// See the [module-level documentation](./index.html) for more information.
+pub struct SocketProxy {
+    listener: UnixListener,
+    routes: Vec<Route>,
+    max_connections: Option<usize>,
+    active: Arc<AtomicUsize>,
+    on_event: Option<Box<EventCallback>>,
+}
+
+impl SocketProxy {
+    /// Create a new proxy that accepts connections on the given listener.
+    ///
+    /// The proxy has no routes (so it rejects every client) until some are added with
+    /// [`route()`](#method.route).
+    pub fn new(listener: UnixListener) -> Self {
+        Self {
+            listener,
+            routes: Vec::new(),
+            max_connections: None,
+            active: Arc::new(AtomicUsize::new(0)),
+            on_event: None,
+        }
+    }
+
+    /// Add a route. Routes are tried in the order they were added, and the first one whose policy
+    /// allows the client is used.
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Set the maximum number of connections that can be forwarded at once (over all routes).
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Set a callback to be called with each connection event (e.g. for logging).
+    pub fn on_event<F: Fn(&Event) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.on_event = Some(Box::new(callback));
+        self
+    }
+
+    /// Get the listener that this proxy accepts connections on.
+    #[inline]
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    /// Route a single connection, and forward traffic in both directions until both sides have
+    /// shut down.
+    ///
+    /// This returns an error if the client's credentials cannot be retrieved or connecting to the
+    /// upstream fails.
+    pub fn handle(&self, sock: UnixStream) -> io::Result<()> {
+        let (pid, uid, gid) = crate::get_peer_pid_ids(&sock)?;
+
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.policy.allows(uid, gid));
+
+        let emit = |kind| {
+            if let Some(on_event) = self.on_event.as_ref() {
+                on_event(&Event {
+                    pid,
+                    uid,
+                    gid,
+                    upstream: route.map(|route| route.upstream.as_path()),
+                    kind,
+                });
+            }
+        };
+
+        let route = match route {
+            Some(route) => route,
+            None => {
+                emit(EventKind::Rejected);
+                return Ok(());
+            }
+        };
+
+        let _slots = match Slot::acquire(&self.active, self.max_connections)
+            .and_then(|global| Some((global, Slot::acquire(&route.active, route.max_connections)?)))
+        {
+            Some(slots) => slots,
+            None => {
+                emit(EventKind::Busy);
+                return Ok(());
+            }
+        };
+
+        let upstream = match UnixStream::connect(&route.upstream) {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                emit(EventKind::UpstreamError(&e));
+                return Err(e);
+            }
+        };
+        emit(EventKind::Connected);
+
+        let (sent, received) = std::thread::scope(|s| {
+            let sent = s.spawn(|| forward(&sock, &upstream));
+            let received = forward(&upstream, &sock);
+            (sent.join().unwrap_or(0), received)
+        });
+
+        emit(EventKind::Closed { sent, received });
+        Ok(())
+    }
+
+    /// Accept connections forever, handling each one in a new thread.
+    pub fn serve(self) -> io::Result<()> {
+        let proxy = Arc::new(self);
+
+        loop {
+            let sock = match proxy.listener.accept() {
+                Ok((sock, _)) => sock,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let proxy = proxy.clone();
+            std::thread::spawn(move || proxy.handle(sock));
+        }
+    }
+}
+
+impl fmt::Debug for SocketProxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SocketProxy")
+            .field("listener", &self.listener)
+            .field("routes", &self.routes)
+            .field("max_connections", &self.max_connections)
+            .finish()
+    }
+}
+
+/// Copy from `src` to `dst` until EOF, then shut down `dst` for writing. Returns the number of
+/// bytes copied.
+///
+/// On errors, both sockets are shut down completely so that the other direction stops too.
+fn forward(src: &UnixStream, dst: &UnixStream) -> u64 {
+    let mut copied = 0;
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let n = match io::Read::read(&mut &*src, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => {
+                let _ = src.shutdown(Shutdown::Both);
+                let _ = dst.shutdown(Shutdown::Both);
+                return copied;
+            }
+        };
+
+        if io::Write::write_all(&mut &*dst, &buf[..n]).is_err() {
+            let _ = src.shutdown(Shutdown::Both);
+            let _ = dst.shutdown(Shutdown::Both);
+            return copied;
+        }
+        copied += n as u64;
+    }
+
+    let _ = dst.shutdown(Shutdown::Write);
+    copied
+}
+
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+const CAP_SYS_ADMIN: u32 = 21;
+
+const NS_GET_USERNS: libc::c_ulong = 0xb701;
+const NS_GET_PARENT: libc::c_ulong = 0xb702;
+
+/// The largest datagram that can be relayed (larger than the default maximum socket buffer size,
+/// so in practice no datagrams are truncated).
+const MAX_DATAGRAM: usize = 256 * 1024;
+
+fn effective_caps() -> io::Result<u64> {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    let mut header = CapHeader {
+        // _LINUX_CAPABILITY_VERSION_3
+        version: 0x2008_0522,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(data[0].effective as u64 | (data[1].effective as u64) << 32)
+}
+
+fn ns_ioctl(fd: &fs::File, request: libc::c_ulong) -> io::Result<fs::File> {
+    let res = unsafe { libc::ioctl(fd.as_raw_fd(), request as _) };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { fs::File::from_raw_fd(res) })
+}
+
+/// Check whether the current user namespace is (an ancestor of) the one that owns the current PID
+/// namespace. `CAP_SYS_ADMIN` only allows sending other processes' PIDs if this is true.
+fn owns_pid_ns() -> io::Result<bool> {
+    let user_ns = fs::metadata("/proc/self/ns/user")?;
+
+    let mut ns = match ns_ioctl(&fs::File::open("/proc/self/ns/pid")?, NS_GET_USERNS) {
+        Ok(ns) => ns,
+        // Kernels before 4.9 don't support this; assume the common case
+        Err(e) if e.raw_os_error() == Some(libc::ENOTTY) => return Ok(true),
+        // The owner is outside our user namespace
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    loop {
+        let meta = ns.metadata()?;
+        if (meta.dev(), meta.ino()) == (user_ns.dev(), user_ns.ino()) {
+            return Ok(true);
+        }
+
+        ns = match ns_ioctl(&ns, NS_GET_PARENT) {
+            Ok(ns) => ns,
+            // We've reached the initial namespace, or one that we are not an ancestor of
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+    }
+}
+
+/// Check that the current process has the privileges needed to relay other processes'
+/// credentials with a [`DatagramRelay`](./struct.DatagramRelay.html).
+///
+/// Sending credentials other than its own requires `CAP_SETUID` and `CAP_SETGID` (for the UID and
+/// GID) and `CAP_SYS_ADMIN` (for the PID) in the relay's user namespace. The latter must also be
+/// the namespace that owns the relay's PID namespace (or an ancestor of it). For example, an
+/// unprivileged user can run the relay with `unshare --user --map-root-user --pid --fork`.
+///
+/// This fails with an error of kind `PermissionDenied` describing what is missing.
+pub fn check_relay_capabilities() -> io::Result<()> {
+    let caps = effective_caps()?;
+
+    let missing: Vec<&str> = [
+        (CAP_SETUID, "CAP_SETUID"),
+        (CAP_SETGID, "CAP_SETGID"),
+        (CAP_SYS_ADMIN, "CAP_SYS_ADMIN"),
+    ]
+    .iter()
+    .filter(|(cap, _)| caps & (1 << cap) == 0)
+    .map(|(_, name)| *name)
+    .collect();
+
+    if !missing.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "relaying credentials requires CAP_SETUID, CAP_SETGID, and CAP_SYS_ADMIN (missing {})",
+                missing.join(", ")
+            ),
+        ));
+    }
+
+    if !owns_pid_ns()? {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "relaying credentials requires CAP_SYS_ADMIN over the current PID namespace \
+             (run the relay in a PID namespace owned by its user namespace)",
+        ));
+    }
+
+    Ok(())
+}
+
+/// A relay for datagram sockets that preserves each sender's credentials.
+///
+/// Each datagram received on the relay's socket is re-sent to the upstream socket of the first
+/// [`Route`](./struct.Route.html) that allows the sender, with the sender's PID, UID, and GID
+/// attached as `SCM_CREDENTIALS`. A backend that enables `SO_PASSCRED` (see
+/// [`scm::set_passcred()`](../scm/fn.set_passcred.html)) therefore sees the original sender's
+/// credentials instead of the relay's.
+///
+/// Datagrams are only relayed one way: the relay's sending socket is unbound, so the backend
+/// cannot reply.
+pub struct DatagramRelay {
+    socket: UnixDatagram,
+    sender: UnixDatagram,
+    max_datagram: usize,
+    routes: Vec<Route>,
+    on_event: Option<Box<EventCallback>>,
+}
+
+impl DatagramRelay {
+    /// Create a new relay that receives datagrams on the given socket.
+    ///
+    /// This enables `SO_PASSCRED` on the socket. It fails if the current process cannot relay
+    /// credentials (see [`check_relay_capabilities()`](./fn.check_relay_capabilities.html)).
+    pub fn new(socket: UnixDatagram) -> io::Result<Self> {
+        check_relay_capabilities()?;
+        scm::set_passcred(&socket, true)?;
+
+        Ok(Self {
+            socket,
+            sender: UnixDatagram::unbound()?,
+            max_datagram: MAX_DATAGRAM,
+            routes: Vec::new(),
+            on_event: None,
+        })
+    }
+
+    /// Add a route. Routes are tried in the order they were added, and the first one whose policy
+    /// allows the sender is used. Datagrams from senders that no route allows are dropped.
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Set a callback to be called with each event (e.g. for logging).
+    pub fn on_event<F: Fn(&Event) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.on_event = Some(Box::new(callback));
+        self
+    }
+
+    /// Get the socket that this relay receives datagrams on.
+    #[inline]
+    pub fn socket(&self) -> &UnixDatagram {
+        &self.socket
+    }
+
+    fn relay(&self, data: &[u8], truncated: bool, cred: &Ucred) -> io::Result<()> {
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.policy.allows(cred.uid, cred.gid));
+
+        let emit = |kind| {
+            if let Some(on_event) = self.on_event.as_ref() {
+                on_event(&Event {
+                    pid: Some(cred.pid).filter(|&pid| pid > 0),
+                    uid: cred.uid,
+                    gid: cred.gid,
+                    upstream: route.map(|route| route.upstream.as_path()),
+                    kind,
+                });
+            }
+        };
+
+        let route = match route {
+            Some(route) => route,
+            None => {
+                emit(EventKind::Rejected);
+                return Ok(());
+            }
+        };
+
+        // Relaying part of a datagram could change its meaning
+        if truncated {
+            emit(EventKind::Truncated);
+            return Err(io::Error::from_raw_os_error(libc::EMSGSIZE));
+        }
+
+        let upstream = UnixSocketName::Path(route.upstream.clone());
+        match scm::send_to_with_creds(&self.sender, data, &upstream, cred) {
+            Ok(_) => {
+                emit(EventKind::Relayed(data.len()));
+                Ok(())
+            }
+            Err(e) => {
+                emit(EventKind::UpstreamError(&e));
+                Err(e)
+            }
+        }
+    }
+
+    /// Receive a single datagram and relay it.
+    ///
+    /// This returns an error if receiving or sending the datagram fails. Notably, sending fails
+    /// with `ESRCH` if the sender exited before its datagram could be relayed (since the kernel
+    /// only attaches the PIDs of live processes), and datagrams that are too large to be relayed
+    /// are dropped with `EMSGSIZE`.
+    pub fn relay_one(&self) -> io::Result<()> {
+        let mut buf = vec![0; self.max_datagram];
+        let (n, cred, truncated) = scm::recv_datagram_with_creds(&self.socket, &mut buf)?;
+
+        match cred {
+            Some(cred) => self.relay(&buf[..n], truncated, &cred),
+            // Shouldn't happen since SO_PASSCRED is enabled
+            None => Ok(()),
+        }
+    }
+
+    /// Relay datagrams forever.
+    ///
+    /// Errors relaying individual datagrams are ignored (after being reported to the callback, if
+    /// one is set). Errors receiving datagrams are returned.
+    pub fn serve(self) -> io::Result<()> {
+        let mut buf = vec![0; self.max_datagram];
+
+        loop {
+            let (n, cred, truncated) = scm::recv_datagram_with_creds(&self.socket, &mut buf)?;
+            if let Some(cred) = cred {
+                let _ = self.relay(&buf[..n], truncated, &cred);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DatagramRelay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DatagramRelay")
+            .field("socket", &self.socket)
+            .field("routes", &self.routes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::sync::Mutex;
+
+    /// Start an upstream server that replies to each connection with its name, followed by
+    /// everything it receives.
+    fn start_upstream(path: &Path, name: &'static str) {
+        let listener = UnixListener::bind(path).unwrap();
+
+        std::thread::spawn(move || {
+            for sock in listener.incoming() {
+                let mut sock = sock.unwrap();
+                std::thread::spawn(move || {
+                    sock.write_all(name.as_bytes()).unwrap();
+                    io::copy(&mut sock.try_clone().unwrap(), &mut sock).unwrap();
+                });
+            }
+        });
+    }
+
+    fn start_proxy(
+        dir: &Path,
+        routes: Vec<Route>,
+        max: usize,
+    ) -> (PathBuf, Arc<Mutex<Vec<String>>>) {
+        let path = dir.join("proxy");
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let mut proxy = SocketProxy::new(UnixListener::bind(&path).unwrap()).max_connections(max);
+        for route in routes {
+            proxy = proxy.route(route);
+        }
+
+        let events2 = events.clone();
+        let proxy = proxy.on_event(move |event| {
+            assert_eq!(event.uid, unsafe { libc::geteuid() });
+            let kind = match &event.kind {
+                EventKind::Closed { sent, received } => format!("closed {} {}", sent, received),
+                kind => format!("{:?}", kind),
+            };
+            events2.lock().unwrap().push(kind);
+        });
+        std::thread::spawn(move || proxy.serve());
+
+        (path, events)
+    }
+
+    fn read_all(sock: &mut UnixStream) -> String {
+        let mut buf = String::new();
+        sock.read_to_string(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_proxy() {
+        let dir = tempfile::tempdir().unwrap();
+        start_upstream(&dir.path().join("a"), "a:");
+        start_upstream(&dir.path().join("b"), "b:");
+
+        let (path, events) = start_proxy(
+            dir.path(),
+            vec![
+                Route::new(PeerPolicy::Uids(Vec::new()), dir.path().join("a")),
+                Route::new(PeerPolicy::SameUser, dir.path().join("b")),
+            ],
+            10,
+        );
+
+        let mut sock = UnixStream::connect(&path).unwrap();
+        sock.write_all(b"hello").unwrap();
+        sock.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(read_all(&mut sock), "b:hello");
+
+        // Wait for the proxy to finish up
+        while events.lock().unwrap().len() < 2 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(*events.lock().unwrap(), ["Connected", "closed 5 7"]);
+    }
+
+    #[test]
+    fn test_proxy_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        start_upstream(&dir.path().join("a"), "a:");
+
+        let (path, events) = start_proxy(
+            dir.path(),
+            vec![Route::new(
+                PeerPolicy::Uids(Vec::new()),
+                dir.path().join("a"),
+            )],
+            10,
+        );
+
+        let mut sock = UnixStream::connect(&path).unwrap();
+        assert_eq!(read_all(&mut sock), "");
+        assert_eq!(*events.lock().unwrap(), ["Rejected"]);
+    }
+
+    #[test]
+    fn test_proxy_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        start_upstream(&dir.path().join("a"), "a:");
+
+        for (route_max, global_max) in [(1, 10), (10, 1)] {
+            let subdir = tempfile::tempdir_in(dir.path()).unwrap();
+            let (path, events) = start_proxy(
+                subdir.path(),
+                vec![Route::new(PeerPolicy::SameUser, dir.path().join("a"))
+                    .max_connections(route_max)],
+                global_max,
+            );
+
+            let mut first = UnixStream::connect(&path).unwrap();
+            let mut buf = [0; 2];
+            first.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"a:");
+
+            let mut second = UnixStream::connect(&path).unwrap();
+            assert_eq!(read_all(&mut second), "");
+
+            assert_eq!(*events.lock().unwrap(), ["Connected", "Busy"]);
+        }
+    }
+
+    #[test]
+    fn test_check_relay_capabilities() {
+        let caps = effective_caps().unwrap();
+        let all = (1 << CAP_SETUID) | (1 << CAP_SETGID) | (1 << CAP_SYS_ADMIN);
+
+        match check_relay_capabilities() {
+            Ok(()) => assert_eq!(caps & all, all),
+            Err(e) => {
+                assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+                assert!(caps & all != all || !owns_pid_ns().unwrap());
+            }
+        }
+    }
+
+    /// Get IDs to impersonate: `nobody` if it's mapped into our user namespace, otherwise our own.
+    fn other_ids() -> (libc::uid_t, libc::gid_t) {
+        let mapped = |name| {
+            fs::read_to_string(format!("/proc/self/{}", name))
+                .unwrap()
+                .lines()
+                .any(|line| {
+                    let fields: Vec<u64> = line
+                        .split_whitespace()
+                        .map(|f| f.parse().unwrap())
+                        .collect();
+                    fields[0] <= 65534 && 65534 < fields[0] + fields[2]
+                })
+        };
+
+        if mapped("uid_map") && mapped("gid_map") {
+            (65534, 65534)
+        } else {
+            unsafe { (libc::geteuid(), libc::getegid()) }
+        }
+    }
+
+    /// Set when `test_relay` re-runs itself in new user and PID namespaces.
+    const IN_NS_VAR: &str = "UNIX_CRED_TEST_RELAY_IN_NS";
+
+    /// Re-run `test_relay` with `unshare -rpf`, so that it is root in new user and PID namespaces
+    /// and can relay credentials without any privileges outside them. (Forking the multithreaded
+    /// test harness and running more code in the child could deadlock.)
+    ///
+    /// Returns `false` if user namespaces (or `unshare`) are unavailable, and panics if the test
+    /// fails.
+    fn run_in_user_pid_ns() -> bool {
+        let output = match std::process::Command::new("unshare")
+            .args(["-rpf", "--"])
+            .arg(std::env::current_exe().unwrap())
+            .args(["--exact", "proxy::tests::test_relay", "--nocapture"])
+            .env(IN_NS_VAR, "1")
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return false,
+            Err(e) => panic!("running unshare failed: {}", e),
+        };
+
+        match output.status.code() {
+            Some(0) => {
+                // Make sure the test actually ran
+                assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+                true
+            }
+            // The test harness exits with 101 if a test fails; anything else means unshare itself
+            // failed
+            Some(code) if code != 101 => false,
+            _ => panic!(
+                "test_relay failed in namespaces:\n{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }
+    }
+
+    fn check_relay() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let relay =
+            DatagramRelay::new(UnixDatagram::bind(dir.path().join("relay")).unwrap()).unwrap();
+
+        let backend = UnixDatagram::bind(dir.path().join("backend")).unwrap();
+        scm::set_passcred(&backend, true).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let mut relay = relay
+            .route(Route::new(
+                PeerPolicy::Uids(vec![u32::MAX - 1]),
+                dir.path().join("nonexistent"),
+            ))
+            .route(Route::new(
+                PeerPolicy::AllowAnyone,
+                dir.path().join("backend"),
+            ))
+            .on_event(move |event| events2.lock().unwrap().push(event.to_string()));
+
+        // Impersonate another process, as if it had sent the datagram itself
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let (uid, gid) = other_ids();
+        let cred = Ucred {
+            pid: child.id() as libc::pid_t,
+            uid,
+            gid,
+        };
+
+        let client = UnixDatagram::unbound().unwrap();
+        let send = |data: &[u8]| {
+            scm::send_to_with_creds(
+                &client,
+                data,
+                &UnixSocketName::Path(dir.path().join("relay")),
+                &cred,
+            )
+            .unwrap();
+        };
+
+        send(b"hello");
+        relay.relay_one().unwrap();
+
+        let mut buf = [0; 16];
+        let (n, backend_cred) = scm::recv_with_creds(&backend, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert_eq!(backend_cred, Some(cred.clone()));
+
+        // Datagrams that don't fit in the buffer are dropped, not truncated
+        relay.max_datagram = 4;
+        send(b"hello");
+        assert_eq!(
+            relay.relay_one().unwrap_err().raw_os_error(),
+            Some(libc::EMSGSIZE)
+        );
+        backend.set_nonblocking(true).unwrap();
+        assert_eq!(
+            backend.recv(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let backend_path = dir.path().join("backend");
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                format!(
+                    "pid={} uid={} gid={}: relayed 5 bytes to {}",
+                    cred.pid,
+                    uid,
+                    gid,
+                    backend_path.display()
+                ),
+                format!(
+                    "pid={} uid={} gid={}: dropped oversized datagram for {}",
+                    cred.pid,
+                    uid,
+                    gid,
+                    backend_path.display()
+                ),
+            ]
+        );
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_relay() {
+        if std::env::var_os(IN_NS_VAR).is_some() {
+            check_relay_capabilities().unwrap();
+            check_relay();
+            return;
+        }
+
+        match check_relay_capabilities() {
+            Ok(()) => check_relay(),
+            Err(e) => {
+                // Not privileged; make sure the error is clear
+                assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+                assert!(e.to_string().contains("CAP_"));
+
+                if !run_in_user_pid_ns() {
+                    eprintln!(
+                        "skipping test_relay: not privileged, and user namespaces are unavailable"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,547 @@
+//! The `sasl` module implements the SASL `EXTERNAL` handshake used by D-Bus (and protocols
+//! modeled after it).
+//!
+//! In this handshake, the client sends a NUL byte followed by `AUTH EXTERNAL <uid>` (with the UID
+//! in decimal, hex-encoded), and the server checks the claimed UID against the peer credentials of
+//! the socket. The client can then negotiate file descriptor passing with `NEGOTIATE_UNIX_FD`,
+//! before sending `BEGIN` to switch to the actual protocol.
+//!
+//! [`ServerAuth`] and [`ClientAuth`] are state machines that process one line at a time, so they
+//! can be used with any I/O model. [`server_handshake()`] and [`client_handshake()`] drive them
+//! over a blocking socket.
+//!
+//! ```
+//! use std::os::unix::net::UnixStream;
+//! use unix_cred::sasl;
+//!
+//! let (mut client, mut server) = UnixStream::pair().unwrap();
+//!
+//! let guid = sasl::random_guid().unwrap();
+//! let thread = std::thread::spawn(move || sasl::client_handshake(&mut client, true).unwrap());
+//!
+//! let auth = sasl::server_handshake(&mut server, &guid, true).unwrap();
+//! assert_eq!(auth.uid(), unsafe { libc::geteuid() });
+//!
+//! let client_auth = thread.join().unwrap();
+//! assert_eq!(client_auth.guid(), Some(guid.as_str()));
+//! assert!(client_auth.unix_fd_negotiated());
+//! ```
+//!
+//! [`ServerAuth`]: ./struct.ServerAuth.html
+//! [`ClientAuth`]: ./struct.ClientAuth.html
+//! [`server_handshake()`]: ./fn.server_handshake.html
+//! [`client_handshake()`]: ./fn.client_handshake.html
+
+use std::io::{self, Read, Write};
+
+/// The maximum length of a line (the same limit that the reference D-Bus implementation uses).
+const MAX_LINE_LEN: usize = 16384;
+
+/// The maximum number of lines that the blocking handshake functions will process before giving
+/// up.
+const MAX_LINES: usize = 64;
+
+const REJECTED: &str = "REJECTED EXTERNAL\r\n";
+
+/// Generate a random GUID (32 hex digits) for the server to send in its `OK` reply.
+pub fn random_guid() -> io::Result<String> {
+    crate::util::random_hex(16)
+}
+
+fn hex_encode(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => {
+                Some((char::from(*hi).to_digit(16)? * 16 + char::from(*lo).to_digit(16)?) as u8)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse the hex-encoded decimal UID sent by the client.
+fn parse_uid(s: &str) -> Option<libc::uid_t> {
+    let s = String::from_utf8(hex_decode(s)?).ok()?;
+
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ServerState {
+    WaitingForAuth,
+    WaitingForData,
+    WaitingForBegin,
+    Done,
+}
+
+/// The server side of the handshake.
+#[derive(Clone, Debug)]
+pub struct ServerAuth {
+    state: ServerState,
+    peer_uid: libc::uid_t,
+    guid: String,
+    allow_unix_fd: bool,
+    unix_fd: bool,
+}
+
+impl ServerAuth {
+    /// Create a new server-side state machine for a peer with the given effective UID (as
+    /// returned by [`get_peer_ids()`](../fn.get_peer_ids.html)).
+    ///
+    /// `guid` is sent to the client in the `OK` reply; see [`random_guid()`](./fn.random_guid.html).
+    pub fn new(peer_uid: libc::uid_t, guid: &str) -> Self {
+        Self {
+            state: ServerState::WaitingForAuth,
+            peer_uid,
+            guid: guid.into(),
+            allow_unix_fd: false,
+            unix_fd: false,
+        }
+    }
+
+    /// Set whether to agree if the client asks to pass file descriptors (default `false`).
+    pub fn allow_unix_fd(mut self, allow: bool) -> Self {
+        self.allow_unix_fd = allow;
+        self
+    }
+
+    fn authenticate(&mut self, response: &str) -> Option<String> {
+        // An empty response means "whatever the credentials say"
+        if response.is_empty() || parse_uid(response) == Some(self.peer_uid) {
+            self.state = ServerState::WaitingForBegin;
+            Some(format!("OK {}\r\n", self.guid))
+        } else {
+            self.reject()
+        }
+    }
+
+    fn reject(&mut self) -> Option<String> {
+        self.state = ServerState::WaitingForAuth;
+        self.unix_fd = false;
+        Some(REJECTED.into())
+    }
+
+    /// Process a line received from the client (without the trailing `\r\n`), returning the reply
+    /// to send (with the trailing `\r\n`).
+    ///
+    /// This returns `None` once the client has sent `BEGIN` (after authenticating successfully);
+    /// at that point, [`is_done()`](#method.is_done) returns `true`, and any further data is part
+    /// of the actual protocol.
+    ///
+    /// Note that the leading NUL byte that the client sends before the first line must be
+    /// stripped before calling this.
+    pub fn handle_line(&mut self, line: &str) -> Option<String> {
+        let (cmd, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+
+        match (self.state, cmd) {
+            (ServerState::Done, _) => None,
+
+            (ServerState::WaitingForAuth, "AUTH") => match arg.find(' ') {
+                Some(i) if &arg[..i] == "EXTERNAL" => self.authenticate(&arg[i + 1..]),
+                None if arg == "EXTERNAL" => {
+                    self.state = ServerState::WaitingForData;
+                    Some("DATA\r\n".into())
+                }
+                // Unsupported mechanism, or a request for the list of mechanisms
+                _ => self.reject(),
+            },
+            (ServerState::WaitingForData, "DATA") => self.authenticate(arg),
+
+            (ServerState::WaitingForBegin, "BEGIN") => {
+                self.state = ServerState::Done;
+                None
+            }
+            (ServerState::WaitingForBegin, "NEGOTIATE_UNIX_FD") => {
+                if self.allow_unix_fd {
+                    self.unix_fd = true;
+                    Some("AGREE_UNIX_FD\r\n".into())
+                } else {
+                    Some("ERROR \"File descriptor passing is not supported\"\r\n".into())
+                }
+            }
+
+            (_, "CANCEL") | (_, "ERROR") => self.reject(),
+            _ => Some("ERROR \"Unknown command\"\r\n".into()),
+        }
+    }
+
+    /// Check whether the client has authenticated successfully.
+    #[inline]
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self.state, ServerState::WaitingForBegin | ServerState::Done)
+    }
+
+    /// Check whether the client has authenticated and sent `BEGIN`.
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.state == ServerState::Done
+    }
+
+    /// Check whether the server agreed to pass file descriptors.
+    #[inline]
+    pub fn unix_fd_negotiated(&self) -> bool {
+        self.unix_fd
+    }
+
+    /// Get the effective UID of the peer.
+    #[inline]
+    pub fn uid(&self) -> libc::uid_t {
+        self.peer_uid
+    }
+
+    /// Get the GUID that is sent in the `OK` reply.
+    #[inline]
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ClientState {
+    Start,
+    WaitingForOk,
+    WaitingForAgree,
+    Done,
+}
+
+/// The client side of the handshake.
+#[derive(Clone, Debug)]
+pub struct ClientAuth {
+    state: ClientState,
+    uid: libc::uid_t,
+    negotiate_unix_fd: bool,
+    unix_fd: bool,
+    guid: Option<String>,
+}
+
+impl ClientAuth {
+    /// Create a new client-side state machine, which will claim the given UID (usually the
+    /// current process's effective UID).
+    pub fn new(uid: libc::uid_t) -> Self {
+        Self {
+            state: ClientState::Start,
+            uid,
+            negotiate_unix_fd: false,
+            unix_fd: false,
+            guid: None,
+        }
+    }
+
+    /// Set whether to ask the server to pass file descriptors (default `false`).
+    pub fn negotiate_unix_fd(mut self, negotiate: bool) -> Self {
+        self.negotiate_unix_fd = negotiate;
+        self
+    }
+
+    /// Get the data to send to start the handshake (the NUL byte and the `AUTH` command).
+    pub fn start(&mut self) -> Vec<u8> {
+        self.state = ClientState::WaitingForOk;
+        format!("\0AUTH EXTERNAL {}\r\n", hex_encode(&self.uid.to_string())).into_bytes()
+    }
+
+    /// Process a line received from the server (without the trailing `\r\n`), returning the line
+    /// to send next (with the trailing `\r\n`).
+    ///
+    /// Once this returns `BEGIN`, [`is_done()`](#method.is_done) returns `true`, and any further
+    /// data is part of the actual protocol.
+    ///
+    /// If the server rejects the credentials, this fails with `EACCES`. If the server sends an
+    /// unexpected reply, this fails with `EPROTO`.
+    pub fn handle_line(&mut self, line: &str) -> io::Result<String> {
+        let (cmd, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+
+        match (self.state, cmd) {
+            (ClientState::WaitingForOk, "OK") if !arg.is_empty() => {
+                self.guid = Some(arg.into());
+
+                if self.negotiate_unix_fd {
+                    self.state = ClientState::WaitingForAgree;
+                    Ok("NEGOTIATE_UNIX_FD\r\n".into())
+                } else {
+                    self.state = ClientState::Done;
+                    Ok("BEGIN\r\n".into())
+                }
+            }
+            (ClientState::WaitingForOk, "DATA") => {
+                Ok(format!("DATA {}\r\n", hex_encode(&self.uid.to_string())))
+            }
+            (ClientState::WaitingForOk, "REJECTED") => {
+                Err(io::Error::from_raw_os_error(libc::EACCES))
+            }
+
+            (ClientState::WaitingForAgree, "AGREE_UNIX_FD")
+            | (ClientState::WaitingForAgree, "ERROR") => {
+                self.unix_fd = cmd == "AGREE_UNIX_FD";
+                self.state = ClientState::Done;
+                Ok("BEGIN\r\n".into())
+            }
+
+            _ => Err(io::Error::from_raw_os_error(libc::EPROTO)),
+        }
+    }
+
+    /// Check whether the handshake is complete (i.e. `BEGIN` has been sent).
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.state == ClientState::Done
+    }
+
+    /// Get the GUID that the server sent in its `OK` reply, if it has been received.
+    #[inline]
+    pub fn guid(&self) -> Option<&str> {
+        self.guid.as_deref()
+    }
+
+    /// Check whether the server agreed to pass file descriptors.
+    #[inline]
+    pub fn unix_fd_negotiated(&self) -> bool {
+        self.unix_fd
+    }
+}
+
+/// Read a single `\r\n`-terminated line (without the line ending).
+///
+/// This reads one byte at a time, so that nothing after the line is consumed.
+fn read_line<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    let mut byte = 0;
+
+    loop {
+        reader.read_exact(std::slice::from_mut(&mut byte))?;
+
+        if byte == b'\n' && line.last() == Some(&b'\r') {
+            line.pop();
+            return String::from_utf8(line).map_err(|_| io::Error::from_raw_os_error(libc::EPROTO));
+        } else if line.len() >= MAX_LINE_LEN {
+            return Err(io::Error::from_raw_os_error(libc::EPROTO));
+        }
+
+        line.push(byte);
+    }
+}
+
+/// Perform the server side of the handshake on the given socket, checking the client's claimed
+/// UID against its peer credentials.
+///
+/// On success, the returned state can be used to check whether file descriptor passing was
+/// negotiated. Nothing after the client's `BEGIN` line is read from the socket.
+///
+/// This fails with `EPROTO` if the client doesn't start with a NUL byte, sends an overly long
+/// line, or doesn't finish the handshake in a reasonable number of lines.
+pub fn server_handshake<S>(sock: &mut S, guid: &str, allow_unix_fd: bool) -> io::Result<ServerAuth>
+where
+    S: crate::PeerSocket + Read + Write,
+{
+    let (uid, _) = sock.peer_ids()?;
+    let mut auth = ServerAuth::new(uid, guid).allow_unix_fd(allow_unix_fd);
+
+    let mut nul = 0;
+    sock.read_exact(std::slice::from_mut(&mut nul))?;
+    if nul != 0 {
+        return Err(io::Error::from_raw_os_error(libc::EPROTO));
+    }
+
+    for _ in 0..MAX_LINES {
+        let line = read_line(sock)?;
+
+        match auth.handle_line(&line) {
+            Some(reply) => sock.write_all(reply.as_bytes())?,
+            None => return Ok(auth),
+        }
+    }
+
+    Err(io::Error::from_raw_os_error(libc::EPROTO))
+}
+
+/// Perform the client side of the handshake on the given socket, claiming the current process's
+/// effective UID.
+///
+/// If `negotiate_unix_fd` is `true`, this asks the server to allow passing file descriptors (check
+/// [`ClientAuth::unix_fd_negotiated()`](./struct.ClientAuth.html#method.unix_fd_negotiated) to
+/// see if it agreed). Nothing after the server's last reply is read from the socket.
+pub fn client_handshake<S: Read + Write>(
+    sock: &mut S,
+    negotiate_unix_fd: bool,
+) -> io::Result<ClientAuth> {
+    let mut auth = ClientAuth::new(unsafe { libc::geteuid() }).negotiate_unix_fd(negotiate_unix_fd);
+
+    sock.write_all(&auth.start())?;
+
+    for _ in 0..MAX_LINES {
+        let line = read_line(sock)?;
+        let reply = auth.handle_line(&line)?;
+        sock.write_all(reply.as_bytes())?;
+
+        if auth.is_done() {
+            return Ok(auth);
+        }
+    }
+
+    Err(io::Error::from_raw_os_error(libc::EPROTO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_parse_uid() {
+        assert_eq!(hex_encode("1000"), "31303030");
+        assert_eq!(parse_uid("31303030"), Some(1000));
+        assert_eq!(parse_uid("30"), Some(0));
+
+        for s in ["", "3", "3g", "2d31", "31 30", "\u{e9}\u{e9}"] {
+            assert_eq!(parse_uid(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_server_auth() {
+        let mut auth = ServerAuth::new(1000, "abcd");
+
+        assert_eq!(
+            auth.handle_line("BEGIN").unwrap(),
+            "ERROR \"Unknown command\"\r\n"
+        );
+        assert_eq!(auth.handle_line("AUTH").unwrap(), REJECTED);
+        assert_eq!(auth.handle_line("AUTH ANONYMOUS").unwrap(), REJECTED);
+        // Wrong UID
+        assert_eq!(auth.handle_line("AUTH EXTERNAL 30").unwrap(), REJECTED);
+        assert!(!auth.is_authenticated());
+
+        // UID in the initial response
+        assert_eq!(
+            auth.handle_line("AUTH EXTERNAL 31303030").unwrap(),
+            "OK abcd\r\n"
+        );
+        assert!(auth.is_authenticated());
+        assert!(auth
+            .handle_line("NEGOTIATE_UNIX_FD")
+            .unwrap()
+            .starts_with("ERROR "));
+        assert!(!auth.unix_fd_negotiated());
+        assert_eq!(auth.handle_line("BEGIN"), None);
+        assert!(auth.is_done());
+
+        // UID in a DATA response
+        let mut auth = ServerAuth::new(1000, "abcd").allow_unix_fd(true);
+        assert_eq!(auth.handle_line("AUTH EXTERNAL").unwrap(), "DATA\r\n");
+        assert_eq!(auth.handle_line("DATA 31303030").unwrap(), "OK abcd\r\n");
+        assert_eq!(
+            auth.handle_line("NEGOTIATE_UNIX_FD").unwrap(),
+            "AGREE_UNIX_FD\r\n"
+        );
+        assert!(auth.unix_fd_negotiated());
+
+        // CANCEL starts over
+        assert_eq!(auth.handle_line("CANCEL").unwrap(), REJECTED);
+        assert!(!auth.is_authenticated());
+        assert!(!auth.unix_fd_negotiated());
+
+        // An empty response means "use the credentials"
+        assert_eq!(auth.handle_line("AUTH EXTERNAL").unwrap(), "DATA\r\n");
+        assert_eq!(auth.handle_line("DATA").unwrap(), "OK abcd\r\n");
+        assert_eq!(auth.handle_line("BEGIN"), None);
+    }
+
+    #[test]
+    fn test_client_auth() {
+        let mut auth = ClientAuth::new(1000).negotiate_unix_fd(true);
+        assert_eq!(auth.start(), b"\0AUTH EXTERNAL 31303030\r\n");
+        assert_eq!(auth.handle_line("DATA").unwrap(), "DATA 31303030\r\n");
+        assert_eq!(
+            auth.handle_line("OK abcd").unwrap(),
+            "NEGOTIATE_UNIX_FD\r\n"
+        );
+        assert_eq!(auth.guid(), Some("abcd"));
+        assert!(!auth.is_done());
+        assert_eq!(auth.handle_line("ERROR \"no\"").unwrap(), "BEGIN\r\n");
+        assert!(auth.is_done());
+        assert!(!auth.unix_fd_negotiated());
+
+        let mut auth = ClientAuth::new(1000);
+        auth.start();
+        assert_eq!(
+            auth.handle_line("REJECTED EXTERNAL")
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EACCES)
+        );
+        assert_eq!(
+            auth.handle_line("AGREE_UNIX_FD")
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EPROTO)
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        let thread = std::thread::spawn(move || {
+            let auth = client_handshake(&mut client, true).unwrap();
+            client.write_all(b"after").unwrap();
+            auth
+        });
+
+        let auth = server_handshake(&mut server, "abcd", false).unwrap();
+        assert!(auth.is_done());
+        assert!(!auth.unix_fd_negotiated());
+
+        let client_auth = thread.join().unwrap();
+        assert_eq!(client_auth.guid(), Some("abcd"));
+        assert!(!client_auth.unix_fd_negotiated());
+
+        // Data after BEGIN is left on the socket
+        let mut buf = [0; 5];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"after");
+    }
+
+    #[test]
+    fn test_handshake_rejected() {
+        let euid = unsafe { libc::geteuid() };
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+
+        let thread = std::thread::spawn(move || server_handshake(&mut server, "abcd", false));
+
+        // Claim to be someone else
+        let claim = hex_encode(&euid.wrapping_add(1).to_string());
+        write!(client, "\0AUTH EXTERNAL {}\r\n", claim).unwrap();
+        assert_eq!(read_line(&mut client).unwrap(), "REJECTED EXTERNAL");
+
+        // The client hangs up before authenticating
+        drop(client);
+        assert_eq!(
+            thread.join().unwrap().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        // Not starting with a NUL byte
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(b"AUTH EXTERNAL 30\r\n").unwrap();
+        assert_eq!(
+            server_handshake(&mut server, "abcd", false)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EPROTO)
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! The `scm` module provides access to credentials passed as `SCM_CREDENTIALS` ancillary messages
+//! (Linux-specific).
+//!
+//! Unlike `SO_PEERCRED`, which only works for connected sockets and returns the credentials from
+//! the time of the `connect()`/`socketpair()` call, `SCM_CREDENTIALS` credentials are attached to
+//! each individual message. This makes them the only way to get the sender's credentials on an
+//! unconnected datagram socket.
+//!
+//! The receiving socket must have `SO_PASSCRED` enabled (see [`set_passcred()`]); the kernel then
+//! attaches the sender's credentials to every message, even if the sender did not send any
+//! explicitly. A sender may also use [`send_with_creds()`] to send specific credentials, but the
+//! kernel will only allow this if the sender is privileged or the credentials are its own.
+//!
+//! [`set_passcred()`]: ./fn.set_passcred.html
+//! [`send_with_creds()`]: ./fn.send_with_creds.html
+
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::addr::UnixSocketName;
+use crate::ucred::Ucred;
+
+/// Large enough for one `SCM_CREDENTIALS` message and a few `SCM_RIGHTS` file descriptors (which
+/// are closed immediately if a sender passes any).
+const CMSG_BUFSIZE: usize = 256;
+
+#[repr(C, align(8))]
+struct CmsgBuf([u8; CMSG_BUFSIZE]);
+
+/// Enable or disable `SO_PASSCRED` on the given socket.
+pub fn set_passcred<S: AsRawFd>(sock: &S, passcred: bool) -> io::Result<()> {
+    let val = passcred as libc::c_int;
+
+    if unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            &val as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Check whether `SO_PASSCRED` is enabled on the given socket.
+pub fn get_passcred<S: AsRawFd>(sock: &S) -> io::Result<bool> {
+    let mut val: libc::c_int = 0;
+
+    unsafe {
+        crate::util::getsockopt_raw(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            std::slice::from_mut(&mut val),
+        )?;
+    }
+
+    Ok(val != 0)
+}
+
+unsafe fn recvmsg_raw(
+    sockfd: RawFd,
+    buf: &mut [u8],
+    addr: Option<&mut (libc::sockaddr_un, libc::socklen_t)>,
+) -> io::Result<(usize, Option<Ucred>, bool)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut cmsg_buf = CmsgBuf([0; CMSG_BUFSIZE]);
+
+    let mut msg: libc::msghdr = std::mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.0.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = CMSG_BUFSIZE as _;
+
+    let addr = addr.map(|(addr, len)| {
+        msg.msg_name = addr as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+        len
+    });
+
+    let n = loop {
+        let n = libc::recvmsg(sockfd, &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if n >= 0 {
+            break n as usize;
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    };
+
+    if let Some(len) = addr {
+        *len = msg.msg_namelen;
+    }
+
+    let mut cred = None;
+
+    let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+    while !cmsg.is_null() {
+        let data = libc::CMSG_DATA(cmsg);
+        let data_len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+
+        match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+            (libc::SOL_SOCKET, libc::SCM_CREDENTIALS)
+                if data_len >= std::mem::size_of::<Ucred>() =>
+            {
+                cred = Some(std::ptr::read_unaligned(data as *const Ucred));
+            }
+            (libc::SOL_SOCKET, libc::SCM_RIGHTS) => {
+                // Don't leak any file descriptors that the sender passed
+                let fds = data as *const RawFd;
+                for i in 0..data_len / std::mem::size_of::<RawFd>() {
+                    libc::close(std::ptr::read_unaligned(fds.add(i)));
+                }
+            }
+            _ => (),
+        }
+
+        cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+    }
+
+    Ok((n, cred, msg.msg_flags & libc::MSG_TRUNC != 0))
+}
+
+/// Receive data from the given socket, along with the sender's `SCM_CREDENTIALS` credentials.
+///
+/// Returns the number of bytes read and the credentials, which will be `None` if no credentials
+/// were attached (for example, if `SO_PASSCRED` was not enabled when the message was sent). Any
+/// file descriptors passed with `SCM_RIGHTS` are closed.
+#[inline]
+pub fn recv_with_creds<S: AsRawFd>(sock: &S, buf: &mut [u8]) -> io::Result<(usize, Option<Ucred>)> {
+    let (n, cred, _) = unsafe { recvmsg_raw(sock.as_raw_fd(), buf, None) }?;
+    Ok((n, cred))
+}
+
+/// Receive data from the given socket, along with the sender's `SCM_CREDENTIALS` credentials and
+/// address.
+///
+/// This is like [`recv_with_creds()`], but it also returns the address that the sender is bound
+/// to (or `None` if it is not bound). This is mainly useful for unconnected datagram sockets.
+///
+/// [`recv_with_creds()`]: ./fn.recv_with_creds.html
+pub fn recv_from_with_creds<S: AsRawFd>(
+    sock: &S,
+    buf: &mut [u8],
+) -> io::Result<(usize, Option<Ucred>, Option<UnixSocketName>)> {
+    let mut addr = (unsafe { std::mem::zeroed() }, 0);
+    let (n, cred, _) = unsafe { recvmsg_raw(sock.as_raw_fd(), buf, Some(&mut addr)) }?;
+    Ok((n, cred, UnixSocketName::from_sockaddr(&addr.0, addr.1)))
+}
+
+/// Receive a datagram from the given socket, along with the sender's `SCM_CREDENTIALS`
+/// credentials and whether the datagram was truncated to fit in `buf`.
+#[cfg(feature = "proxy")]
+pub(crate) fn recv_datagram_with_creds<S: AsRawFd>(
+    sock: &S,
+    buf: &mut [u8],
+) -> io::Result<(usize, Option<Ucred>, bool)> {
+    unsafe { recvmsg_raw(sock.as_raw_fd(), buf, None) }
+}
+
+unsafe fn sendmsg_raw(
+    sockfd: RawFd,
+    buf: &[u8],
+    cred: &Ucred,
+    addr: Option<&(libc::sockaddr_un, libc::socklen_t)>,
+) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut cmsg_buf = CmsgBuf([0; CMSG_BUFSIZE]);
+
+    let mut msg: libc::msghdr = std::mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.0.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<Ucred>() as u32) as _;
+
+    if let Some((addr, len)) = addr {
+        msg.msg_name = addr as *const _ as *mut libc::c_void;
+        msg.msg_namelen = *len;
+    }
+
+    let cmsg = libc::CMSG_FIRSTHDR(&msg);
+    (*cmsg).cmsg_level = libc::SOL_SOCKET;
+    (*cmsg).cmsg_type = libc::SCM_CREDENTIALS;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<Ucred>() as u32) as _;
+    std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut Ucred, cred.clone());
+
+    loop {
+        let n = libc::sendmsg(sockfd, &msg, libc::MSG_NOSIGNAL);
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Send data on the given (connected) socket, with the given credentials attached as an
+/// `SCM_CREDENTIALS` message.
+///
+/// Unless the process is privileged, the kernel only allows sending credentials whose PID is the
+/// current process's PID, and whose UID and GID match one of its real, effective, or saved IDs.
+/// Otherwise, this fails with `EPERM`.
+#[inline]
+pub fn send_with_creds<S: AsRawFd>(sock: &S, buf: &[u8], cred: &Ucred) -> io::Result<usize> {
+    unsafe { sendmsg_raw(sock.as_raw_fd(), buf, cred, None) }
+}
+
+/// Send data on the given socket to the given address, with the given credentials attached as an
+/// `SCM_CREDENTIALS` message.
+///
+/// See [`send_with_creds()`] for restrictions on the credentials that may be sent.
+///
+/// [`send_with_creds()`]: ./fn.send_with_creds.html
+pub fn send_to_with_creds<S: AsRawFd>(
+    sock: &S,
+    buf: &[u8],
+    addr: &UnixSocketName,
+    cred: &Ucred,
+) -> io::Result<usize> {
+    let addr = addr.to_sockaddr()?;
+    unsafe { sendmsg_raw(sock.as_raw_fd(), buf, cred, Some(&addr)) }
+}
+
+/// Get the credentials of the current process, in the form that the kernel fills in for
+/// `SCM_CREDENTIALS` (the PID and the *real* UID/GID).
+pub fn current_creds() -> Ucred {
+    unsafe {
+        Ucred {
+            pid: libc::getpid(),
+            uid: libc::getuid(),
+            gid: libc::getgid(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::{UnixDatagram, UnixStream};
+
+    #[test]
+    fn test_passcred() {
+        let (a, _b) = UnixStream::pair().unwrap();
+
+        assert!(!get_passcred(&a).unwrap());
+        set_passcred(&a, true).unwrap();
+        assert!(get_passcred(&a).unwrap());
+        set_passcred(&a, false).unwrap();
+        assert!(!get_passcred(&a).unwrap());
+    }
+
+    #[test]
+    fn test_stream_creds() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut buf = [0; 16];
+
+        // Without SO_PASSCRED, no credentials are attached
+        send_with_creds(&a, b"abc", &current_creds()).unwrap();
+        let (n, cred) = recv_with_creds(&b, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"abc");
+        assert_eq!(cred, None);
+
+        // With SO_PASSCRED, the kernel attaches them even if the sender doesn't
+        set_passcred(&b, true).unwrap();
+        std::io::Write::write_all(&mut &a, b"def").unwrap();
+        let (n, cred) = recv_with_creds(&b, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"def");
+        assert_eq!(cred, Some(current_creds()));
+    }
+
+    #[test]
+    fn test_datagram_creds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+
+        let server = UnixDatagram::bind(&path).unwrap();
+        set_passcred(&server, true).unwrap();
+        let mut buf = [0; 16];
+
+        // Unbound sender
+        let client = UnixDatagram::unbound().unwrap();
+        client.send_to(b"abc", &path).unwrap();
+        let (n, cred, addr) = recv_from_with_creds(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"abc");
+        assert_eq!(cred, Some(current_creds()));
+        assert_eq!(addr, None);
+
+        // Sender bound to a path, which we can reply to
+        let client_path = dir.path().join("client");
+        let client = UnixDatagram::bind(&client_path).unwrap();
+        send_to_with_creds(
+            &client,
+            b"def",
+            &UnixSocketName::Path(path.clone()),
+            &current_creds(),
+        )
+        .unwrap();
+        let (n, cred, addr) = recv_from_with_creds(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"def");
+        assert_eq!(cred, Some(current_creds()));
+        assert_eq!(addr, Some(UnixSocketName::Path(client_path)));
+
+        send_to_with_creds(&server, b"ghi", &addr.unwrap(), &current_creds()).unwrap();
+        let n = client.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ghi");
+    }
+
+    #[test]
+    fn test_send_invalid_creds() {
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut cred = current_creds();
+        cred.pid = 1;
+
+        assert_eq!(
+            send_with_creds(&a, b"abc", &cred)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EPERM)
+        );
+    }
+}
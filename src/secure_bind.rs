@@ -0,0 +1,371 @@
+//! The `secure_bind` module creates listening sockets with controlled ownership and permissions.
+//!
+//! Binding a socket with `UnixListener::bind()` and then fixing up its permissions leaves a window
+//! in which the socket is visible with whatever permissions the umask allowed. [`SecureBind`]
+//! instead binds the socket inside a private temporary directory, sets its owner, group, and
+//! mode, and only then renames it into place. A stale socket left at the path (one that nothing is
+//! listening on) is atomically replaced; anything else is left alone.
+//!
+//! Filesystem permissions are only a first line of defense; servers should still check
+//! [`get_peer_ids()`] on each connection.
+//!
+//! ```no_run
+//! use unix_cred::secure_bind::SecureBind;
+//!
+//! // Creates e.g. /run/user/1000/foo/foo.sock, or /run/foo/foo.sock for root
+//! let listener = SecureBind::in_runtime_dir("foo/foo.sock").mode(0o660).bind().unwrap();
+//! ```
+//!
+//! [`SecureBind`]: ./struct.SecureBind.html
+//! [`get_peer_ids()`]: ../fn.get_peer_ids.html
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn runtime_dir_from(xdg: Option<OsString>) -> PathBuf {
+    match xdg.map(PathBuf::from) {
+        // The spec says relative paths are invalid and should be ignored
+        Some(path) if path.is_absolute() => path,
+        _ => PathBuf::from("/run"),
+    }
+}
+
+/// Get the directory that runtime files (like sockets) should be created in.
+///
+/// This is `$XDG_RUNTIME_DIR` if it is set to an absolute path, or `/run` otherwise.
+pub fn runtime_dir() -> PathBuf {
+    runtime_dir_from(std::env::var_os("XDG_RUNTIME_DIR"))
+}
+
+/// Check that whatever is at `path` (if anything) is a stale socket that can be replaced.
+fn check_replaceable(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if !meta.file_type().is_socket() => {
+            Err(io::Error::from_raw_os_error(libc::EEXIST))
+        }
+        Ok(_) => match UnixStream::connect(path) {
+            // Nobody is listening on it
+            Err(e) if e.raw_os_error() == Some(libc::ECONNREFUSED) => Ok(()),
+            // It was removed in the meantime
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            _ => Err(io::Error::from_raw_os_error(libc::EADDRINUSE)),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A builder for creating listening Unix sockets with controlled ownership and permissions.
+///
+/// See the [module-level documentation](./index.html) for more information.
+#[derive(Clone, Debug)]
+pub struct SecureBind {
+    path: PathBuf,
+    mode: u32,
+    dir_mode: u32,
+    owner: Option<libc::uid_t>,
+    group: Option<libc::gid_t>,
+}
+
+impl SecureBind {
+    /// Create a new builder for a socket at the given path.
+    ///
+    /// By default, the socket is created with mode `0o600` and owned by the current process's
+    /// effective UID and GID.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            mode: 0o600,
+            dir_mode: 0o700,
+            owner: None,
+            group: None,
+        }
+    }
+
+    /// Create a new builder for a socket at the given path, relative to the
+    /// [runtime directory](./fn.runtime_dir.html).
+    pub fn in_runtime_dir<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(runtime_dir().join(path))
+    }
+
+    /// Set the mode of the socket (default `0o600`).
+    ///
+    /// Only the permission bits (`0o777`) are used. Connecting to a socket requires write
+    /// permission on it.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode & 0o777;
+        self
+    }
+
+    /// Set the mode of any parent directories that need to be created (default `0o700`).
+    ///
+    /// Existing directories are left alone.
+    pub fn dir_mode(mut self, mode: u32) -> Self {
+        self.dir_mode = mode & 0o7777;
+        self
+    }
+
+    /// Set the owner of the socket (changing the owner usually requires root privileges).
+    pub fn owner(mut self, uid: libc::uid_t) -> Self {
+        self.owner = Some(uid);
+        self
+    }
+
+    /// Set the group of the socket (the current process must usually be a member of the group).
+    pub fn group(mut self, gid: libc::gid_t) -> Self {
+        self.group = Some(gid);
+        self
+    }
+
+    /// Get the path that the socket will be created at.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn create_parent(&self, parent: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = parent
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.is_dir())
+            .collect();
+
+        for dir in missing.into_iter().rev() {
+            match fs::DirBuilder::new().mode(self.dir_mode).create(dir) {
+                // The umask may have masked out some of the bits
+                Ok(()) => fs::set_permissions(dir, fs::Permissions::from_mode(self.dir_mode))?,
+                // Someone else created it in the meantime; leave it alone
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn setup_tmp(&self, tmp_path: &Path) -> io::Result<()> {
+        if self.owner.is_some() || self.group.is_some() {
+            std::os::unix::fs::chown(tmp_path, self.owner, self.group)?;
+        }
+        fs::set_permissions(tmp_path, fs::Permissions::from_mode(self.mode))?;
+        check_replaceable(&self.path)?;
+        fs::rename(tmp_path, &self.path)
+    }
+
+    /// Create the socket, start listening on it, and move it into place.
+    ///
+    /// If something other than a socket exists at the path, this fails with `EEXIST`. If a socket
+    /// exists and something is still listening on it, this fails with `EADDRINUSE`.
+    ///
+    /// Note that since the socket was bound under a temporary name, the listener's
+    /// `local_addr()` will report that name, not the final path.
+    pub fn bind(&self) -> io::Result<UnixListener> {
+        self.bind_with(|path| UnixListener::bind(path))
+    }
+
+    /// Create a datagram socket and move it into place.
+    ///
+    /// As with [`bind()`](#method.bind), the socket's `local_addr()` will report the temporary
+    /// name, not the final path.
+    pub fn bind_datagram(&self) -> io::Result<UnixDatagram> {
+        self.bind_with(|path| UnixDatagram::bind(path))
+    }
+
+    fn bind_with<T, F: FnOnce(&Path) -> io::Result<T>>(&self, bind: F) -> io::Result<T> {
+        let parent = self.path.parent().unwrap_or_else(|| Path::new(""));
+        if self.path.file_name().is_none() {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        self.create_parent(parent)?;
+
+        // Nobody else can reach the socket until it's renamed out of this directory, whatever
+        // permissions it was created with. Keep the names short so they fit in sun_path.
+        let tmp_dir = parent.join(format!(
+            ".{}.{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::DirBuilder::new().mode(0o700).create(&tmp_dir)?;
+
+        let tmp_path = tmp_dir.join("s");
+        let res = bind(&tmp_path).and_then(|sock| {
+            self.setup_tmp(&tmp_path)?;
+            Ok(sock)
+        });
+
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        let _ = fs::remove_dir(&tmp_dir);
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_runtime_dir() {
+        assert_eq!(
+            runtime_dir_from(Some("/run/user/1000".into())),
+            Path::new("/run/user/1000")
+        );
+        assert_eq!(runtime_dir_from(Some("relative".into())), Path::new("/run"));
+        assert_eq!(runtime_dir_from(Some("".into())), Path::new("/run"));
+        assert_eq!(runtime_dir_from(None), Path::new("/run"));
+    }
+
+    #[test]
+    fn test_secure_bind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a/b/sock");
+
+        let listener = SecureBind::new(&path).bind().unwrap();
+
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o600);
+        assert_eq!(meta.uid(), unsafe { libc::geteuid() });
+        for sub in ["a", "a/b"] {
+            let meta = fs::metadata(dir.path().join(sub)).unwrap();
+            assert_eq!(meta.mode() & 0o7777, 0o700);
+        }
+
+        // Every directory that is created gets the full mode, regardless of the umask
+        let path2 = dir.path().join("c/d/sock");
+        let _listener2 = SecureBind::new(&path2).dir_mode(0o777).bind().unwrap();
+        for sub in ["c", "c/d"] {
+            let meta = fs::metadata(dir.path().join(sub)).unwrap();
+            assert_eq!(meta.mode() & 0o7777, 0o777);
+        }
+
+        let client = UnixStream::connect(&path).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        assert_eq!(
+            crate::get_peer_ids(&client).unwrap(),
+            crate::get_peer_ids(&server).unwrap()
+        );
+
+        // Only the socket is left behind
+        assert_eq!(fs::read_dir(dir.path().join("a/b")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_secure_bind_datagram() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+
+        let sock = SecureBind::new(&path).mode(0o620).bind_datagram().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o620);
+
+        UnixDatagram::unbound()
+            .unwrap()
+            .send_to(b"abc", &path)
+            .unwrap();
+        let mut buf = [0; 3];
+        assert_eq!(sock.recv(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"abc");
+    }
+
+    #[test]
+    fn test_secure_bind_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+
+        let gid = unsafe { libc::getegid() };
+        let dir_mode = fs::metadata(dir.path()).unwrap().mode();
+
+        // A stale socket
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(UnixStream::connect(&path).is_err());
+
+        let _listener = SecureBind::new(&path)
+            .mode(0o660)
+            .group(gid)
+            .bind()
+            .unwrap();
+        UnixStream::connect(&path).unwrap();
+
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o660);
+        assert_eq!(meta.gid(), gid);
+        // Existing directories are left alone
+        assert_eq!(fs::metadata(dir.path()).unwrap().mode(), dir_mode);
+
+        assert_eq!(
+            SecureBind::new("/").bind().unwrap_err().raw_os_error(),
+            Some(libc::EINVAL)
+        );
+
+        // Nothing is left behind if binding fails
+        let err = SecureBind::new(&path)
+            .bind_with(|_| Err::<(), _>(io::Error::from_raw_os_error(libc::EADDRINUSE)))
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EADDRINUSE));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_secure_bind_no_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+
+        // A socket that is still in use
+        let _listener = UnixListener::bind(&path).unwrap();
+        let ino = fs::symlink_metadata(&path).unwrap().ino();
+        assert_eq!(
+            SecureBind::new(&path).bind().unwrap_err().raw_os_error(),
+            Some(libc::EADDRINUSE)
+        );
+        assert_eq!(
+            SecureBind::new(&path)
+                .bind_datagram()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EADDRINUSE)
+        );
+        assert_eq!(fs::symlink_metadata(&path).unwrap().ino(), ino);
+
+        // A live datagram socket
+        let dgram_path = dir.path().join("dgram");
+        let _dgram = UnixDatagram::bind(&dgram_path).unwrap();
+        assert_eq!(
+            SecureBind::new(&dgram_path)
+                .bind_datagram()
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EADDRINUSE)
+        );
+
+        // Something other than a socket
+        let file = dir.path().join("file");
+        fs::write(&file, b"abc").unwrap();
+        assert_eq!(
+            SecureBind::new(&file).bind().unwrap_err().raw_os_error(),
+            Some(libc::EEXIST)
+        );
+        assert_eq!(fs::read(&file).unwrap(), b"abc");
+
+        let subdir = dir.path().join("subdir");
+        fs::create_dir(&subdir).unwrap();
+        assert_eq!(
+            SecureBind::new(&subdir).bind().unwrap_err().raw_os_error(),
+            Some(libc::EEXIST)
+        );
+        assert!(subdir.is_dir());
+
+        // Nothing is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+    }
+}
@@ -0,0 +1,715 @@
+//! The `selinux` module provides a parser for SELinux security contexts, along with support for
+//! matching them against patterns.
+//!
+//! [`get_peer_selinux_context()`] retrieves the context of a socket's peer using `SO_PEERSEC` (see
+//! [`lsm::get_peersec()`]) and parses it. The parsed context can then be checked against a
+//! [`SelinuxPattern`]:
+//!
+//! ```
+//! use unix_cred::selinux::{SelinuxContext, SelinuxPattern};
+//!
+//! let pattern: SelinuxPattern = "*:*:container_t:s0:c0.c1023".parse().unwrap();
+//!
+//! let ctx: SelinuxContext = "system_u:system_r:container_t:s0:c1,c2".parse().unwrap();
+//! assert!(pattern.matches(&ctx));
+//!
+//! let ctx: SelinuxContext = "system_u:system_r:init_t:s0".parse().unwrap();
+//! assert!(!pattern.matches(&ctx));
+//! ```
+//!
+//! [`get_peer_selinux_context()`]: ./fn.get_peer_selinux_context.html
+//! [`lsm::get_peersec()`]: ../lsm/fn.get_peersec.html
+//! [`SelinuxPattern`]: ./struct.SelinuxPattern.html
+
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::str::FromStr;
+
+#[inline]
+fn einval() -> io::Error {
+    io::Error::from_raw_os_error(libc::EINVAL)
+}
+
+fn parse_prefixed_int(s: &str, prefix: char) -> io::Result<u32> {
+    let digits = s.strip_prefix(prefix).ok_or_else(einval)?;
+
+    if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(einval());
+    }
+
+    digits.parse().map_err(|_| einval())
+}
+
+/// A set of MLS categories.
+///
+/// The categories are stored as sorted, non-overlapping inclusive ranges, so large ranges like
+/// `c0.c1023` don't need to be expanded.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CategorySet {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl CategorySet {
+    /// Create an empty set.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the categories from `start` through `end` (inclusive) to the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is greater than `end`.
+    pub fn insert_range(&mut self, start: u32, end: u32) {
+        assert!(start <= end);
+
+        // Merge with any ranges that overlap or are adjacent to the new one
+        let i = self
+            .ranges
+            .partition_point(|&(_, e)| e.saturating_add(1) < start);
+        let mut j = i;
+        let (mut start, mut end) = (start, end);
+        while j < self.ranges.len() && self.ranges[j].0 <= end.saturating_add(1) {
+            start = start.min(self.ranges[j].0);
+            end = end.max(self.ranges[j].1);
+            j += 1;
+        }
+
+        self.ranges.splice(i..j, std::iter::once((start, end)));
+    }
+
+    /// Add a single category to the set.
+    #[inline]
+    pub fn insert(&mut self, cat: u32) {
+        self.insert_range(cat, cat);
+    }
+
+    /// Check whether the set contains the given category.
+    pub fn contains(&self, cat: u32) -> bool {
+        let i = self.ranges.partition_point(|&(_, e)| e < cat);
+        self.ranges.get(i).is_some_and(|&(s, _)| s <= cat)
+    }
+
+    /// Check whether this set contains every category in `other`.
+    pub fn is_superset(&self, other: &Self) -> bool {
+        // Adjacent ranges are always merged, so each of the other set's ranges must fit within a
+        // single one of ours
+        other.ranges.iter().all(|&(start, end)| {
+            let i = self.ranges.partition_point(|&(_, e)| e < start);
+            self.ranges
+                .get(i)
+                .is_some_and(|&(s, e)| s <= start && end <= e)
+        })
+    }
+
+    /// Check whether the set is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Get the number of categories in the set.
+    pub fn len(&self) -> u64 {
+        self.ranges.iter().map(|&(s, e)| (e - s) as u64 + 1).sum()
+    }
+
+    /// Get the ranges of categories in the set, as sorted `(start, end)` pairs (inclusive).
+    /// Overlapping and adjacent ranges are merged.
+    #[inline]
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+}
+
+impl std::iter::FromIterator<u32> for CategorySet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for cat in iter {
+            set.insert(cat);
+        }
+        set
+    }
+}
+
+/// Represents an MLS/MCS security level (for example, `s0:c0,c5.c10`).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MlsLevel {
+    /// The sensitivity (for example, 0 for `s0`).
+    pub sensitivity: u32,
+    /// The set of categories.
+    pub categories: CategorySet,
+}
+
+impl MlsLevel {
+    /// Check whether this level dominates `other`.
+    ///
+    /// A level dominates another level if its sensitivity is greater than or equal to the other
+    /// level's sensitivity and its categories are a superset of the other level's categories.
+    #[inline]
+    pub fn dominates(&self, other: &Self) -> bool {
+        self.sensitivity >= other.sensitivity && self.categories.is_superset(&other.categories)
+    }
+}
+
+impl FromStr for MlsLevel {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (sens, cats) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        let sensitivity = parse_prefixed_int(sens, 's')?;
+        let mut categories = CategorySet::new();
+
+        if let Some(cats) = cats {
+            for item in cats.split(',') {
+                let (start, end) = match item.find('.') {
+                    Some(i) => (
+                        parse_prefixed_int(&item[..i], 'c')?,
+                        parse_prefixed_int(&item[i + 1..], 'c')?,
+                    ),
+                    None => {
+                        let cat = parse_prefixed_int(item, 'c')?;
+                        (cat, cat)
+                    }
+                };
+
+                if start > end {
+                    return Err(einval());
+                }
+
+                categories.insert_range(start, end);
+            }
+        }
+
+        Ok(Self {
+            sensitivity,
+            categories,
+        })
+    }
+}
+
+impl fmt::Display for MlsLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "s{}", self.sensitivity)?;
+
+        let mut sep = ':';
+
+        for &(start, end) in self.categories.ranges() {
+            // Like the kernel, only use the "cX.cY" syntax for runs of 3 or more categories
+            match end - start {
+                0 => write!(f, "{}c{}", sep, start)?,
+                1 => write!(f, "{}c{},c{}", sep, start, end)?,
+                _ => write!(f, "{}c{}.c{}", sep, start, end)?,
+            }
+
+            sep = ',';
+        }
+
+        Ok(())
+    }
+}
+
+/// Represents an MLS/MCS security range (for example, `s0-s0:c0.c1023`).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MlsRange {
+    /// The low level of the range.
+    pub low: MlsLevel,
+    /// The high level of the range. This always dominates the low level.
+    pub high: MlsLevel,
+}
+
+impl MlsRange {
+    /// Check whether this range contains `other`.
+    ///
+    /// A range contains another range if its high level dominates the other range's high level,
+    /// and its low level is dominated by the other range's low level.
+    #[inline]
+    pub fn contains(&self, other: &Self) -> bool {
+        self.high.dominates(&other.high) && other.low.dominates(&self.low)
+    }
+}
+
+impl FromStr for MlsRange {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (low, high) = match s.find('-') {
+            Some(i) => (s[..i].parse()?, s[i + 1..].parse()?),
+            None => {
+                let level: MlsLevel = s.parse()?;
+                (level.clone(), level)
+            }
+        };
+
+        if !MlsLevel::dominates(&high, &low) {
+            return Err(einval());
+        }
+
+        Ok(Self { low, high })
+    }
+}
+
+impl fmt::Display for MlsRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.low == self.high {
+            write!(f, "{}", self.low)
+        } else {
+            write!(f, "{}-{}", self.low, self.high)
+        }
+    }
+}
+
+/// Represents a parsed SELinux security context (for example,
+/// `system_u:system_r:container_t:s0:c1,c2`).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SelinuxContext {
+    /// The SELinux user.
+    pub user: String,
+    /// The role.
+    pub role: String,
+    /// The type (or domain).
+    pub type_: String,
+    /// The MLS/MCS range, or `None` if the context does not include one (i.e. MLS is disabled).
+    pub range: Option<MlsRange>,
+}
+
+fn split_context(s: &str) -> io::Result<(&str, &str, &str, Option<&str>)> {
+    let mut it = s.splitn(4, ':');
+
+    let user = it.next().unwrap_or("");
+    let role = it.next().ok_or_else(einval)?;
+    let type_ = it.next().ok_or_else(einval)?;
+    let range = it.next();
+
+    if user.is_empty() || role.is_empty() || type_.is_empty() {
+        return Err(einval());
+    }
+
+    Ok((user, role, type_, range))
+}
+
+impl FromStr for SelinuxContext {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (user, role, type_, range) = split_context(s)?;
+
+        Ok(Self {
+            user: user.into(),
+            role: role.into(),
+            type_: type_.into(),
+            range: range.map(str::parse).transpose()?,
+        })
+    }
+}
+
+impl fmt::Display for SelinuxContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.user, self.role, self.type_)?;
+
+        if let Some(range) = self.range.as_ref() {
+            write!(f, ":{}", range)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Match `s` against a simple glob pattern, in which `*` matches any sequence of characters.
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    // There is always at least one part
+    let first = parts.next().unwrap();
+    let mut rest = match s.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let mut parts: Vec<&str> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        // No '*' in the pattern
+        None => return rest.is_empty(),
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// A pattern that can be matched against [`SelinuxContext`]s.
+///
+/// Patterns use the same `user:role:type[:range]` syntax as contexts. The user, role, and type may
+/// contain `*` wildcards (so `*` matches anything, and `svirt_*` matches any type starting with
+/// `svirt_`). If a range is given, a context only matches if its range is contained within the
+/// pattern's range (see [`MlsRange::contains()`]); if no range is given, any range matches.
+///
+/// If a single level is given instead of a range, it is treated as an upper bound. For example,
+/// `*:*:container_t:s0:c0.c1023` is equivalent to `*:*:container_t:s0-s0:c0.c1023`, so it matches
+/// `container_t` at any category set within `c0.c1023`.
+///
+/// [`SelinuxContext`]: ./struct.SelinuxContext.html
+/// [`MlsRange::contains()`]: ./struct.MlsRange.html#method.contains
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SelinuxPattern {
+    user: String,
+    role: String,
+    type_: String,
+    range: Option<MlsRange>,
+}
+
+impl SelinuxPattern {
+    /// Check whether the given context matches this pattern.
+    pub fn matches(&self, ctx: &SelinuxContext) -> bool {
+        if !glob_match(&self.user, &ctx.user)
+            || !glob_match(&self.role, &ctx.role)
+            || !glob_match(&self.type_, &ctx.type_)
+        {
+            return false;
+        }
+
+        match (self.range.as_ref(), ctx.range.as_ref()) {
+            (None, _) => true,
+            (Some(prange), Some(crange)) => prange.contains(crange),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl FromStr for SelinuxPattern {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (user, role, type_, range) = split_context(s)?;
+
+        let range = match range {
+            // A single level is treated as an upper bound
+            Some(range) if !range.contains('-') => Some(MlsRange {
+                low: MlsLevel {
+                    sensitivity: 0,
+                    categories: CategorySet::new(),
+                },
+                high: range.parse()?,
+            }),
+            range => range.map(str::parse).transpose()?,
+        };
+
+        Ok(Self {
+            user: user.into(),
+            role: role.into(),
+            type_: type_.into(),
+            range,
+        })
+    }
+}
+
+impl fmt::Display for SelinuxPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.user, self.role, self.type_)?;
+
+        if let Some(range) = self.range.as_ref() {
+            write!(f, ":{}", range)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the SELinux context of the given socket's peer.
+///
+/// The context is retrieved using `SO_PEERSEC` (see [`lsm::get_peersec()`]). If SELinux is not
+/// active, this may fail with `ENOPROTOOPT`, or (if another LSM is providing `SO_PEERSEC`) with
+/// `EINVAL` because the label could not be parsed.
+///
+/// [`lsm::get_peersec()`]: ../lsm/fn.get_peersec.html
+#[inline]
+pub fn get_peer_selinux_context(sock: &UnixStream) -> io::Result<SelinuxContext> {
+    crate::lsm::get_peersec(sock)?.parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(sensitivity: u32, categories: &[u32]) -> MlsLevel {
+        MlsLevel {
+            sensitivity,
+            categories: categories.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!("s0".parse::<MlsLevel>().unwrap(), level(0, &[]));
+        assert_eq!("s15:c3".parse::<MlsLevel>().unwrap(), level(15, &[3]));
+        assert_eq!(
+            "s0:c0,c5.c8,c2".parse::<MlsLevel>().unwrap(),
+            level(0, &[0, 2, 5, 6, 7, 8])
+        );
+        assert_eq!(
+            "s0:c0.c1023".parse::<MlsLevel>().unwrap().categories.len(),
+            1024
+        );
+
+        // Policies may define more than 1024 categories, and huge ranges aren't expanded
+        assert_eq!(
+            "s0:c0.c4294967295"
+                .parse::<MlsLevel>()
+                .unwrap()
+                .categories
+                .len(),
+            1 << 32
+        );
+        assert_eq!(
+            "s0:c5000.c5002,c4999".parse::<MlsLevel>().unwrap(),
+            level(0, &[4999, 5000, 5001, 5002])
+        );
+
+        for s in [
+            "",
+            "s",
+            "0",
+            "s0:",
+            "s0:c",
+            "s0:0",
+            "s0:c1.",
+            "s0:c5.c1",
+            "s0:c1,",
+            "s+1",
+            "x0",
+            "s0:c1:c2",
+            "s0:c4294967296",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                s.parse::<MlsLevel>().unwrap_err().raw_os_error(),
+                Some(libc::EINVAL),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_level_display() {
+        for (s, expected) in [
+            ("s0", "s0"),
+            ("s0:c1", "s0:c1"),
+            ("s0:c1,c2", "s0:c1,c2"),
+            ("s0:c1.c3", "s0:c1.c3"),
+            ("s0:c5,c0.c2,c7,c8", "s0:c0.c2,c5,c7,c8"),
+            ("s2:c0.c1023", "s2:c0.c1023"),
+            ("s0:c1,c0,c2.c5,c3", "s0:c0.c5"),
+            ("s0:c2000.c3000,c3001", "s0:c2000.c3001"),
+        ]
+        .iter()
+        {
+            assert_eq!(s.parse::<MlsLevel>().unwrap().to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_dominates() {
+        assert!(level(0, &[]).dominates(&level(0, &[])));
+        assert!(level(1, &[]).dominates(&level(0, &[])));
+        assert!(!level(0, &[]).dominates(&level(1, &[])));
+        assert!(level(0, &[1, 2]).dominates(&level(0, &[1])));
+        assert!(!level(0, &[1]).dominates(&level(0, &[1, 2])));
+        assert!(!level(1, &[1]).dominates(&level(0, &[2])));
+        assert!(!level(0, &[1]).dominates(&level(0, &[2])));
+
+        let level = |s: &str| s.parse::<MlsLevel>().unwrap();
+        assert!(level("s0:c0.c2047").dominates(&level("s0:c1024.c1500,c2047")));
+        assert!(level("s0:c0.c10,c11.c20").dominates(&level("s0:c5.c15")));
+        assert!(!level("s0:c0.c10,c12.c20").dominates(&level("s0:c5.c15")));
+        assert!(!level("s0:c0.c1023").dominates(&level("s0:c1024")));
+    }
+
+    #[test]
+    fn test_category_set() {
+        let mut set = CategorySet::new();
+        assert!(set.is_empty());
+
+        set.insert_range(10, 20);
+        set.insert(5);
+        set.insert_range(22, 30);
+        assert_eq!(set.ranges(), [(5, 5), (10, 20), (22, 30)]);
+        set.insert(21);
+        assert_eq!(set.ranges(), [(5, 5), (10, 30)]);
+        set.insert_range(0, 12);
+        assert_eq!(set.ranges(), [(0, 30)]);
+        set.insert_range(u32::MAX - 1, u32::MAX);
+        assert_eq!(set.ranges(), [(0, 30), (u32::MAX - 1, u32::MAX)]);
+
+        assert!(set.contains(0));
+        assert!(set.contains(30));
+        assert!(!set.contains(31));
+        assert!(set.contains(u32::MAX));
+        assert_eq!(set.len(), 33);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let range: MlsRange = "s0-s0:c0.c1023".parse().unwrap();
+        assert_eq!(range.low, level(0, &[]));
+        assert_eq!(range.high.categories.len(), 1024);
+        assert_eq!(range.to_string(), "s0-s0:c0.c1023");
+
+        let range: MlsRange = "s0:c1,c2".parse().unwrap();
+        assert_eq!(range.low, range.high);
+        assert_eq!(range.to_string(), "s0:c1,c2");
+
+        // The high level must dominate the low level
+        for s in ["s1-s0", "s0:c1-s0:c2", "s0-", "-s0", "s0-s0-s0"].iter() {
+            assert_eq!(
+                s.parse::<MlsRange>().unwrap_err().raw_os_error(),
+                Some(libc::EINVAL),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_range_contains() {
+        let full: MlsRange = "s0-s0:c0.c1023".parse().unwrap();
+
+        assert!(full.contains(&full));
+        assert!(full.contains(&"s0".parse().unwrap()));
+        assert!(full.contains(&"s0:c1,c2".parse().unwrap()));
+        assert!(full.contains(&"s0-s0:c1,c2".parse().unwrap()));
+        assert!(!"s0-s0:c0.c511"
+            .parse::<MlsRange>()
+            .unwrap()
+            .contains(&"s0:c512".parse().unwrap()));
+        assert!(!full.contains(&"s1".parse().unwrap()));
+
+        let cats: MlsRange = "s0:c1,c2".parse().unwrap();
+        assert!(cats.contains(&cats));
+        assert!(!cats.contains(&"s0".parse().unwrap()));
+        assert!(!cats.contains(&"s0:c1".parse().unwrap()));
+        assert!(!cats.contains(&full));
+    }
+
+    #[test]
+    fn test_parse_context() {
+        let ctx: SelinuxContext = "system_u:system_r:container_t:s0:c1,c2".parse().unwrap();
+        assert_eq!(ctx.user, "system_u");
+        assert_eq!(ctx.role, "system_r");
+        assert_eq!(ctx.type_, "container_t");
+        assert_eq!(ctx.range, Some("s0:c1,c2".parse().unwrap()));
+        assert_eq!(ctx.to_string(), "system_u:system_r:container_t:s0:c1,c2");
+
+        let ctx: SelinuxContext = "unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023"
+            .parse()
+            .unwrap();
+        assert_eq!(ctx.range, Some("s0-s0:c0.c1023".parse().unwrap()));
+        assert_eq!(
+            ctx.to_string(),
+            "unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023"
+        );
+
+        let ctx: SelinuxContext = "user_u:user_r:user_t".parse().unwrap();
+        assert_eq!(ctx.range, None);
+        assert_eq!(ctx.to_string(), "user_u:user_r:user_t");
+
+        for s in [
+            "",
+            "kernel",
+            "a:b",
+            "a::c",
+            ":b:c",
+            "a:b:",
+            "a:b:c:",
+            "a:b:c:s0:c",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                s.parse::<SelinuxContext>().unwrap_err().raw_os_error(),
+                Some(libc::EINVAL),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "abc"));
+        assert!(glob_match("abc", "abc"));
+        assert!(!glob_match("abc", "abcd"));
+        assert!(!glob_match("abc", "ab"));
+        assert!(glob_match("svirt_*", "svirt_lxc_net_t"));
+        assert!(!glob_match("svirt_*", "container_t"));
+        assert!(glob_match("*_t", "container_t"));
+        assert!(glob_match("a*b*c", "aXbYc"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(!glob_match("a*b*c", "acb"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let ctx: SelinuxContext = "system_u:system_r:container_t:s0:c1,c2".parse().unwrap();
+
+        for (pattern, expected) in [
+            ("*:*:*", true),
+            ("*:*:container_t", true),
+            ("*:*:init_t", false),
+            ("system_u:system_r:container_t:s0:c1,c2", true),
+            ("*:*:container_t:s0-s0:c0.c1023", true),
+            ("*:*:container_t:s0-s0:c1,c2", true),
+            ("*:*:container_t:s0:c1", false),
+            ("*:*:container_t:s0:c3,c4", false),
+            ("*:*:container_t:s0:c1-s0:c0.c1023", true),
+            ("*:*:container_t:s0:c1,c3-s0:c0.c1023", false),
+            ("*:*:container_*:s0:c0.c3", true),
+            ("*_u:*_r:*", true),
+            ("user_u:*:*", false),
+            ("*:object_r:*", false),
+        ]
+        .iter()
+        {
+            let pattern: SelinuxPattern = pattern.parse().unwrap();
+            assert_eq!(pattern.matches(&ctx), *expected, "{}", pattern);
+        }
+
+        // A pattern with a range never matches a context without one
+        let ctx: SelinuxContext = "user_u:user_r:user_t".parse().unwrap();
+        assert!("*:*:*".parse::<SelinuxPattern>().unwrap().matches(&ctx));
+        assert!(!"*:*:*:s0".parse::<SelinuxPattern>().unwrap().matches(&ctx));
+    }
+
+    #[test]
+    fn test_get_peer_selinux_context() {
+        let (a, _b) = UnixStream::pair().unwrap();
+
+        match crate::lsm::get_peersec(&a) {
+            Ok(label) => match label.parse::<SelinuxContext>() {
+                Ok(ctx) => assert_eq!(get_peer_selinux_context(&a).unwrap(), ctx),
+                Err(_) => assert_eq!(
+                    get_peer_selinux_context(&a).unwrap_err().raw_os_error(),
+                    Some(libc::EINVAL)
+                ),
+            },
+            Err(e) => assert_eq!(
+                get_peer_selinux_context(&a).unwrap_err().raw_os_error(),
+                e.raw_os_error()
+            ),
+        }
+    }
+}
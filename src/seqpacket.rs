@@ -0,0 +1,391 @@
+//! The `seqpacket` module provides `SOCK_SEQPACKET` Unix sockets (Linux-specific), which the
+//! standard library does not support.
+//!
+//! `SOCK_SEQPACKET` sockets are connection-oriented like `SOCK_STREAM` sockets, but they preserve
+//! message boundaries like `SOCK_DGRAM` sockets. Peer credentials work exactly like they do for
+//! stream sockets: [`UnixSeqpacket`] implements [`PeerSocket`], whose methods are equivalent to
+//! [`get_peer_ids()`], [`get_peer_pid_ids()`], etc. It can also be passed to the functions in the
+//! [`scm`] module to send and receive `SCM_CREDENTIALS` messages.
+//!
+//! Addresses are given as [`UnixSocketName`]s, so both paths and abstract names are supported.
+//!
+//! ```
+//! use unix_cred::seqpacket::UnixSeqpacket;
+//! use unix_cred::PeerSocket;
+//!
+//! let (a, b) = UnixSeqpacket::pair().unwrap();
+//! a.send(b"hello").unwrap();
+//! a.send(b"world").unwrap();
+//!
+//! let mut buf = [0; 16];
+//! assert_eq!(b.recv(&mut buf).unwrap(), 5);
+//! assert_eq!(&buf[..5], b"hello");
+//!
+//! let (uid, _gid) = b.peer_ids().unwrap();
+//! assert_eq!(uid, unsafe { libc::geteuid() });
+//! ```
+//!
+//! [`UnixSeqpacket`]: ./struct.UnixSeqpacket.html
+//! [`PeerSocket`]: ../trait.PeerSocket.html
+//! [`get_peer_ids()`]: ../fn.get_peer_ids.html
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+//! [`scm`]: ../scm/index.html
+//! [`UnixSocketName`]: ../addr/enum.UnixSocketName.html
+
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::prelude::*;
+use std::path::Path;
+
+use crate::addr::UnixSocketName;
+use crate::sock;
+use crate::ucred::Ucred;
+
+/// A connected `SOCK_SEQPACKET` Unix socket.
+#[derive(Debug)]
+pub struct UnixSeqpacket(OwnedFd);
+
+impl UnixSeqpacket {
+    /// Connect to the socket at the given path.
+    #[inline]
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::connect_addr(&UnixSocketName::Path(path.as_ref().into()))
+    }
+
+    /// Connect to the socket at the given address.
+    #[inline]
+    pub fn connect_addr(addr: &UnixSocketName) -> io::Result<Self> {
+        sock::connect_new(libc::SOCK_SEQPACKET, addr).map(Self)
+    }
+
+    /// Create an unnamed pair of connected sockets.
+    #[inline]
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let (a, b) = sock::socketpair(libc::SOCK_SEQPACKET)?;
+        Ok((Self(a), Self(b)))
+    }
+
+    /// Send a message on the socket, returning the number of bytes written.
+    ///
+    /// This never raises `SIGPIPE`; if the peer has closed the connection, it fails with `EPIPE`.
+    #[inline]
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        sock::send(self.0.as_fd(), buf)
+    }
+
+    /// Receive a message from the socket, returning the number of bytes read.
+    ///
+    /// If the message is larger than `buf`, the rest of it is discarded. A return value of 0
+    /// means that the peer has closed the connection (or sent an empty message).
+    #[inline]
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        sock::recv(self.0.as_fd(), buf, 0)
+    }
+
+    /// Receive a message from the socket without removing it from the queue.
+    #[inline]
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        sock::recv(self.0.as_fd(), buf, libc::MSG_PEEK)
+    }
+
+    /// Send a message with the given credentials attached as an `SCM_CREDENTIALS` message.
+    ///
+    /// This is equivalent to [`scm::send_with_creds()`](../scm/fn.send_with_creds.html).
+    #[inline]
+    pub fn send_with_creds(&self, buf: &[u8], cred: &Ucred) -> io::Result<usize> {
+        crate::scm::send_with_creds(self, buf, cred)
+    }
+
+    /// Receive a message along with the sender's `SCM_CREDENTIALS` credentials.
+    ///
+    /// This is equivalent to [`scm::recv_with_creds()`](../scm/fn.recv_with_creds.html).
+    #[inline]
+    pub fn recv_with_creds(&self, buf: &mut [u8]) -> io::Result<(usize, Option<Ucred>)> {
+        crate::scm::recv_with_creds(self, buf)
+    }
+
+    /// Get the address that this socket is bound to, or `None` if it is unnamed.
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<Option<UnixSocketName>> {
+        sock::local_addr(self.0.as_fd())
+    }
+
+    /// Get the address of this socket's peer, or `None` if it is unnamed.
+    #[inline]
+    pub fn peer_addr(&self) -> io::Result<Option<UnixSocketName>> {
+        sock::peer_addr(self.0.as_fd())
+    }
+
+    /// Shut down the read half, write half, or both halves of the connection.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let how = match how {
+            Shutdown::Read => libc::SHUT_RD,
+            Shutdown::Write => libc::SHUT_WR,
+            Shutdown::Both => libc::SHUT_RDWR,
+        };
+
+        if unsafe { libc::shutdown(self.0.as_raw_fd(), how) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Move the socket into or out of non-blocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        sock::set_nonblocking(self.0.as_fd(), nonblocking)
+    }
+
+    /// Create a new independently owned handle to the same socket.
+    #[inline]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        self.0.try_clone().map(Self)
+    }
+}
+
+impl AsRawFd for UnixSeqpacket {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsFd for UnixSeqpacket {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl IntoRawFd for UnixSeqpacket {
+    #[inline]
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl FromRawFd for UnixSeqpacket {
+    #[inline]
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+impl From<OwnedFd> for UnixSeqpacket {
+    #[inline]
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
+}
+
+impl From<UnixSeqpacket> for OwnedFd {
+    #[inline]
+    fn from(sock: UnixSeqpacket) -> Self {
+        sock.0
+    }
+}
+
+impl crate::private::Sealed for UnixSeqpacket {}
+impl crate::PeerSocket for UnixSeqpacket {}
+
+/// A `SOCK_SEQPACKET` Unix socket that is listening for connections.
+#[derive(Debug)]
+pub struct UnixSeqpacketListener(OwnedFd);
+
+impl UnixSeqpacketListener {
+    /// Create a new socket bound to the given path, and start listening on it.
+    #[inline]
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::bind_addr(&UnixSocketName::Path(path.as_ref().into()))
+    }
+
+    /// Create a new socket bound to the given address, and start listening on it.
+    #[inline]
+    pub fn bind_addr(addr: &UnixSocketName) -> io::Result<Self> {
+        sock::bind_new(libc::SOCK_SEQPACKET, addr).map(Self)
+    }
+
+    /// Accept a new connection, returning the connected socket and the address of the peer (or
+    /// `None` if it is unnamed, which is usually the case).
+    #[inline]
+    pub fn accept(&self) -> io::Result<(UnixSeqpacket, Option<UnixSocketName>)> {
+        let (fd, addr) = sock::accept(self.0.as_fd())?;
+        Ok((UnixSeqpacket(fd), addr))
+    }
+
+    /// Return an iterator over incoming connections.
+    ///
+    /// The iterator never returns `None`.
+    #[inline]
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<UnixSeqpacket>> + '_ {
+        std::iter::repeat_with(move || self.accept().map(|(sock, _)| sock))
+    }
+
+    /// Get the address that this socket is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<Option<UnixSocketName>> {
+        sock::local_addr(self.0.as_fd())
+    }
+
+    /// Move the socket into or out of non-blocking mode.
+    #[inline]
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        sock::set_nonblocking(self.0.as_fd(), nonblocking)
+    }
+
+    /// Create a new independently owned handle to the same socket.
+    #[inline]
+    pub fn try_clone(&self) -> io::Result<Self> {
+        self.0.try_clone().map(Self)
+    }
+}
+
+impl AsRawFd for UnixSeqpacketListener {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl AsFd for UnixSeqpacketListener {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl IntoRawFd for UnixSeqpacketListener {
+    #[inline]
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+impl FromRawFd for UnixSeqpacketListener {
+    #[inline]
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+impl From<OwnedFd> for UnixSeqpacketListener {
+    #[inline]
+    fn from(fd: OwnedFd) -> Self {
+        Self(fd)
+    }
+}
+
+impl From<UnixSeqpacketListener> for OwnedFd {
+    #[inline]
+    fn from(listener: UnixSeqpacketListener) -> Self {
+        listener.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::PeerSocket;
+
+    #[test]
+    fn test_pair() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+
+        a.send(b"abc").unwrap();
+        a.send(b"defgh").unwrap();
+
+        // Message boundaries are preserved, and truncated messages are discarded
+        let mut buf = [0; 4];
+        assert_eq!(b.peek(&mut buf).unwrap(), 3);
+        assert_eq!(b.recv(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(b.recv(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"defg");
+
+        assert_eq!(a.local_addr().unwrap(), None);
+        assert_eq!(a.peer_addr().unwrap(), None);
+
+        a.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(b.recv(&mut buf).unwrap(), 0);
+
+        drop(b);
+        assert_eq!(a.send(b"x").unwrap_err().raw_os_error(), Some(libc::EPIPE));
+    }
+
+    #[test]
+    fn test_peer_ids() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+
+        let (pid, uid, gid) = a.peer_pid_ids().unwrap();
+        assert_eq!(pid, Some(unsafe { libc::getpid() }));
+        assert_eq!(uid, unsafe { libc::geteuid() });
+        assert_eq!(gid, unsafe { libc::getegid() });
+
+        assert_eq!(b.peer_ids().unwrap(), (uid, gid));
+        assert_eq!(b.peer_ucred().unwrap().pid, pid.unwrap());
+    }
+
+    #[test]
+    fn test_creds() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        crate::scm::set_passcred(&b, true).unwrap();
+
+        a.send_with_creds(b"abc", &crate::scm::current_creds())
+            .unwrap();
+
+        let mut buf = [0; 4];
+        let (n, cred) = b.recv_with_creds(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"abc");
+        assert_eq!(cred, Some(crate::scm::current_creds()));
+    }
+
+    #[test]
+    fn test_listener() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+
+        let listener = UnixSeqpacketListener::bind(&path).unwrap();
+        assert_eq!(
+            listener.local_addr().unwrap(),
+            Some(UnixSocketName::Path(path.clone()))
+        );
+
+        let client = UnixSeqpacket::connect(&path).unwrap();
+        let (server, addr) = listener.accept().unwrap();
+        assert_eq!(addr, None);
+        assert_eq!(
+            client.peer_addr().unwrap(),
+            Some(UnixSocketName::Path(path.clone()))
+        );
+
+        client.send(b"abc").unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(server.recv(&mut buf).unwrap(), 3);
+
+        // Connecting to a stream socket fails
+        let stream_path = dir.path().join("stream");
+        let _stream = std::os::unix::net::UnixListener::bind(&stream_path).unwrap();
+        assert_eq!(
+            UnixSeqpacket::connect(&stream_path)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EPROTOTYPE)
+        );
+    }
+
+    #[test]
+    fn test_abstract() {
+        let name = UnixSocketName::Abstract(
+            format!("unix-cred-seqpacket-test-{}", std::process::id()).into_bytes(),
+        );
+
+        let listener = UnixSeqpacketListener::bind_addr(&name).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), Some(name.clone()));
+
+        let client = UnixSeqpacket::connect_addr(&name).unwrap();
+        let server = listener.incoming().next().unwrap().unwrap();
+        assert_eq!(client.peer_addr().unwrap(), Some(name));
+        assert!(server.peer_ids().is_ok());
+    }
+}
@@ -0,0 +1,189 @@
+//! Thin wrappers around the socket syscalls, for socket types (and addresses) that the standard
+//! library doesn't support.
+
+use std::io;
+use std::os::unix::prelude::*;
+
+use crate::addr::UnixSocketName;
+
+/// The backlog passed to `listen()` (the same as the standard library uses).
+const LISTEN_BACKLOG: libc::c_int = 128;
+
+#[inline]
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Create a new `AF_UNIX` socket of the given type, with `SOCK_CLOEXEC` set.
+pub fn socket(ty: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = cvt(unsafe { libc::socket(libc::AF_UNIX, ty | libc::SOCK_CLOEXEC, 0) })?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Create a connected pair of `AF_UNIX` sockets of the given type, with `SOCK_CLOEXEC` set.
+pub fn socketpair(ty: libc::c_int) -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    cvt(unsafe { libc::socketpair(libc::AF_UNIX, ty | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) })?;
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+pub fn bind(fd: BorrowedFd, addr: &UnixSocketName) -> io::Result<()> {
+    let (addr, len) = addr.to_sockaddr()?;
+    cvt(unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const _ as *const libc::sockaddr,
+            len,
+        )
+    })?;
+    Ok(())
+}
+
+pub fn listen(fd: BorrowedFd) -> io::Result<()> {
+    cvt(unsafe { libc::listen(fd.as_raw_fd(), LISTEN_BACKLOG) })?;
+    Ok(())
+}
+
+pub fn connect(fd: BorrowedFd, addr: &UnixSocketName) -> io::Result<()> {
+    let (addr, len) = addr.to_sockaddr()?;
+
+    loop {
+        match cvt(unsafe {
+            libc::connect(
+                fd.as_raw_fd(),
+                &addr as *const _ as *const libc::sockaddr,
+                len,
+            )
+        }) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            res => return res.map(drop),
+        }
+    }
+}
+
+/// Accept a connection, returning the new socket (with `SOCK_CLOEXEC` set) and the address of
+/// the peer (or `None` if it is unnamed).
+pub fn accept(fd: BorrowedFd) -> io::Result<(OwnedFd, Option<UnixSocketName>)> {
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+
+    loop {
+        let mut len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+        match cvt(unsafe {
+            libc::accept4(
+                fd.as_raw_fd(),
+                &mut addr as *mut _ as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_CLOEXEC,
+            )
+        }) {
+            Ok(newfd) => {
+                let newfd = unsafe { OwnedFd::from_raw_fd(newfd) };
+                return Ok((newfd, UnixSocketName::from_sockaddr(&addr, len)));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn get_name(
+    fd: BorrowedFd,
+    func: unsafe extern "C" fn(
+        libc::c_int,
+        *mut libc::sockaddr,
+        *mut libc::socklen_t,
+    ) -> libc::c_int,
+) -> io::Result<Option<UnixSocketName>> {
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+
+    cvt(unsafe {
+        func(
+            fd.as_raw_fd(),
+            &mut addr as *mut _ as *mut libc::sockaddr,
+            &mut len,
+        )
+    })?;
+    Ok(UnixSocketName::from_sockaddr(&addr, len))
+}
+
+/// Get the address that the socket is bound to, or `None` if it is unnamed.
+pub fn local_addr(fd: BorrowedFd) -> io::Result<Option<UnixSocketName>> {
+    get_name(fd, libc::getsockname)
+}
+
+/// Get the address of the socket's peer, or `None` if it is unnamed.
+pub fn peer_addr(fd: BorrowedFd) -> io::Result<Option<UnixSocketName>> {
+    get_name(fd, libc::getpeername)
+}
+
+/// Create a socket of the given type and bind it to the given address (and, unless it's a
+/// datagram socket, start listening on it).
+pub fn bind_new(ty: libc::c_int, addr: &UnixSocketName) -> io::Result<OwnedFd> {
+    let fd = socket(ty)?;
+    bind(fd.as_fd(), addr)?;
+    if ty != libc::SOCK_DGRAM {
+        listen(fd.as_fd())?;
+    }
+    Ok(fd)
+}
+
+/// Create a socket of the given type and connect it to the given address.
+pub fn connect_new(ty: libc::c_int, addr: &UnixSocketName) -> io::Result<OwnedFd> {
+    let fd = socket(ty)?;
+    connect(fd.as_fd(), addr)?;
+    Ok(fd)
+}
+
+pub fn send(fd: BorrowedFd, buf: &[u8]) -> io::Result<usize> {
+    loop {
+        let n = unsafe {
+            libc::send(
+                fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+pub fn recv(fd: BorrowedFd, buf: &mut [u8], flags: libc::c_int) -> io::Result<usize> {
+    loop {
+        let n = unsafe {
+            libc::recv(
+                fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                flags,
+            )
+        };
+        if n >= 0 {
+            return Ok(n as usize);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+pub fn set_nonblocking(fd: BorrowedFd, nonblocking: bool) -> io::Result<()> {
+    let mut val = nonblocking as libc::c_int;
+    cvt(unsafe { libc::ioctl(fd.as_raw_fd(), libc::FIONBIO, &mut val) })?;
+    Ok(())
+}
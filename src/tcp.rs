@@ -0,0 +1,472 @@
+//! The `tcp` module looks up the owner of the other end of a loopback TCP connection
+//! (Linux-specific).
+//!
+//! TCP sockets have no equivalent of `SO_PEERCRED`. However, if the peer is connected over the
+//! loopback interface, its socket must be in the same network namespace, so it can be found by
+//! looking up the connection's four-tuple (in reverse). [`get_tcp_loopback_peer_ids()`] does this
+//! with `NETLINK_SOCK_DIAG` (falling back to parsing `/proc/net/tcp` and `/proc/net/tcp6` if that
+//! fails), which gives the UID that created the peer's socket and the socket's inode number.
+//!
+//! The inode number can be passed to [`find_socket_holders()`] to find the processes that
+//! currently have the peer's socket open; [`get_tcp_loopback_peer_holders()`] does this directly.
+//!
+//! Note that, unlike with Unix sockets, the credentials are looked up when these functions are
+//! called, not when the connection was made. If the peer closes the connection, the lookup may
+//! fail (or return the credentials of an unrelated connection that later reused the same ports).
+//!
+//! [`get_tcp_loopback_peer_ids()`]: ./fn.get_tcp_loopback_peer_ids.html
+//! [`get_tcp_loopback_peer_holders()`]: ./fn.get_tcp_loopback_peer_holders.html
+//! [`find_socket_holders()`]: ../diag/fn.find_socket_holders.html
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+
+use crate::constants;
+use crate::diag::{self, SocketHolder};
+use crate::netlink;
+
+/// The size of `struct inet_diag_msg`.
+const INET_DIAG_MSG_LEN: usize = 72;
+/// The offset of the `idiag_uid` field in `struct inet_diag_msg`.
+const INET_DIAG_UID_OFFSET: usize = 64;
+/// The offset of the `idiag_inode` field in `struct inet_diag_msg`.
+const INET_DIAG_INODE_OFFSET: usize = 68;
+
+// Other TCP states (from include/net/tcp_states.h)
+const TCP_SYN_RECV: u8 = 3;
+const TCP_FIN_WAIT1: u8 = 4;
+const TCP_CLOSE_WAIT: u8 = 8;
+const TCP_LAST_ACK: u8 = 9;
+const TCP_CLOSING: u8 = 11;
+
+/// The states in which a socket may still belong to a process. In particular, this excludes
+/// `TIME_WAIT` and `FIN_WAIT2` (which, once the socket is closed, are represented by "timewait"
+/// entries that report UID 0) and `CLOSE`.
+const LIVE_STATES: [u8; 7] = [
+    constants::TCP_ESTABLISHED,
+    constants::TCP_SYN_SENT,
+    TCP_SYN_RECV,
+    TCP_FIN_WAIT1,
+    TCP_CLOSE_WAIT,
+    TCP_LAST_ACK,
+    TCP_CLOSING,
+];
+
+/// Represents the owner of the peer's end of a loopback TCP connection.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TcpPeerIds {
+    /// The effective user ID of the process that created the peer's socket.
+    pub uid: libc::uid_t,
+    /// The inode number of the peer's socket (never 0; sockets that are no longer associated with
+    /// a file descriptor are treated as if they don't exist).
+    pub inode: u32,
+}
+
+/// A TCP connection, as seen from one end.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct Tuple {
+    local: SocketAddr,
+    remote: SocketAddr,
+}
+
+fn is_loopback(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_loopback(),
+        IpAddr::V6(addr) => {
+            addr.is_loopback() || addr.to_ipv4_mapped().is_some_and(|addr| addr.is_loopback())
+        }
+    }
+}
+
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), v6.port()),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+fn ip_bytes(addr: &IpAddr) -> [u8; 16] {
+    let mut buf = [0; 16];
+    match addr {
+        IpAddr::V4(addr) => buf[..4].copy_from_slice(&addr.octets()),
+        IpAddr::V6(addr) => buf.copy_from_slice(&addr.octets()),
+    }
+    buf
+}
+
+/// Build a `struct inet_diag_req_v2` that looks up the given connection.
+fn inet_diag_request(tuple: &Tuple) -> Vec<u8> {
+    let family = match tuple.local {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    let mut req = Vec::with_capacity(56);
+    req.push(family as u8);
+    req.push(libc::IPPROTO_TCP as u8);
+    // idiag_ext and pad
+    req.extend_from_slice(&[0, 0]);
+    // idiag_states
+    let states = LIVE_STATES
+        .iter()
+        .fold(0u32, |states, &state| states | (1 << state));
+    req.extend_from_slice(&states.to_ne_bytes());
+    // id.idiag_sport and id.idiag_dport
+    req.extend_from_slice(&tuple.local.port().to_be_bytes());
+    req.extend_from_slice(&tuple.remote.port().to_be_bytes());
+    // id.idiag_src and id.idiag_dst
+    req.extend_from_slice(&ip_bytes(&tuple.local.ip()));
+    req.extend_from_slice(&ip_bytes(&tuple.remote.ip()));
+    // id.idiag_if
+    req.extend_from_slice(&0u32.to_ne_bytes());
+    // id.idiag_cookie: INET_DIAG_NOCOOKIE
+    req.extend_from_slice(&[0xff; 8]);
+    req
+}
+
+fn lookup_netlink(tuple: &Tuple) -> io::Result<TcpPeerIds> {
+    let msgs = netlink::sock_diag_request(&inet_diag_request(tuple), false)?;
+    let msg = msgs
+        .first()
+        .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+
+    if msg.len() < INET_DIAG_MSG_LEN {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    // idiag_state
+    check_live(
+        msg[1],
+        TcpPeerIds {
+            uid: netlink::read_u32(msg, INET_DIAG_UID_OFFSET)?,
+            inode: netlink::read_u32(msg, INET_DIAG_INODE_OFFSET)?,
+        },
+    )
+}
+
+/// Check that a socket that was found in the given state is still owned by a process.
+///
+/// Sockets that have been closed (including timewait entries, which always report UID 0 and inode
+/// 0) must not be trusted, so this fails with `ENOENT` as if the socket didn't exist.
+fn check_live(state: u8, ids: TcpPeerIds) -> io::Result<TcpPeerIds> {
+    if ids.inode == 0 || !LIVE_STATES.contains(&state) {
+        return Err(io::Error::from_raw_os_error(libc::ENOENT));
+    }
+
+    Ok(ids)
+}
+
+/// Parse an address from `/proc/net/tcp{,6}`, which is formatted as the raw address (printed as
+/// one or four native-endian 32-bit hex words) and the port, separated by a colon.
+fn parse_proc_addr(s: &str) -> Option<SocketAddr> {
+    let (addr, port) = s.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+
+    let mut bytes = Vec::with_capacity(16);
+    for i in (0..addr.len()).step_by(8) {
+        let word = u32::from_str_radix(addr.get(i..i + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&bytes);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Find the given connection in the contents of `/proc/net/tcp` or `/proc/net/tcp6`.
+fn parse_proc_tcp(data: &str, tuple: &Tuple) -> io::Result<TcpPeerIds> {
+    for line in data.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+
+        if parse_proc_addr(fields[1]) != Some(tuple.local)
+            || parse_proc_addr(fields[2]) != Some(tuple.remote)
+        {
+            continue;
+        }
+
+        let state = u8::from_str_radix(fields[3], 16)
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        return check_live(
+            state,
+            TcpPeerIds {
+                uid: crate::procfs::parse_proc_int(fields[7])?,
+                inode: crate::procfs::parse_proc_int(fields[9])?,
+            },
+        );
+    }
+
+    Err(io::Error::from_raw_os_error(libc::ENOENT))
+}
+
+fn lookup_proc(tuple: &Tuple) -> io::Result<TcpPeerIds> {
+    let path = match tuple.local {
+        SocketAddr::V4(_) => "/proc/net/tcp",
+        SocketAddr::V6(_) => "/proc/net/tcp6",
+    };
+
+    parse_proc_tcp(&std::fs::read_to_string(path)?, tuple)
+}
+
+fn lookup(tuple: &Tuple) -> io::Result<TcpPeerIds> {
+    // If NETLINK_SOCK_DIAG isn't available (or the tcp_diag module isn't loaded), fall back on
+    // /proc, which will fail with ENOENT if the connection really doesn't exist
+    lookup_netlink(tuple).or_else(|_| lookup_proc(tuple))
+}
+
+/// Look up the owner of the loopback TCP socket with the given local and remote addresses.
+///
+/// This is the building block of [`get_tcp_loopback_peer_ids()`], for when the addresses of the
+/// connection are known but there is no `TcpStream` for either end (for example, in an ident
+/// server). The same errors apply: if either address is not a loopback address, this fails with
+/// `EADDRNOTAVAIL`, and if no such socket exists, this fails with `ENOENT`.
+///
+/// [`get_tcp_loopback_peer_ids()`]: ./fn.get_tcp_loopback_peer_ids.html
+pub fn get_tcp_loopback_socket_ids(
+    local: SocketAddr,
+    remote: SocketAddr,
+) -> io::Result<TcpPeerIds> {
+    if !is_loopback(&local.ip()) || !is_loopback(&remote.ip()) {
+        return Err(io::Error::from_raw_os_error(libc::EADDRNOTAVAIL));
+    }
+
+    let tuple = Tuple { local, remote };
+
+    // An IPv6 socket that accepted a connection from an IPv4 socket sees v4-mapped addresses, but
+    // the IPv4 socket at the other end has plain IPv4 addresses. (If the other end was an IPv6
+    // socket connecting to a v4-mapped address, it keeps the mapped addresses, so try both.)
+    let unmapped = Tuple {
+        local: unmap(local),
+        remote: unmap(remote),
+    };
+    if unmapped != tuple && unmapped.local.is_ipv4() && unmapped.remote.is_ipv4() {
+        match lookup(&unmapped) {
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => (),
+            res => return res,
+        }
+    }
+
+    lookup(&tuple)
+}
+
+/// Look up the owner of the other end of the given loopback TCP connection.
+///
+/// If the peer is not connected over the loopback interface (i.e. either end's address is not in
+/// `127.0.0.0/8` or `::1`, including IPv4-mapped IPv6 addresses), this fails with
+/// `EADDRNOTAVAIL`. If the peer's socket cannot be found, or it has been closed (even if the
+/// connection is still shutting down), this fails with `ENOENT`.
+///
+/// See the [module-level documentation](./index.html) for caveats.
+pub fn get_tcp_loopback_peer_ids(sock: &TcpStream) -> io::Result<TcpPeerIds> {
+    // From the peer's point of view, the addresses are reversed
+    get_tcp_loopback_socket_ids(sock.peer_addr()?, sock.local_addr()?)
+}
+
+/// Find the processes that currently have the other end of the given loopback TCP connection
+/// open, along with their current credentials.
+///
+/// This looks up the peer's socket with [`get_tcp_loopback_peer_ids()`], then scans `/proc`
+/// with [`find_socket_holders()`]; the caveats of both apply.
+///
+/// [`get_tcp_loopback_peer_ids()`]: ./fn.get_tcp_loopback_peer_ids.html
+/// [`find_socket_holders()`]: ../diag/fn.find_socket_holders.html
+pub fn get_tcp_loopback_peer_holders(sock: &TcpStream) -> io::Result<Vec<SocketHolder>> {
+    diag::find_socket_holders(get_tcp_loopback_peer_ids(sock)?.inode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    fn pair(addr: &str) -> Option<(TcpStream, TcpStream)> {
+        let listener = TcpListener::bind(addr).ok()?;
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        Some((client, server))
+    }
+
+    fn check_pair(client: &TcpStream, server: &TcpStream) {
+        let euid = unsafe { libc::geteuid() };
+
+        let ids = get_tcp_loopback_peer_ids(server).unwrap();
+        assert_eq!(ids.uid, euid);
+        assert_eq!(ids.inode, diag::socket_inode(client).unwrap());
+
+        let ids = get_tcp_loopback_peer_ids(client).unwrap();
+        assert_eq!(ids.uid, euid);
+        assert_eq!(ids.inode, diag::socket_inode(server).unwrap());
+
+        // Other test threads may be spawning processes, which briefly hold copies of our fds
+        let holders = get_tcp_loopback_peer_holders(server).unwrap();
+        let pid = unsafe { libc::getpid() };
+        let ours = holders.iter().find(|holder| holder.pid == pid).unwrap();
+        assert_eq!(ours.uid, euid);
+    }
+
+    #[test]
+    fn test_get_tcp_loopback_peer_ids() {
+        let (client, server) = pair("127.0.0.1:0").unwrap();
+        check_pair(&client, &server);
+
+        // IPv6 may not be available
+        if let Some((client, server)) = pair("[::1]:0") {
+            check_pair(&client, &server);
+        }
+
+        // An IPv4 client connecting to a dual-stack IPv6 listener
+        if let Ok(listener) = TcpListener::bind("[::]:0") {
+            let port = listener.local_addr().unwrap().port();
+            if let Ok(client) = TcpStream::connect(("127.0.0.1", port)) {
+                let (server, _) = listener.accept().unwrap();
+                check_pair(&client, &server);
+            }
+        }
+    }
+
+    #[test]
+    fn test_closed_peer() {
+        let (client, server) = pair("127.0.0.1:0").unwrap();
+        let tuple = Tuple {
+            local: client.local_addr().unwrap(),
+            remote: client.peer_addr().unwrap(),
+        };
+
+        // Once the client closes its end, its socket lingers in FIN_WAIT2 (and then TIME_WAIT,
+        // once the server closes too) without an owner
+        drop(client);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        assert_eq!(
+            get_tcp_loopback_peer_ids(&server)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+        assert_eq!(
+            lookup_proc(&tuple).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+
+        drop(server);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(
+            lookup(&tuple).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    #[test]
+    fn test_proc_fallback() {
+        let (client, server) = pair("127.0.0.1:0").unwrap();
+
+        let tuple = Tuple {
+            local: client.local_addr().unwrap(),
+            remote: client.peer_addr().unwrap(),
+        };
+        assert_eq!(
+            lookup_proc(&tuple).unwrap(),
+            lookup_netlink(&tuple).unwrap()
+        );
+        assert_eq!(
+            lookup_proc(&tuple).unwrap().inode,
+            diag::socket_inode(&client).unwrap()
+        );
+
+        drop(server);
+    }
+
+    #[test]
+    fn test_parse_proc_tcp() {
+        let data = "\
+  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 12345 1
+   1: 0100007F:1F90 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1001        0 23456 1
+   2: 0100007F:1F91 0100007F:D432 06 00000000:00000000 03:00001770 00000000     0        0 0
+   3: 0100007F:1F92 0100007F:D433 05 00000000:00000000 00:00000000 00000000     0        0 0 1
+";
+        let local: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let remote: SocketAddr = "127.0.0.1:54321".parse().unwrap();
+
+        if cfg!(target_endian = "little") {
+            assert_eq!(
+                parse_proc_tcp(data, &Tuple { local, remote }).unwrap(),
+                TcpPeerIds {
+                    uid: 1001,
+                    inode: 23456
+                }
+            );
+        }
+        assert_eq!(
+            parse_proc_tcp(
+                data,
+                &Tuple {
+                    local: remote,
+                    remote: local
+                }
+            )
+            .unwrap_err()
+            .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+
+        // Closed sockets (TIME_WAIT and orphaned FIN_WAIT2) report UID 0
+        if cfg!(target_endian = "little") {
+            for (local, remote) in [
+                ("127.0.0.1:8081", "127.0.0.1:54322"),
+                ("127.0.0.1:8082", "127.0.0.1:54323"),
+            ] {
+                let tuple = Tuple {
+                    local: local.parse().unwrap(),
+                    remote: remote.parse().unwrap(),
+                };
+                assert_eq!(
+                    parse_proc_tcp(data, &tuple).unwrap_err().raw_os_error(),
+                    Some(libc::ENOENT)
+                );
+            }
+        }
+
+        assert_eq!(
+            parse_proc_tcp("header\n0: 0100007F:1F90\n", &Tuple { local, remote })
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EINVAL)
+        );
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        for addr in ["127.0.0.1", "127.1.2.3", "::1", "::ffff:127.0.0.1"] {
+            assert!(is_loopback(&addr.parse().unwrap()), "{}", addr);
+        }
+        for addr in ["10.0.0.1", "0.0.0.0", "::", "::ffff:10.0.0.1", "fe80::1"] {
+            assert!(!is_loopback(&addr.parse().unwrap()), "{}", addr);
+        }
+
+        assert_eq!(
+            get_tcp_loopback_socket_ids(
+                "10.0.0.1:1234".parse().unwrap(),
+                "127.0.0.1:80".parse().unwrap()
+            )
+            .unwrap_err()
+            .raw_os_error(),
+            Some(libc::EADDRNOTAVAIL)
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! The `terminal` module provides information about the session, process group, and controlling
+//! terminal of a socket's peer.
+//!
+//! This is read from `/proc/<pid>/stat`, so it is subject to the same PID reuse caveats as
+//! [`get_peer_pid_ids()`].
+//!
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+use std::path::PathBuf;
+
+use crate::procfs;
+
+/// The major number of Unix98 pseudoterminal slaves (`/dev/pts/N`, where `N` is the minor number).
+const UNIX98_PTY_SLAVE_MAJOR: u32 = 136;
+
+/// Represents the credentials of a Unix socket's peer, along with its session, process group, and
+/// controlling terminal.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PeerTerminal {
+    /// The peer's PID.
+    ///
+    /// **WARNING**: This is the PID of the process that originally opened the socket. That process
+    /// may have died, and another process may now be running with that PID. Use with caution.
+    pub pid: libc::pid_t,
+    /// The peer's effective user ID.
+    pub uid: libc::uid_t,
+    /// The peer's effective group ID.
+    pub gid: libc::gid_t,
+    /// The ID of the peer's session.
+    pub session: libc::pid_t,
+    /// The ID of the peer's process group.
+    pub pgrp: libc::pid_t,
+    /// The device number of the peer's controlling terminal, or `None` if it has no controlling
+    /// terminal.
+    pub tty: Option<libc::dev_t>,
+    /// The ID of the foreground process group of the peer's controlling terminal, or `None` if it
+    /// has no controlling terminal.
+    pub tpgid: Option<libc::pid_t>,
+}
+
+impl PeerTerminal {
+    /// Get the path of the peer's controlling terminal.
+    ///
+    /// Pseudoterminals are resolved to `/dev/pts/N`; other terminals are looked up in
+    /// `/sys/dev/char`. Returns `None` if the peer has no controlling terminal, or if the path
+    /// could not be determined.
+    pub fn tty_path(&self) -> Option<PathBuf> {
+        self.tty.and_then(tty_path)
+    }
+
+    /// Check whether the peer is in the foreground process group of its controlling terminal.
+    #[inline]
+    pub fn is_foreground(&self) -> bool {
+        self.tpgid == Some(self.pgrp)
+    }
+
+    /// Check whether this peer has the same controlling terminal and session as `other`.
+    ///
+    /// This is useful to implement "same terminal as the original authentication" policies. Peers
+    /// with no controlling terminal never match.
+    #[inline]
+    pub fn same_terminal(&self, other: &Self) -> bool {
+        self.tty.is_some() && self.tty == other.tty && self.session == other.session
+    }
+}
+
+fn tty_path(dev: libc::dev_t) -> Option<PathBuf> {
+    let major = libc::major(dev);
+    let minor = libc::minor(dev);
+
+    // devpts has no entries in /sys/dev/char
+    if major == UNIX98_PTY_SLAVE_MAJOR {
+        return Some(PathBuf::from(format!("/dev/pts/{}", minor)));
+    }
+
+    let uevent =
+        std::fs::read_to_string(format!("/sys/dev/char/{}:{}/uevent", major, minor)).ok()?;
+
+    uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME="))
+        .map(|name| PathBuf::from("/dev").join(name))
+}
+
+unsafe fn get_peer_terminal_raw(sockfd: RawFd) -> io::Result<PeerTerminal> {
+    let cred = crate::ucred::get_ucred_raw(sockfd)?;
+    let stat = procfs::read_stat(cred.pid)?;
+
+    Ok(PeerTerminal {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid,
+        session: stat.session,
+        pgrp: stat.pgrp,
+        tty: match stat.tty_nr {
+            0 => None,
+            tty_nr => Some(tty_nr as libc::dev_t),
+        },
+        tpgid: match stat.tpgid {
+            -1 => None,
+            tpgid => Some(tpgid),
+        },
+    })
+}
+
+/// Get the PID, UID, and GID of the given socket's peer, along with its session, process group,
+/// and controlling terminal.
+///
+/// **WARNING**: The session and terminal information is looked up by PID after the credentials
+/// are retrieved. If the original peer process has died and its PID has been reused, it will
+/// describe the wrong process. Use with caution.
+#[inline]
+pub fn get_peer_terminal(sock: &UnixStream) -> io::Result<PeerTerminal> {
+    unsafe { get_peer_terminal_raw(sock.as_raw_fd()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_ctty() -> Option<libc::dev_t> {
+        let file = std::fs::File::open("/dev/tty").ok()?;
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::fstat(file.as_raw_fd(), &mut st) }, 0);
+        Some(st.st_rdev)
+    }
+
+    #[test]
+    fn test_get_peer_terminal() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        let aterm = get_peer_terminal(&a).unwrap();
+        assert_eq!(aterm.pid, unsafe { libc::getpid() });
+        assert_eq!(aterm.uid, unsafe { libc::geteuid() });
+        assert_eq!(aterm.gid, unsafe { libc::getegid() });
+        assert_eq!(aterm.session, unsafe { libc::getsid(0) });
+        assert_eq!(aterm.pgrp, unsafe { libc::getpgrp() });
+        assert_eq!(aterm.tty, get_ctty());
+        assert_eq!(aterm.tpgid.is_some(), aterm.tty.is_some());
+
+        let bterm = get_peer_terminal(&b).unwrap();
+        assert_eq!(aterm, bterm);
+        assert_eq!(aterm.same_terminal(&bterm), aterm.tty.is_some());
+    }
+
+    #[test]
+    fn test_tty_path() {
+        assert_eq!(
+            tty_path(libc::makedev(136, 3)),
+            Some(PathBuf::from("/dev/pts/3"))
+        );
+        assert_eq!(
+            tty_path(libc::makedev(136, 1000)),
+            Some(PathBuf::from("/dev/pts/1000"))
+        );
+        assert_eq!(
+            tty_path(libc::makedev(136, 300_000)),
+            Some(PathBuf::from("/dev/pts/300000"))
+        );
+
+        // Not a pseudoterminal, even though older kernels used majors 137-143 for them
+        if !std::path::Path::new("/sys/dev/char/137:2").exists() {
+            assert_eq!(tty_path(libc::makedev(137, 2)), None);
+        }
+
+        if std::path::Path::new("/sys/dev/char/1:3").exists() {
+            assert_eq!(
+                tty_path(libc::makedev(1, 3)),
+                Some(PathBuf::from("/dev/null"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_terminal() {
+        let term = PeerTerminal {
+            pid: 100,
+            uid: 1000,
+            gid: 1000,
+            session: 90,
+            pgrp: 100,
+            tty: Some(libc::makedev(136, 3)),
+            tpgid: Some(100),
+        };
+
+        assert!(term.is_foreground());
+        assert!(term.same_terminal(&term));
+        assert_eq!(term.tty_path(), Some(PathBuf::from("/dev/pts/3")));
+
+        let other = PeerTerminal {
+            pid: 200,
+            tpgid: Some(200),
+            ..term.clone()
+        };
+        assert!(!other.is_foreground());
+        assert!(term.same_terminal(&other));
+
+        assert!(!term.same_terminal(&PeerTerminal {
+            session: 91,
+            ..term.clone()
+        }));
+        assert!(!term.same_terminal(&PeerTerminal {
+            tty: Some(libc::makedev(136, 4)),
+            ..term.clone()
+        }));
+
+        let no_tty = PeerTerminal {
+            tty: None,
+            tpgid: None,
+            ..term
+        };
+        assert!(!no_tty.is_foreground());
+        assert!(!no_tty.same_terminal(&no_tty));
+        assert_eq!(no_tty.tty_path(), None);
+    }
+}
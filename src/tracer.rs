@@ -0,0 +1,154 @@
+//! The `tracer` module provides functions to detect whether a socket's peer is being traced (with
+//! `ptrace()`), and to retrieve the credentials of the tracer.
+//!
+//! A process that is being traced can be controlled completely by its tracer, which may be
+//! running as a different user. So if a peer is being traced, its UID/GID cannot really be
+//! trusted. [`get_ucred_untraced()`] can be used to reject traced peers entirely.
+//!
+//! This information is read from `/proc/<pid>/status`, so it is subject to the same PID reuse
+//! caveats as [`get_peer_pid_ids()`].
+//!
+//! [`get_ucred_untraced()`]: ./fn.get_ucred_untraced.html
+//! [`get_peer_pid_ids()`]: ../fn.get_peer_pid_ids.html
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+
+use crate::procfs;
+use crate::ucred::Ucred;
+
+/// Represents the credentials of a process that is tracing another process.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Tracer {
+    /// The tracer's PID.
+    ///
+    /// Note that tracing is done on a per-thread basis, so this is actually the thread ID of the
+    /// thread that is tracing the process.
+    pub pid: libc::pid_t,
+    /// The tracer's real user ID.
+    pub ruid: libc::uid_t,
+    /// The tracer's effective user ID.
+    pub uid: libc::uid_t,
+    /// The tracer's real group ID.
+    pub rgid: libc::gid_t,
+    /// The tracer's effective group ID.
+    pub gid: libc::gid_t,
+}
+
+fn get_tracer_pid(pid: libc::pid_t) -> io::Result<Option<libc::pid_t>> {
+    let status = procfs::read_proc_string(pid, "status")?;
+
+    match procfs::parse_proc_int(procfs::status_field(&status, "TracerPid")?)? {
+        0 => Ok(None),
+        tracer_pid => Ok(Some(tracer_pid)),
+    }
+}
+
+/// Get the PID and credentials of the process that is tracing the process with the given PID.
+///
+/// Returns `None` if the process is not being traced.
+pub fn get_tracer(pid: libc::pid_t) -> io::Result<Option<Tracer>> {
+    let tracer_pid = match get_tracer_pid(pid)? {
+        Some(tracer_pid) => tracer_pid,
+        None => return Ok(None),
+    };
+
+    let status = procfs::read_proc_string(tracer_pid, "status")?;
+    let uids = procfs::parse_status_ids(procfs::status_field(&status, "Uid")?)?;
+    let gids = procfs::parse_status_ids(procfs::status_field(&status, "Gid")?)?;
+
+    Ok(Some(Tracer {
+        pid: tracer_pid,
+        ruid: uids[0],
+        uid: uids[1],
+        rgid: gids[0],
+        gid: gids[1],
+    }))
+}
+
+/// Get the PID and credentials of the process that is tracing the given socket's peer.
+///
+/// Returns `None` if the peer is not being traced.
+#[inline]
+pub fn get_peer_tracer(sock: &UnixStream) -> io::Result<Option<Tracer>> {
+    let cred = unsafe { crate::ucred::get_ucred_raw(sock.as_raw_fd()) }?;
+    get_tracer(cred.pid)
+}
+
+unsafe fn get_ucred_untraced_raw(sockfd: RawFd) -> io::Result<Ucred> {
+    let cred = crate::ucred::get_ucred_raw(sockfd)?;
+
+    if get_tracer_pid(cred.pid)?.is_some() {
+        return Err(io::Error::from_raw_os_error(libc::EPERM));
+    }
+
+    Ok(cred)
+}
+
+/// Get the credentials of the given socket's peer, failing with `EPERM` if the peer is being
+/// traced.
+///
+/// This is equivalent to [`get_ucred()`], except that it refuses traced peers.
+///
+/// **WARNING**: This only checks whether the peer is being traced at the time of the call. The
+/// peer may start being traced at any time afterward.
+///
+/// [`get_ucred()`]: ../ucred/fn.get_ucred.html
+#[inline]
+pub fn get_ucred_untraced(sock: &UnixStream) -> io::Result<Ucred> {
+    unsafe { get_ucred_untraced_raw(sock.as_raw_fd()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_untraced() {
+        let (a, b) = UnixStream::pair().unwrap();
+
+        assert_eq!(get_peer_tracer(&a).unwrap(), None);
+        assert_eq!(
+            get_ucred_untraced(&b).unwrap(),
+            crate::ucred::get_ucred(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_traced() {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        if unsafe { libc::ptrace(libc::PTRACE_SEIZE, pid, 0, 0) } < 0 {
+            // We may not be allowed to use ptrace() in this environment
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return;
+        }
+
+        let tracer = get_tracer(pid).unwrap().unwrap();
+        assert_eq!(tracer.pid, unsafe { libc::gettid() });
+        assert_eq!(tracer.ruid, unsafe { libc::getuid() });
+        assert_eq!(tracer.uid, unsafe { libc::geteuid() });
+        assert_eq!(tracer.rgid, unsafe { libc::getgid() });
+        assert_eq!(tracer.gid, unsafe { libc::getegid() });
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_get_tracer_error() {
+        assert_eq!(
+            get_tracer(-1).unwrap_err().raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+}
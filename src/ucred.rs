@@ -89,6 +89,49 @@ pub fn get_ucred(sock: &UnixStream) -> io::Result<Ucred> {
     unsafe { get_ucred_raw(sock.as_raw_fd()) }
 }
 
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn get_peer_groups_raw(sockfd: RawFd) -> io::Result<Vec<libc::gid_t>> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 16];
+
+    loop {
+        let mut len = std::mem::size_of_val(groups.as_slice()) as libc::socklen_t;
+
+        if libc::getsockopt(
+            sockfd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERGROUPS,
+            groups.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        ) < 0
+        {
+            let err = io::Error::last_os_error();
+
+            // If the buffer was too small, the kernel tells us how large it needs to be
+            if err.raw_os_error() == Some(libc::ERANGE)
+                && len as usize > std::mem::size_of_val(groups.as_slice())
+            {
+                groups.resize(len as usize / std::mem::size_of::<libc::gid_t>(), 0);
+                continue;
+            }
+
+            return Err(err);
+        }
+
+        groups.truncate(len as usize / std::mem::size_of::<libc::gid_t>());
+        return Ok(groups);
+    }
+}
+
+/// Get the supplementary group list of the given socket's peer (Linux-specific).
+///
+/// Like the other credentials, this is cached at the time that the `connect()`/`socketpair()`
+/// call was made. It uses `SO_PEERGROUPS`, which requires Linux 4.13+.
+#[cfg(target_os = "linux")]
+#[inline]
+pub fn get_peer_groups<S: crate::PeerSocket>(sock: &S) -> io::Result<Vec<libc::gid_t>> {
+    unsafe { get_peer_groups_raw(sock.as_raw_fd()) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -114,6 +157,22 @@ mod tests {
         assert_eq!(bcred.pid, pid);
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_get_peer_groups() {
+        let (a, _b) = UnixStream::pair().unwrap();
+
+        let ngroups = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        let mut expected = vec![0; ngroups as usize];
+        let ngroups = unsafe { libc::getgroups(ngroups, expected.as_mut_ptr()) };
+        expected.truncate(ngroups as usize);
+
+        let mut groups = get_peer_groups(&a).unwrap();
+        groups.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(groups, expected);
+    }
+
     #[test]
     fn test_get_ucred_error() {
         let dir = tempfile::tempdir().unwrap();
@@ -0,0 +1,270 @@
+//! The `ucspi` module implements a `unixserver`-compatible server (only with the `ucspi`
+//! feature).
+//!
+//! Following the [UCSPI](https://cr.yp.to/proto/ucspi.txt) conventions used by `ucspi-unix`,
+//! [`UnixServer`] accepts connections on a Unix socket and runs a program for each one, with its
+//! standard input and output connected to the socket. The peer's credentials are passed to the
+//! program in the `UNIXREMOTEEUID`, `UNIXREMOTEEGID`, and `UNIXREMOTEPID` environment variables
+//! (see [`peer_env()`] for the full list).
+//!
+//! ```no_run
+//! use std::os::unix::net::UnixListener;
+//! use unix_cred::policy::PeerPolicy;
+//! use unix_cred::ucspi::UnixServer;
+//!
+//! let listener = UnixListener::bind("/run/foo.sock").unwrap();
+//! UnixServer::new(listener, "/usr/libexec/foo-handler", Vec::<String>::new())
+//!     .max_connections(20)
+//!     .policy(PeerPolicy::Uids(vec![0, 1000]))
+//!     .serve()
+//!     .unwrap();
+//! ```
+//!
+//! [`UnixServer`]: ./struct.UnixServer.html
+//! [`peer_env()`]: ./fn.peer_env.html
+
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::prelude::*;
+use std::process::{Child, Command, Stdio};
+
+use crate::policy::PeerPolicy;
+use crate::util::ConnectionLimit;
+
+/// The default limit on the number of concurrent connections (the same as `unixserver`'s).
+pub const DEFAULT_MAX_CONNECTIONS: usize = 10;
+
+/// Get the environment variables that describe a connection accepted by a UCSPI server.
+///
+/// This returns:
+///
+/// - `PROTO`: always `UNIX`.
+/// - `UNIXLOCALPATH`: the path that the socket is bound to (omitted for unnamed and abstract
+///   sockets).
+/// - `UNIXLOCALPID`, `UNIXLOCALUID`, `UNIXLOCALGID`: the current process's PID, effective UID,
+///   and effective GID.
+/// - `UNIXREMOTEEUID`, `UNIXREMOTEEGID`: the peer's effective UID and GID.
+/// - `UNIXREMOTEPID`: the peer's PID.
+pub fn peer_env(sock: &UnixStream) -> io::Result<Vec<(&'static str, OsString)>> {
+    let (pid, uid, gid) = crate::get_peer_pid_ids(sock)?;
+
+    let mut env = vec![("PROTO", OsString::from("UNIX"))];
+
+    if let Some(path) = sock.local_addr()?.as_pathname() {
+        env.push(("UNIXLOCALPATH", path.as_os_str().to_os_string()));
+    }
+
+    unsafe {
+        env.push(("UNIXLOCALPID", libc::getpid().to_string().into()));
+        env.push(("UNIXLOCALUID", libc::geteuid().to_string().into()));
+        env.push(("UNIXLOCALGID", libc::getegid().to_string().into()));
+    }
+
+    env.push(("UNIXREMOTEEUID", uid.to_string().into()));
+    env.push(("UNIXREMOTEEGID", gid.to_string().into()));
+    if let Some(pid) = pid {
+        env.push(("UNIXREMOTEPID", pid.to_string().into()));
+    }
+
+    Ok(env)
+}
+
+/// A UCSPI server that runs a program for each connection.
+///
+/// See the [module-level documentation](./index.html) for more information.
+#[derive(Debug)]
+pub struct UnixServer {
+    listener: UnixListener,
+    program: OsString,
+    args: Vec<OsString>,
+    max_connections: usize,
+    policy: PeerPolicy,
+}
+
+impl UnixServer {
+    /// Create a new server that accepts connections on the given listener, and runs the given
+    /// program with the given arguments for each one.
+    ///
+    /// By default, connections are accepted from any peer that can connect to the socket (i.e.
+    /// access is controlled by the socket's permissions), and at most
+    /// [`DEFAULT_MAX_CONNECTIONS`](./constant.DEFAULT_MAX_CONNECTIONS.html) connections are handled
+    /// at once.
+    pub fn new<P, I, A>(listener: UnixListener, program: P, args: I) -> Self
+    where
+        P: Into<OsString>,
+        I: IntoIterator<Item = A>,
+        A: Into<OsString>,
+    {
+        Self {
+            listener,
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            policy: PeerPolicy::AllowAnyone,
+        }
+    }
+
+    /// Set the maximum number of connections that are handled at once.
+    ///
+    /// Once this many programs are running, no more connections are accepted until one of them
+    /// exits. A limit of 0 is treated as 1.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Set the policy that peers must be allowed by.
+    ///
+    /// Connections from peers that are not allowed are closed without running the program.
+    pub fn policy(mut self, policy: PeerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Get the listener that this server accepts connections on.
+    #[inline]
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    /// Check the peer of the given connection against the policy, and if it is allowed, run the
+    /// program with its standard input and output connected to the socket.
+    ///
+    /// This returns `None` if the peer is not allowed (in which case the connection is closed).
+    pub fn handle(&self, sock: UnixStream) -> io::Result<Option<Child>> {
+        let (uid, gid) = crate::get_peer_ids(&sock)?;
+        if !self.policy.allows(uid, gid) {
+            return Ok(None);
+        }
+
+        let env = peer_env(&sock)?;
+        let stdin = OwnedFd::from(sock.try_clone()?);
+        let stdout = OwnedFd::from(sock);
+
+        Command::new(&self.program)
+            .args(&self.args)
+            .envs(env)
+            .stdin(Stdio::from(stdin))
+            .stdout(Stdio::from(stdout))
+            .spawn()
+            .map(Some)
+    }
+
+    /// Accept connections forever, running the program for each one.
+    ///
+    /// Errors handling individual connections (e.g. failing to start the program) cause the
+    /// connection to be closed, but are otherwise ignored. Errors from `accept()` (other than
+    /// `ECONNABORTED`) are returned.
+    pub fn serve(self) -> io::Result<()> {
+        let limit = ConnectionLimit::new(self.max_connections);
+
+        loop {
+            limit.wait();
+
+            let sock = match self.listener.accept() {
+                Ok((sock, _)) => sock,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if let Ok(Some(mut child)) = self.handle(sock) {
+                let guard = limit.enter();
+                std::thread::spawn(move || {
+                    let _ = child.wait();
+                    drop(guard);
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+
+    #[test]
+    fn test_peer_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let env = peer_env(&server).unwrap();
+        let get = |name| {
+            env.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_str().unwrap().to_string())
+        };
+
+        let (uid, gid) = crate::get_peer_ids(&server).unwrap();
+        assert_eq!(get("PROTO").unwrap(), "UNIX");
+        assert_eq!(get("UNIXLOCALPATH").unwrap(), path.to_str().unwrap());
+        assert_eq!(get("UNIXREMOTEEUID").unwrap(), uid.to_string());
+        assert_eq!(get("UNIXREMOTEEGID").unwrap(), gid.to_string());
+        assert_eq!(
+            get("UNIXREMOTEPID").unwrap(),
+            std::process::id().to_string()
+        );
+        assert_eq!(get("UNIXLOCALPID").unwrap(), std::process::id().to_string());
+
+        // Unnamed sockets have no path
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert!(peer_env(&a)
+            .unwrap()
+            .iter()
+            .all(|(name, _)| *name != "UNIXLOCALPATH"));
+    }
+
+    fn start_server(policy: PeerPolicy) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = UnixServer::new(
+            listener,
+            "/bin/sh",
+            [
+                "-c",
+                "echo \"$UNIXREMOTEEUID $UNIXREMOTEEGID $UNIXREMOTEPID\"; cat",
+            ],
+        )
+        .max_connections(2)
+        .policy(policy);
+        std::thread::spawn(move || server.serve());
+
+        (dir, path)
+    }
+
+    #[test]
+    fn test_server() {
+        let (_dir, path) = start_server(PeerPolicy::SameUser);
+
+        let mut sock = UnixStream::connect(&path).unwrap();
+        sock.write_all(b"hello\n").unwrap();
+        sock.shutdown(Shutdown::Write).unwrap();
+
+        let mut buf = String::new();
+        sock.read_to_string(&mut buf).unwrap();
+
+        let (uid, gid) = crate::get_peer_ids(&sock).unwrap();
+        assert_eq!(
+            buf,
+            format!("{} {} {}\nhello\n", uid, gid, std::process::id())
+        );
+    }
+
+    #[test]
+    fn test_server_rejects() {
+        let (_dir, path) = start_server(PeerPolicy::Uids(Vec::new()));
+
+        let mut sock = UnixStream::connect(&path).unwrap();
+        let mut buf = String::new();
+        sock.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "");
+    }
+}
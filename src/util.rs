@@ -8,7 +8,7 @@ pub unsafe fn getsockopt_raw<T: Sized>(
     optname: libc::c_int,
     data: &mut [T],
 ) -> io::Result<usize> {
-    let mut len = (data.len() * std::mem::size_of::<T>()) as libc::socklen_t;
+    let mut len = std::mem::size_of_val(data) as libc::socklen_t;
 
     if libc::getsockopt(
         sockfd,
@@ -24,6 +24,147 @@ pub unsafe fn getsockopt_raw<T: Sized>(
     Ok(len as usize)
 }
 
+/// Look up the name of the user with the given UID.
+#[cfg(all(target_os = "linux", any(feature = "ident", feature = "http-proxy")))]
+pub fn user_name(uid: libc::uid_t) -> Option<String> {
+    let mut buf = vec![0u8; 1024];
+
+    loop {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+
+        let ret = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut pwd,
+                buf.as_mut_ptr() as *mut libc::c_char,
+                buf.len(),
+                &mut result,
+            )
+        };
+
+        if ret == libc::ERANGE && buf.len() < 1024 * 1024 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        } else if ret != 0 || result.is_null() {
+            return None;
+        }
+
+        let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+        return Some(name.to_string_lossy().into_owned());
+    }
+}
+
+/// Generate `len` random bytes with `getrandom()`, and return them hex-encoded.
+#[cfg(target_os = "linux")]
+pub fn random_hex(len: usize) -> io::Result<String> {
+    let mut buf = vec![0u8; len];
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        let n = unsafe {
+            libc::getrandom(
+                buf[filled..].as_mut_ptr() as *mut libc::c_void,
+                buf.len() - filled,
+                0,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        } else {
+            filled += n as usize;
+        }
+    }
+
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Limits the number of connections that a server handles at once.
+#[cfg(all(
+    target_os = "linux",
+    any(
+        feature = "http-proxy",
+        feature = "ident",
+        feature = "ucspi",
+        feature = "varlink"
+    )
+))]
+#[derive(Debug)]
+pub struct ConnectionLimit {
+    max: usize,
+    active: std::sync::Mutex<usize>,
+    cvar: std::sync::Condvar,
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        feature = "http-proxy",
+        feature = "ident",
+        feature = "ucspi",
+        feature = "varlink"
+    )
+))]
+impl ConnectionLimit {
+    /// Create a new limit (a limit of 0 is treated as 1).
+    pub fn new(max: usize) -> std::sync::Arc<Self> {
+        std::sync::Arc::new(Self {
+            max: max.max(1),
+            active: std::sync::Mutex::new(0),
+            cvar: std::sync::Condvar::new(),
+        })
+    }
+
+    /// Wait until fewer than the maximum number of connections are active.
+    ///
+    /// Servers should call this before `accept()`, so that connections over the limit wait in the
+    /// listen queue.
+    pub fn wait(&self) {
+        let mut active = self.active.lock().unwrap();
+        while *active >= self.max {
+            active = self.cvar.wait(active).unwrap();
+        }
+    }
+
+    /// Mark a connection as active until the returned guard is dropped.
+    pub fn enter(self: &std::sync::Arc<Self>) -> ConnectionGuard {
+        *self.active.lock().unwrap() += 1;
+        ConnectionGuard(self.clone())
+    }
+}
+
+/// Marks a connection as active (see [`ConnectionLimit::enter()`]).
+#[cfg(all(
+    target_os = "linux",
+    any(
+        feature = "http-proxy",
+        feature = "ident",
+        feature = "ucspi",
+        feature = "varlink"
+    )
+))]
+#[derive(Debug)]
+pub struct ConnectionGuard(std::sync::Arc<ConnectionLimit>);
+
+#[cfg(all(
+    target_os = "linux",
+    any(
+        feature = "http-proxy",
+        feature = "ident",
+        feature = "ucspi",
+        feature = "varlink"
+    )
+))]
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        *self.0.active.lock().unwrap() -= 1;
+        self.0.cvar.notify_one();
+    }
+}
+
 #[cfg(all(test, target_os = "freebsd"))]
 pub fn has_cr_pid() -> bool {
     const OSRELDATE_MIB: [libc::c_int; 2] = [libc::CTL_KERN, libc::KERN_OSRELDATE];
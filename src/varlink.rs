@@ -0,0 +1,714 @@
+//! The `varlink` module implements a minimal [varlink](https://varlink.org) server that passes the
+//! caller's credentials to every method (only with the `varlink` feature).
+//!
+//! This covers the parts of the protocol that most services need: NUL-delimited JSON messages,
+//! method dispatch, the `more` and `oneway` flags, and the `org.varlink.service` interface used
+//! for introspection. Each handler receives a [`Call`], which provides the call's parameters along
+//! with the UID, GID, and PID of the caller (as returned by
+//! [`get_peer_pid_ids()`](../fn.get_peer_pid_ids.html) when the connection was accepted).
+//!
+//! ```no_run
+//! use std::os::unix::net::UnixListener;
+//!
+//! use serde_json::json;
+//! use unix_cred::varlink::{Interface, Service};
+//!
+//! let iface = Interface::new(
+//!     "org.example.whoami",
+//!     "interface org.example.whoami\n\nmethod WhoAmI() -> (uid: int, gid: int)\n",
+//! )
+//! .method("WhoAmI", |call| Ok(json!({ "uid": call.uid(), "gid": call.gid() })));
+//!
+//! let service = Service::new("Example", "whoami", "1", "https://example.com").interface(iface);
+//! service.serve(UnixListener::bind("/run/org.example.whoami").unwrap()).unwrap();
+//! ```
+//!
+//! [`Call`]: ./struct.Call.html
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+
+use crate::util::ConnectionLimit;
+
+/// The default maximum number of connections that are handled at once.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+
+/// How long a connection may be idle (or take to send a message) before it is closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The maximum size of a single message.
+const MAX_MESSAGE_LEN: u64 = 8 * 1024 * 1024;
+
+const SERVICE_INTERFACE: &str = "org.varlink.service";
+
+const SERVICE_DESCRIPTION: &str = "\
+# The Varlink Service Interface is provided by every varlink service. It
+# describes the service and the interfaces it implements.
+interface org.varlink.service
+
+# Get a list of all the interfaces a service provides and information
+# about the implementation.
+method GetInfo() -> (
+  vendor: string,
+  product: string,
+  version: string,
+  url: string,
+  interfaces: []string
+)
+
+# Get the description of an interface that is implemented by this service.
+method GetInterfaceDescription(interface: string) -> (description: string)
+
+# The requested interface was not found.
+error InterfaceNotFound (interface: string)
+
+# The requested method was not found
+error MethodNotFound (method: string)
+
+# The interface defines the requested method, but the service does not
+# implement it.
+error MethodNotImplemented (method: string)
+
+# One of the passed parameters is invalid.
+error InvalidParameter (parameter: string)
+
+# Client is denied access
+error PermissionDenied ()
+
+# Method is expected to be called with 'more' set to true, but wasn't
+error ExpectedMore ()
+";
+
+/// A varlink error reply.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    /// The fully qualified name of the error (e.g. `org.varlink.service.InvalidParameter`).
+    pub name: String,
+    /// The error's parameters (usually an object).
+    pub parameters: Value,
+}
+
+impl Error {
+    /// Create a new error with the given name and parameters.
+    pub fn new<S: Into<String>>(name: S, parameters: Value) -> Self {
+        Self {
+            name: name.into(),
+            parameters,
+        }
+    }
+
+    fn service(name: &str, parameters: Value) -> Self {
+        Self::new(format!("{}.{}", SERVICE_INTERFACE, name), parameters)
+    }
+
+    /// `org.varlink.service.InvalidParameter`, for the parameter with the given name.
+    pub fn invalid_parameter(parameter: &str) -> Self {
+        Self::service("InvalidParameter", json!({ "parameter": parameter }))
+    }
+
+    /// `org.varlink.service.PermissionDenied`.
+    pub fn permission_denied() -> Self {
+        Self::service("PermissionDenied", json!({}))
+    }
+
+    /// `org.varlink.service.ExpectedMore`, for methods that must be called with `more` set.
+    pub fn expected_more() -> Self {
+        Self::service("ExpectedMore", json!({}))
+    }
+
+    /// `org.varlink.service.MethodNotImplemented`, for the method with the given (fully
+    /// qualified) name.
+    pub fn method_not_implemented(method: &str) -> Self {
+        Self::service("MethodNotImplemented", json!({ "method": method }))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.parameters)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The credentials of a connected client.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Creds {
+    pid: Option<libc::pid_t>,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+/// A single method call, as passed to a method handler.
+pub struct Call<'a> {
+    method: &'a str,
+    parameters: &'a Map<String, Value>,
+    oneway: bool,
+    more: bool,
+    creds: Creds,
+    writer: &'a mut dyn Write,
+}
+
+impl Call<'_> {
+    /// Get the fully qualified name of the method being called.
+    #[inline]
+    pub fn method(&self) -> &str {
+        self.method
+    }
+
+    /// Get the parameters of the call.
+    #[inline]
+    pub fn parameters(&self) -> &Map<String, Value> {
+        self.parameters
+    }
+
+    /// Get the parameter with the given name, failing with
+    /// [`Error::invalid_parameter()`](./struct.Error.html#method.invalid_parameter) if it is
+    /// missing.
+    pub fn param(&self, name: &str) -> Result<&Value, Error> {
+        self.parameters
+            .get(name)
+            .ok_or_else(|| Error::invalid_parameter(name))
+    }
+
+    /// Check whether the caller asked for no reply.
+    #[inline]
+    pub fn oneway(&self) -> bool {
+        self.oneway
+    }
+
+    /// Check whether the caller is willing to accept multiple replies (see
+    /// [`reply_continues()`](#method.reply_continues)).
+    #[inline]
+    pub fn more(&self) -> bool {
+        self.more
+    }
+
+    /// Get the effective UID of the caller.
+    #[inline]
+    pub fn uid(&self) -> libc::uid_t {
+        self.creds.uid
+    }
+
+    /// Get the effective GID of the caller.
+    #[inline]
+    pub fn gid(&self) -> libc::gid_t {
+        self.creds.gid
+    }
+
+    /// Get the PID of the caller (if it is available).
+    ///
+    /// **WARNING**: See the warnings on [`get_peer_pid_ids()`](../fn.get_peer_pid_ids.html).
+    #[inline]
+    pub fn pid(&self) -> Option<libc::pid_t> {
+        self.creds.pid
+    }
+
+    /// Send a reply that will be followed by more replies (only allowed if
+    /// [`more()`](#method.more) is `true`). The handler's return value is sent as the last
+    /// reply.
+    ///
+    /// If `more` was not set, this fails with `EINVAL`. If the call is oneway, this does nothing.
+    pub fn reply_continues(&mut self, parameters: Value) -> io::Result<()> {
+        if !self.more {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        } else if self.oneway {
+            return Ok(());
+        }
+
+        write_message(
+            self.writer,
+            &json!({ "parameters": parameters, "continues": true }),
+        )
+    }
+}
+
+type Handler = dyn Fn(&mut Call) -> Result<Value, Error> + Send + Sync;
+
+/// A varlink interface, with handlers for its methods.
+pub struct Interface {
+    name: String,
+    description: String,
+    methods: HashMap<String, Box<Handler>>,
+}
+
+impl Interface {
+    /// Create a new interface with the given name and description (in the varlink interface
+    /// definition language, returned by `org.varlink.service.GetInterfaceDescription`).
+    ///
+    /// Calls are not checked against the description; handlers should validate their parameters.
+    pub fn new<N: Into<String>, D: Into<String>>(name: N, description: D) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Add a handler for the method with the given (unqualified) name.
+    ///
+    /// The handler's return value is sent as the reply's parameters (which should be an object),
+    /// or as an error reply.
+    pub fn method<F>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(&mut Call) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.methods.insert(name.into(), Box::new(handler));
+        self
+    }
+
+    /// Get the name of this interface.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Interface")
+            .field("name", &self.name)
+            .field("methods", &self.methods.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A varlink service, consisting of one or more interfaces.
+#[derive(Debug)]
+pub struct Service {
+    vendor: String,
+    product: String,
+    version: String,
+    url: String,
+    interfaces: Vec<Interface>,
+    max_connections: usize,
+}
+
+impl Service {
+    /// Create a new service with the given information (returned by
+    /// `org.varlink.service.GetInfo`).
+    ///
+    /// By default, at most [`DEFAULT_MAX_CONNECTIONS`](./constant.DEFAULT_MAX_CONNECTIONS.html)
+    /// connections are handled at once.
+    pub fn new(vendor: &str, product: &str, version: &str, url: &str) -> Self {
+        Self {
+            vendor: vendor.into(),
+            product: product.into(),
+            version: version.into(),
+            url: url.into(),
+            interfaces: Vec::new(),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+
+    /// Set the maximum number of connections that are handled at once by
+    /// [`serve()`](#method.serve).
+    ///
+    /// Once this many connections are open, no more are accepted until one of them is closed. A
+    /// limit of 0 is treated as 1.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Add an interface to this service.
+    pub fn interface(mut self, interface: Interface) -> Self {
+        self.interfaces.push(interface);
+        self
+    }
+
+    fn find_interface(&self, name: &str) -> Option<&Interface> {
+        self.interfaces.iter().find(|iface| iface.name == name)
+    }
+
+    fn call_service(&self, method: &str, call: &Call) -> Result<Value, Error> {
+        match method {
+            "GetInfo" => {
+                let mut interfaces = vec![SERVICE_INTERFACE];
+                interfaces.extend(self.interfaces.iter().map(|iface| iface.name.as_str()));
+
+                Ok(json!({
+                    "vendor": self.vendor,
+                    "product": self.product,
+                    "version": self.version,
+                    "url": self.url,
+                    "interfaces": interfaces,
+                }))
+            }
+            "GetInterfaceDescription" => {
+                let name = call
+                    .param("interface")?
+                    .as_str()
+                    .ok_or_else(|| Error::invalid_parameter("interface"))?;
+
+                let description = if name == SERVICE_INTERFACE {
+                    SERVICE_DESCRIPTION
+                } else {
+                    match self.find_interface(name) {
+                        Some(iface) => &iface.description,
+                        None => {
+                            return Err(Error::service(
+                                "InterfaceNotFound",
+                                json!({ "interface": name }),
+                            ))
+                        }
+                    }
+                };
+
+                Ok(json!({ "description": description }))
+            }
+            _ => Err(Error::service(
+                "MethodNotFound",
+                json!({ "method": call.method }),
+            )),
+        }
+    }
+
+    fn dispatch(&self, call: &mut Call) -> Result<Value, Error> {
+        let (iface, method) = match call.method.rfind('.') {
+            Some(i) if i > 0 && i + 1 < call.method.len() => {
+                (&call.method[..i], &call.method[i + 1..])
+            }
+            _ => return Err(Error::invalid_parameter("method")),
+        };
+
+        if iface == SERVICE_INTERFACE {
+            return self.call_service(method, call);
+        }
+
+        let iface = self
+            .find_interface(iface)
+            .ok_or_else(|| Error::service("InterfaceNotFound", json!({ "interface": iface })))?;
+
+        match iface.methods.get(method) {
+            Some(handler) => handler(call),
+            None => Err(Error::service(
+                "MethodNotFound",
+                json!({ "method": call.method }),
+            )),
+        }
+    }
+
+    fn handle_message<W: Write>(&self, msg: &[u8], creds: Creds, writer: &mut W) -> io::Result<()> {
+        let msg: Map<String, Value> =
+            serde_json::from_slice(msg).map_err(|_| io::Error::from_raw_os_error(libc::EPROTO))?;
+
+        let method = match msg.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => return Err(io::Error::from_raw_os_error(libc::EPROTO)),
+        };
+        let flag = |name| msg.get(name).and_then(Value::as_bool).unwrap_or(false);
+        let oneway = flag("oneway");
+
+        let empty = Map::new();
+        let res = match msg.get("parameters") {
+            Some(Value::Object(parameters)) => Ok(parameters),
+            None | Some(Value::Null) => Ok(&empty),
+            Some(_) => Err(Error::invalid_parameter("parameters")),
+        }
+        .and_then(|parameters| {
+            self.dispatch(&mut Call {
+                method,
+                parameters,
+                oneway,
+                more: flag("more"),
+                creds,
+                writer: &mut *writer,
+            })
+        });
+
+        if oneway {
+            return Ok(());
+        }
+
+        match res {
+            Ok(parameters) => write_message(writer, &json!({ "parameters": parameters })),
+            Err(e) => write_message(
+                writer,
+                &json!({ "error": e.name, "parameters": e.parameters }),
+            ),
+        }
+    }
+
+    /// Handle calls on a single connection until the client closes it.
+    ///
+    /// This fails with `EPROTO` (and stops processing calls) if the client sends a malformed
+    /// message. The connection is closed if it is idle (or the client stops sending a message)
+    /// for 60 seconds.
+    pub fn handle(&self, sock: UnixStream) -> io::Result<()> {
+        let (pid, uid, gid) = crate::get_peer_pid_ids(&sock)?;
+        let creds = Creds { pid, uid, gid };
+
+        sock.set_read_timeout(Some(IDLE_TIMEOUT))?;
+
+        let mut reader = BufReader::new(&sock);
+        let mut writer = &sock;
+        let mut msg = Vec::new();
+
+        loop {
+            msg.clear();
+            (&mut reader)
+                .take(MAX_MESSAGE_LEN)
+                .read_until(0, &mut msg)?;
+
+            match msg.pop() {
+                Some(0) => self.handle_message(&msg, creds, &mut writer)?,
+                // Clean EOF
+                None => return Ok(()),
+                // Truncated or overly long message
+                Some(_) => return Err(io::Error::from_raw_os_error(libc::EPROTO)),
+            }
+        }
+    }
+
+    /// Accept connections forever, handling each one in a new thread (up to the
+    /// [maximum](#method.max_connections) at once).
+    pub fn serve(self, listener: UnixListener) -> io::Result<()> {
+        let limit = ConnectionLimit::new(self.max_connections);
+        let service = Arc::new(self);
+
+        loop {
+            limit.wait();
+
+            let sock = match listener.accept() {
+                Ok((sock, _)) => sock,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let guard = limit.enter();
+            let service = service.clone();
+            std::thread::spawn(move || {
+                let _guard = guard;
+                service.handle(sock)
+            });
+        }
+    }
+}
+
+fn write_message<W: Write + ?Sized>(writer: &mut W, msg: &Value) -> io::Result<()> {
+    let mut buf = serde_json::to_vec(msg)?;
+    buf.push(0);
+    writer.write_all(&buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Client(BufReader<UnixStream>);
+
+    impl Client {
+        fn send(&mut self, msg: Value) {
+            write_message(self.0.get_mut(), &msg).unwrap();
+        }
+
+        fn recv(&mut self) -> Value {
+            let mut buf = Vec::new();
+            self.0.read_until(0, &mut buf).unwrap();
+            assert_eq!(buf.pop(), Some(0));
+            serde_json::from_slice(&buf).unwrap()
+        }
+
+        fn call(&mut self, method: &str, parameters: Value) -> Value {
+            self.send(json!({ "method": method, "parameters": parameters }));
+            self.recv()
+        }
+    }
+
+    fn start() -> Client {
+        let iface = Interface::new("org.example.test", "interface org.example.test\n")
+            .method("WhoAmI", |call| {
+                Ok(json!({ "uid": call.uid(), "gid": call.gid(), "pid": call.pid() }))
+            })
+            .method("Count", |call| {
+                if !call.more() {
+                    return Err(Error::expected_more());
+                }
+
+                let n = call
+                    .param("n")?
+                    .as_u64()
+                    .ok_or_else(|| Error::invalid_parameter("n"))?;
+                for i in 0..n {
+                    call.reply_continues(json!({ "i": i })).unwrap();
+                }
+                Ok(json!({ "i": n }))
+            });
+
+        let service =
+            Service::new("Vendor", "Product", "1.0", "https://example.com").interface(iface);
+
+        let (client, server) = UnixStream::pair().unwrap();
+        std::thread::spawn(move || service.handle(server));
+        Client(BufReader::new(client))
+    }
+
+    #[test]
+    fn test_service_interface() {
+        let mut client = start();
+
+        assert_eq!(
+            client.call("org.varlink.service.GetInfo", json!({})),
+            json!({ "parameters": {
+                "vendor": "Vendor",
+                "product": "Product",
+                "version": "1.0",
+                "url": "https://example.com",
+                "interfaces": ["org.varlink.service", "org.example.test"],
+            }})
+        );
+
+        assert_eq!(
+            client.call(
+                "org.varlink.service.GetInterfaceDescription",
+                json!({ "interface": "org.example.test" })
+            ),
+            json!({ "parameters": { "description": "interface org.example.test\n" } })
+        );
+        let reply = client.call(
+            "org.varlink.service.GetInterfaceDescription",
+            json!({ "interface": "org.varlink.service" }),
+        );
+        assert!(reply["parameters"]["description"]
+            .as_str()
+            .unwrap()
+            .contains("method GetInfo()"));
+
+        assert_eq!(
+            client.call(
+                "org.varlink.service.GetInterfaceDescription",
+                json!({ "interface": "org.example.missing" })
+            ),
+            json!({
+                "error": "org.varlink.service.InterfaceNotFound",
+                "parameters": { "interface": "org.example.missing" },
+            })
+        );
+        assert_eq!(
+            client.call("org.varlink.service.GetInterfaceDescription", json!({}))["error"],
+            "org.varlink.service.InvalidParameter"
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        let mut client = start();
+        let (pid, uid, gid) = crate::get_peer_pid_ids(client.0.get_ref()).unwrap();
+
+        assert_eq!(
+            client.call("org.example.test.WhoAmI", json!({})),
+            json!({ "parameters": { "uid": uid, "gid": gid, "pid": pid } })
+        );
+        // Parameters may be omitted
+        client.send(json!({ "method": "org.example.test.WhoAmI" }));
+        assert_eq!(client.recv()["parameters"]["uid"], uid);
+
+        assert_eq!(
+            client.call("org.example.test.Missing", json!({})),
+            json!({
+                "error": "org.varlink.service.MethodNotFound",
+                "parameters": { "method": "org.example.test.Missing" },
+            })
+        );
+        assert_eq!(
+            client.call("org.example.missing.Method", json!({}))["error"],
+            "org.varlink.service.InterfaceNotFound"
+        );
+        assert_eq!(
+            client.call("org.example.test.WhoAmI", json!([]))["error"],
+            "org.varlink.service.InvalidParameter"
+        );
+    }
+
+    #[test]
+    fn test_more_oneway() {
+        let mut client = start();
+
+        assert_eq!(
+            client.call("org.example.test.Count", json!({ "n": 2 }))["error"],
+            "org.varlink.service.ExpectedMore"
+        );
+
+        client.send(
+            json!({ "method": "org.example.test.Count", "parameters": { "n": 2 }, "more": true }),
+        );
+        assert_eq!(
+            client.recv(),
+            json!({ "parameters": { "i": 0 }, "continues": true })
+        );
+        assert_eq!(
+            client.recv(),
+            json!({ "parameters": { "i": 1 }, "continues": true })
+        );
+        assert_eq!(client.recv(), json!({ "parameters": { "i": 2 } }));
+
+        // No replies to oneway calls
+        client.send(json!({
+            "method": "org.example.test.Count",
+            "parameters": { "n": 2 },
+            "more": true,
+            "oneway": true,
+        }));
+        client.send(json!({ "method": "org.example.missing.Method", "oneway": true }));
+        assert_eq!(
+            client.call("org.example.test.Count", json!({ "n": "x" }))["error"],
+            "org.varlink.service.ExpectedMore"
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let service = Service::new("Vendor", "Product", "1.0", "https://example.com");
+
+        for msg in [&b"{\0"[..], b"[]\0", b"{\"parameters\": {}}\0", b"{}"] {
+            let (mut client, server) = UnixStream::pair().unwrap();
+            client.write_all(msg).unwrap();
+            drop(client);
+
+            assert_eq!(
+                service.handle(server).unwrap_err().raw_os_error(),
+                Some(libc::EPROTO)
+            );
+        }
+    }
+
+    #[test]
+    fn test_serve_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let service =
+            Service::new("Vendor", "Product", "1.0", "https://example.com").max_connections(1);
+        std::thread::spawn(move || service.serve(listener));
+
+        // An idle connection takes up the only slot
+        let mut first = Client(BufReader::new(UnixStream::connect(&path).unwrap()));
+        assert!(first
+            .call("org.varlink.service.GetInfo", json!({}))
+            .get("parameters")
+            .is_some());
+
+        let mut second = Client(BufReader::new(UnixStream::connect(&path).unwrap()));
+        second
+            .0
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        second.send(json!({ "method": "org.varlink.service.GetInfo" }));
+        assert_eq!(
+            second.0.read_until(0, &mut Vec::new()).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        drop(first);
+        second.0.get_ref().set_read_timeout(None).unwrap();
+        assert!(second.recv().get("parameters").is_some());
+    }
+}
@@ -0,0 +1,440 @@
+//! The `verify` module helps clients check that they are talking to the server they expect
+//! (Linux-specific).
+//!
+//! Most of this crate is about servers checking their clients, but the reverse matters too: if an
+//! attacker can bind the socket path before the real daemon does (or replace it afterward),
+//! clients will happily send it their requests. [`connect_verified()`] connects to a socket and
+//! checks the server's credentials before returning the connection.
+//!
+//! On Linux, the server's credentials are those of the process that called `listen()`, as of the
+//! time of the `listen()` call.
+//!
+//! Checking the server's credentials doesn't help much if an attacker can swap out one of the
+//! directories leading up to the socket. [`verify_socket_path()`] audits the path itself, and
+//! [`connect_verified_path()`] combines that with a check that the server owns the socket.
+//!
+//! [`connect_verified()`]: ./fn.connect_verified.html
+//! [`verify_socket_path()`]: ./fn.verify_socket_path.html
+//! [`connect_verified_path()`]: ./fn.connect_verified_path.html
+
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::os::unix::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use crate::ancestry::ProcessId;
+use crate::policy::PeerPolicy;
+use crate::procfs;
+
+/// The server that a client expects to be connected to.
+#[derive(Debug)]
+pub enum ExpectedServer {
+    /// The server must be running with the given effective UID.
+    Uid(libc::uid_t),
+    /// The server must be running the executable at the given path.
+    ///
+    /// This is compared with the target of the server's `/proc/<pid>/exe` link, so it should be
+    /// an absolute, canonical path. Note that this requires permission to read that link, which
+    /// unprivileged clients usually do not have for daemons running as other users (in which case
+    /// the check fails). It also fails if the process that called `listen()` has since exited
+    /// (for example, if the daemon forked into the background afterward).
+    Exe(PathBuf),
+    /// The server's effective UID and GID must be allowed by the given policy.
+    Policy(PeerPolicy),
+}
+
+impl ExpectedServer {
+    /// Check whether a server with the given PID, effective UID, and effective GID is the
+    /// expected server.
+    pub fn matches(
+        &self,
+        pid: Option<libc::pid_t>,
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+    ) -> io::Result<bool> {
+        match self {
+            Self::Uid(expected) => Ok(uid == *expected),
+            Self::Exe(expected) => {
+                let pid = match pid {
+                    Some(pid) => pid,
+                    None => return Ok(false),
+                };
+
+                // Make sure the PID wasn't reused while we were looking at it
+                let proc = ProcessId::from_pid(pid)?;
+                Ok(
+                    procfs::read_exe(pid)?.as_deref() == Some(expected.as_path())
+                        && proc.is_alive()?,
+                )
+            }
+            Self::Policy(policy) => Ok(policy.allows(uid, gid)),
+        }
+    }
+}
+
+/// Check whether the given socket's peer is the expected server.
+///
+/// If it is not, this fails with `EACCES`.
+pub fn verify_peer<S: crate::PeerSocket>(sock: &S, expected: &ExpectedServer) -> io::Result<()> {
+    let (pid, uid, gid) = sock.peer_pid_ids()?;
+
+    match expected.matches(pid, uid, gid) {
+        Ok(true) => Ok(()),
+        // If the process has already exited, it can't be the server we expected
+        Ok(false) | Err(_) => Err(io::Error::from_raw_os_error(libc::EACCES)),
+    }
+}
+
+/// Connect to the socket at the given path, and check that the server is the expected one.
+///
+/// If it is not, the connection is closed and this fails with `EACCES`.
+///
+/// ```no_run
+/// use unix_cred::verify::{connect_verified, ExpectedServer};
+///
+/// // Only talk to the daemon if it's running as root
+/// let sock = connect_verified("/run/foo.sock", &ExpectedServer::Uid(0)).unwrap();
+/// ```
+pub fn connect_verified<P: AsRef<Path>>(
+    path: P,
+    expected: &ExpectedServer,
+) -> io::Result<UnixStream> {
+    let sock = UnixStream::connect(path)?;
+    verify_peer(&sock, expected)?;
+    Ok(sock)
+}
+
+fn check_dir(path: &Path, trusted_uid: libc::uid_t) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+
+    if meta.file_type().is_symlink() {
+        return Err(io::Error::from_raw_os_error(libc::ELOOP));
+    } else if !meta.is_dir() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+
+    if meta.uid() != 0 && meta.uid() != trusted_uid {
+        return Err(io::Error::from_raw_os_error(libc::EACCES));
+    }
+
+    // Group/world-writable directories are only safe if the sticky bit prevents other users from
+    // renaming or removing our entries (like /tmp)
+    if meta.mode() & 0o022 != 0 && meta.mode() & libc::S_ISVTX == 0 {
+        return Err(io::Error::from_raw_os_error(libc::EACCES));
+    }
+
+    Ok(())
+}
+
+/// Check that the socket at the given path can be trusted, returning its metadata if so.
+///
+/// This checks that:
+///
+/// - The path refers to a socket (not a symlink to one), which is owned by root or `trusted_uid`.
+/// - Every directory leading up to the socket is a real directory (not a symlink), is owned by
+///   root or `trusted_uid`, and is not group- or world-writable unless the sticky bit is set.
+///
+/// If a directory is a symlink, this fails with `ELOOP`. If the socket or a directory is owned by
+/// another user, or a directory is writable by others, it fails with `EACCES`. If the path refers
+/// to something other than a socket, it fails with `ENOTSOCK`.
+///
+/// Relative paths are resolved against the current directory (and all of its ancestors are
+/// checked too). Paths containing `..` components are rejected with `EINVAL`.
+///
+/// Note that this only checks the path at one point in time. Use
+/// [`connect_verified_path()`](./fn.connect_verified_path.html) to connect to the socket and
+/// make sure that the server owns it.
+pub fn verify_socket_path<P: AsRef<Path>>(
+    path: P,
+    trusted_uid: libc::uid_t,
+) -> io::Result<fs::Metadata> {
+    let path = path.as_ref();
+
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let meta = fs::symlink_metadata(&path)?;
+    if meta.file_type().is_symlink() {
+        return Err(io::Error::from_raw_os_error(libc::ELOOP));
+    } else if !meta.file_type().is_socket() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTSOCK));
+    }
+
+    // In a sticky directory like /tmp, anyone could have created the socket
+    if meta.uid() != 0 && meta.uid() != trusted_uid {
+        return Err(io::Error::from_raw_os_error(libc::EACCES));
+    }
+
+    for dir in path.ancestors().skip(1) {
+        // Skip the empty path (and "." components, which resolve to the same directory)
+        if !dir.as_os_str().is_empty() && !dir.ends_with(".") {
+            check_dir(dir, trusted_uid)?;
+        }
+    }
+
+    Ok(meta)
+}
+
+/// Check that the socket at the given path can be trusted, connect to it, and check that the
+/// server's effective UID is `trusted_uid` and that it owns the socket.
+///
+/// See [`verify_socket_path()`](./fn.verify_socket_path.html) for the checks performed on the
+/// path. After connecting, the socket is checked again to make sure it wasn't replaced in the
+/// meantime, and the UID returned by [`get_peer_ids()`](../fn.get_peer_ids.html) is compared with
+/// both `trusted_uid` and the owner of the socket. (So a root-owned socket is only accepted if
+/// `trusted_uid` is 0.) If any check fails, the connection is closed and this fails with
+/// `EACCES`.
+///
+/// ```no_run
+/// use unix_cred::verify::connect_verified_path;
+///
+/// let sock = connect_verified_path("/run/foo/foo.sock", 0).unwrap();
+/// ```
+pub fn connect_verified_path<P: AsRef<Path>>(
+    path: P,
+    trusted_uid: libc::uid_t,
+) -> io::Result<UnixStream> {
+    let path = path.as_ref();
+
+    let before = verify_socket_path(path, trusted_uid)?;
+    let sock = UnixStream::connect(path)?;
+    let after = verify_socket_path(path, trusted_uid)?;
+
+    let (uid, _) = crate::get_peer_ids(&sock)?;
+
+    if before.dev() != after.dev()
+        || before.ino() != after.ino()
+        || before.uid() != uid
+        || uid != trusted_uid
+    {
+        return Err(io::Error::from_raw_os_error(libc::EACCES));
+    }
+
+    Ok(sock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_connect_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        let euid = unsafe { libc::geteuid() };
+        let exe = std::env::current_exe().unwrap().canonicalize().unwrap();
+
+        for expected in [
+            ExpectedServer::Uid(euid),
+            ExpectedServer::Exe(exe),
+            ExpectedServer::Policy(PeerPolicy::SameUser),
+        ] {
+            connect_verified(&path, &expected).unwrap();
+        }
+
+        for expected in [
+            ExpectedServer::Uid(euid.wrapping_add(1)),
+            ExpectedServer::Exe("/nonexistent".into()),
+            ExpectedServer::Policy(PeerPolicy::Uids(Vec::new())),
+        ] {
+            assert_eq!(
+                connect_verified(&path, &expected)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EACCES)
+            );
+        }
+
+        assert_eq!(
+            connect_verified(dir.path().join("missing"), &ExpectedServer::Uid(euid))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOENT)
+        );
+    }
+
+    #[test]
+    fn test_verify_socket_path() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let euid = unsafe { libc::geteuid() };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        // Skip the test if the temporary directory is somewhere we wouldn't trust
+        if verify_socket_path(&path, euid).is_err() {
+            return;
+        }
+        connect_verified_path(&path, euid).unwrap();
+
+        let check_err = |path: &Path, eno| {
+            assert_eq!(
+                verify_socket_path(path, euid).unwrap_err().raw_os_error(),
+                Some(eno)
+            );
+            assert_eq!(
+                connect_verified_path(path, euid)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(eno)
+            );
+        };
+
+        // Not a socket
+        fs::write(dir.path().join("file"), b"").unwrap();
+        check_err(&dir.path().join("file"), libc::ENOTSOCK);
+
+        // Symlinks
+        symlink(&path, dir.path().join("link")).unwrap();
+        check_err(&dir.path().join("link"), libc::ELOOP);
+        symlink(dir.path(), dir.path().join("dirlink")).unwrap();
+        check_err(&dir.path().join("dirlink").join("sock"), libc::ELOOP);
+
+        // ".." components
+        check_err(
+            &dir.path().join("sub").join("..").join("sock"),
+            libc::EINVAL,
+        );
+
+        // Writable by others
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        check_err(&path, libc::EACCES);
+        // ... unless it's sticky
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+        verify_socket_path(&path, euid).unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700)).unwrap();
+
+        // Owned by someone else
+        if euid == 0 {
+            std::os::unix::fs::chown(dir.path(), Some(12345), None).unwrap();
+            assert_eq!(
+                verify_socket_path(&path, euid).unwrap_err().raw_os_error(),
+                Some(libc::EACCES)
+            );
+            verify_socket_path(&path, 12345).unwrap();
+            std::os::unix::fs::chown(dir.path(), Some(0), None).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_verify_socket_owner() {
+        let euid = unsafe { libc::geteuid() };
+        let other = euid.wrapping_add(12345);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        if verify_socket_path(&path, euid).is_err() {
+            return;
+        }
+
+        if euid == 0 {
+            // A root-owned socket is trusted, but the server must still be running as the trusted
+            // UID
+            verify_socket_path(&path, other).unwrap();
+            assert_eq!(
+                connect_verified_path(&path, other)
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::EACCES)
+            );
+
+            // A socket owned by someone else is not trusted, even if the directories are
+            std::os::unix::fs::chown(&path, Some(other), None).unwrap();
+            assert_eq!(
+                verify_socket_path(&path, euid).unwrap_err().raw_os_error(),
+                Some(libc::EACCES)
+            );
+        } else {
+            // We own the socket (and its directory), but we aren't the trusted user
+            std::os::unix::fs::chown(dir.path(), Some(euid), None).unwrap();
+            assert_eq!(
+                verify_socket_path(&path, other).unwrap_err().raw_os_error(),
+                Some(libc::EACCES)
+            );
+        }
+
+        assert_eq!(
+            connect_verified_path(&path, euid)
+                .map(drop)
+                .map_err(|e| e.raw_os_error()),
+            if euid == 0 {
+                Err(Some(libc::EACCES))
+            } else {
+                Ok(())
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_socket_peer_mismatch() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let other = 12345;
+
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        std::os::unix::fs::chown(&sub, Some(other), None).unwrap();
+        let path = sub.join("sock");
+
+        // Create the listener as `other`. Raw setresuid() only changes the credentials of the
+        // calling thread.
+        let _listener = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let ret = unsafe {
+                    libc::syscall(
+                        libc::SYS_setresuid,
+                        libc::uid_t::MAX,
+                        other,
+                        libc::uid_t::MAX,
+                    )
+                };
+                assert_eq!(ret, 0);
+                UnixListener::bind(&path).unwrap()
+            }
+        })
+        .join()
+        .unwrap();
+
+        if verify_socket_path(&path, other).is_err() {
+            return;
+        }
+        connect_verified_path(&path, other).unwrap();
+
+        // The socket is owned by root, but the server is running as `other`
+        std::os::unix::fs::chown(&path, Some(0), None).unwrap();
+        verify_socket_path(&path, other).unwrap();
+        assert_eq!(
+            connect_verified_path(&path, other)
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::EACCES)
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(!ExpectedServer::Exe("/bin/sh".into())
+            .matches(None, 0, 0)
+            .unwrap());
+        assert!(ExpectedServer::Uid(5).matches(None, 5, 0).unwrap());
+    }
+}